@@ -0,0 +1,361 @@
+use crate::ach_file::{record_line, AchFile, AchRecord, AchRecordType, TraceMap};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+
+/// One field of one record changed by a transformation.
+/// Whole records that were removed or added are logged with the field `record`, and the line
+/// as it was written (or will be) as the old or new value.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change {
+    /// Pipeline stage the transformation belongs to, when run as part of a pipeline
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+    /// Label of the transformation that made the change
+    pub label: String,
+    pub record_type: AchRecordType,
+    /// 1-based batch the record is in after the change, or was in before it was removed
+    pub batch: Option<usize>,
+    /// 1-based entry within that batch, for entries and their addenda
+    pub entry: Option<usize>,
+    /// Trace number the entry had before the transformation
+    pub trace: Option<String>,
+    pub field: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.stage {
+            Some(stage) => write!(f, "[{}/{}]", stage, self.label)?,
+            None => write!(f, "[{}]", self.label)?,
+        }
+        if let Some(batch) = self.batch {
+            write!(f, " batch {}", batch)?;
+        }
+        if let Some(entry) = self.entry {
+            write!(f, " entry {}", entry)?;
+        }
+        if let Some(trace) = &self.trace {
+            write!(f, " ({})", trace)?;
+        }
+        write!(
+            f,
+            " {:?}.{}: '{}' -> '{}'",
+            self.record_type, self.field, self.old, self.new
+        )
+    }
+}
+
+/// Every change made while applying transformations, in the order they were made
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ChangeLog {
+    changes: Vec<Change>,
+}
+
+impl ChangeLog {
+    pub fn record(&mut self, change: Change) {
+        self.changes.push(change)
+    }
+
+    /// Add every change in `later`, as made by the pipeline stage `stage`
+    pub fn append(&mut self, stage: &str, later: ChangeLog) {
+        self.changes
+            .extend(later.changes.into_iter().map(|change| Change {
+                stage: Some(stage.to_string()),
+                ..change
+            }));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Change> {
+        self.changes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.changes).unwrap()
+    }
+
+    /// Log everything that differs between `before` and `after`, the same file on either side
+    /// of the transformation `label`. Entries are matched up by trace number, following
+    /// `trace_map` for any the transformation renumbered.
+    pub(crate) fn record_diff(
+        &mut self,
+        label: &str,
+        before: &AchFile,
+        after: &AchFile,
+        trace_map: &TraceMap,
+    ) {
+        let mut log = |record_type, batch, entry, trace: Option<&str>, field: &str, old, new| {
+            self.changes.push(Change {
+                stage: None,
+                label: label.to_string(),
+                record_type,
+                batch,
+                entry,
+                trace: trace.map(str::to_string),
+                field: field.to_string(),
+                old,
+                new,
+            })
+        };
+
+        for (field, old, new) in
+            field_changes(AchRecordType::Header, before.header(), after.header())
+        {
+            log(AchRecordType::Header, None, None, None, field, old, new);
+        }
+
+        // (batch, entry, trace) of every entry before the transformation, and whether it has
+        // been found again afterwards
+        let mut originals = vec![];
+        // Where in `originals` the entries of each trace not found again yet are, first first
+        let mut unfound: HashMap<&str, VecDeque<usize>> = HashMap::new();
+        for (b, batch) in before.batches().iter().enumerate() {
+            for (e, entry) in batch.entries().iter().enumerate() {
+                let trace = entry.field("trace").unwrap().as_str();
+                unfound.entry(trace).or_default().push_back(originals.len());
+                originals.push((b, e, trace, false));
+            }
+        }
+        let mut original_traces = HashMap::new();
+        for (old, new) in trace_map.iter() {
+            original_traces.entry(new).or_insert(old);
+        }
+        let mut batch_origins = HashSet::new();
+
+        for (b, batch) in after.batches().iter().enumerate() {
+            let mut batch_origin = None;
+
+            for (e, entry) in batch.entries().iter().enumerate() {
+                let trace = entry.field("trace").unwrap().as_str();
+                let original_trace = original_traces.get(trace).copied().unwrap_or(trace);
+                let original = unfound
+                    .get_mut(original_trace)
+                    .and_then(VecDeque::pop_front)
+                    .map(|i| &mut originals[i]);
+
+                let (ob, oe) = match original {
+                    Some((ob, oe, _, found)) => {
+                        *found = true;
+                        (*ob, *oe)
+                    }
+                    None => {
+                        let line = record_line(AchRecordType::EntryDetail, entry);
+                        log(
+                            AchRecordType::EntryDetail,
+                            Some(b + 1),
+                            Some(e + 1),
+                            None,
+                            "record",
+                            String::new(),
+                            line,
+                        );
+                        continue;
+                    }
+                };
+                batch_origin.get_or_insert(ob);
+
+                let old_entry = &before.batches()[ob].entries()[oe];
+                if (ob, oe) != (b, e) {
+                    log(
+                        AchRecordType::EntryDetail,
+                        Some(b + 1),
+                        Some(e + 1),
+                        Some(original_trace),
+                        "position",
+                        format!("batch {}, entry {}", ob + 1, oe + 1),
+                        format!("batch {}, entry {}", b + 1, e + 1),
+                    );
+                }
+                for (field, old, new) in field_changes(AchRecordType::EntryDetail, old_entry, entry)
+                {
+                    log(
+                        AchRecordType::EntryDetail,
+                        Some(b + 1),
+                        Some(e + 1),
+                        Some(original_trace),
+                        field,
+                        old,
+                        new,
+                    );
+                }
+
+                let old_addenda = old_entry.addenda();
+                for i in 0..old_addenda.len().max(entry.addenda().len()) {
+                    let (old, new) = (old_addenda.get(i), entry.addenda().get(i));
+                    let changes = match (old, new) {
+                        (Some(old), Some(new)) => field_changes(AchRecordType::Addenda, old, new),
+                        (Some(old), None) => vec![(
+                            "record",
+                            record_line(AchRecordType::Addenda, old),
+                            String::new(),
+                        )],
+                        (None, Some(new)) => vec![(
+                            "record",
+                            String::new(),
+                            record_line(AchRecordType::Addenda, new),
+                        )],
+                        (None, None) => vec![],
+                    };
+                    for (field, old, new) in changes {
+                        log(
+                            AchRecordType::Addenda,
+                            Some(b + 1),
+                            Some(e + 1),
+                            Some(original_trace),
+                            field,
+                            old,
+                            new,
+                        );
+                    }
+                }
+            }
+
+            // A batch is compared with the one its first entry came from
+            let changes = match batch_origin {
+                Some(ob) => {
+                    let old_batch = &before.batches()[ob];
+                    let mut changes: Vec<_> = field_changes(
+                        AchRecordType::CompanyBatchHeader,
+                        old_batch.header(),
+                        batch.header(),
+                    )
+                    .into_iter()
+                    .map(|c| (AchRecordType::CompanyBatchHeader, c))
+                    .collect();
+                    changes.extend(
+                        field_changes(
+                            AchRecordType::CompanyBatchTrailer,
+                            old_batch.trailer(),
+                            batch.trailer(),
+                        )
+                        .into_iter()
+                        .map(|c| (AchRecordType::CompanyBatchTrailer, c)),
+                    );
+                    changes
+                }
+                None => vec![(
+                    AchRecordType::CompanyBatchHeader,
+                    (
+                        "record",
+                        String::new(),
+                        record_line(AchRecordType::CompanyBatchHeader, batch.header()),
+                    ),
+                )],
+            };
+            for (record_type, (field, old, new)) in changes {
+                log(record_type, Some(b + 1), None, None, field, old, new);
+            }
+            batch_origins.extend(batch_origin);
+        }
+
+        for (ob, oe, trace, found) in originals {
+            if !found {
+                let entry = &before.batches()[ob].entries()[oe];
+                log(
+                    AchRecordType::EntryDetail,
+                    Some(ob + 1),
+                    Some(oe + 1),
+                    Some(trace),
+                    "record",
+                    record_line(AchRecordType::EntryDetail, entry),
+                    String::new(),
+                );
+            }
+        }
+        for (ob, batch) in before.batches().iter().enumerate() {
+            if !batch_origins.contains(&ob) {
+                log(
+                    AchRecordType::CompanyBatchHeader,
+                    Some(ob + 1),
+                    None,
+                    None,
+                    "record",
+                    record_line(AchRecordType::CompanyBatchHeader, batch.header()),
+                    String::new(),
+                );
+            }
+        }
+
+        for (field, old, new) in
+            field_changes(AchRecordType::Trailer, before.trailer(), after.trailer())
+        {
+            log(AchRecordType::Trailer, None, None, None, field, old, new);
+        }
+    }
+}
+
+/// One line per change
+impl Display for ChangeLog {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+fn field_changes(
+    record_type: AchRecordType,
+    old: &dyn AchRecord,
+    new: &dyn AchRecord,
+) -> Vec<(&'static str, String, String)> {
+    record_type
+        .layout()
+        .iter()
+        .filter_map(|spec| {
+            let old = old.field(spec.name)?.to_string();
+            let new = new.field(spec.name)?.to_string();
+            (old != new).then_some((spec.name, old, new))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod ach_change_log_tests {
+    use crate::ach_change_log::ChangeLog;
+    use crate::ach_file::{AchFile, AchRecordType, TraceMap};
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    #[test]
+    fn test_record_diff() {
+        let before: AchFile = SAMPLE.parse().unwrap();
+        let mut after = before.clone();
+        after.batches_mut()[0].entries_mut().remove(1);
+        let trace_map: TraceMap = after.renumber().unwrap();
+
+        let mut log = ChangeLog::default();
+        log.record_diff("test", &before, &after, &trace_map);
+        let changes: Vec<_> = log
+            .iter()
+            .map(|c| (c.record_type, c.batch, c.entry, c.field.as_str()))
+            .collect();
+
+        assert!(changes.contains(&(AchRecordType::EntryDetail, Some(1), Some(2), "record")));
+        assert!(changes.contains(&(AchRecordType::EntryDetail, Some(1), Some(2), "position")));
+        assert!(changes.contains(&(AchRecordType::EntryDetail, Some(1), Some(2), "trace")));
+        assert!(changes.contains(&(AchRecordType::Addenda, Some(1), Some(2), "batch")));
+        assert!(changes.contains(&(
+            AchRecordType::CompanyBatchTrailer,
+            Some(1),
+            None,
+            "entry_and_addenda_count"
+        )));
+        assert!(changes.contains(&(AchRecordType::Trailer, None, None, "entry_hash")));
+
+        let removed = log.iter().find(|c| c.field == "record").unwrap();
+        assert_eq!(removed.trace.as_deref(), Some("091000010000002"));
+        assert!(removed.old.contains("ZERO DOLLAR TEST"));
+        assert!(log.to_json().contains("\"field\": \"position\""));
+    }
+}
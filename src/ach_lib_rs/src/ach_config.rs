@@ -0,0 +1,1193 @@
+use crate::ach_operations::OperationRegistry;
+use crate::ach_transformations::Transformations;
+use log::error;
+use serde::de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem with a transformation config, and where in the config it is
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub file: Option<PathBuf>,
+    /// 1-based line and column
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn error(at: (usize, usize), message: String) -> Self {
+        Diagnostic {
+            file: None,
+            line: at.0,
+            column: at.1,
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    pub(crate) fn warning(at: (usize, usize), message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(at, message)
+        }
+    }
+}
+
+/// `file:line:column: severity: message`, the format editors and CI logs pick up
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let file = match &self.file {
+            Some(file) => file.display().to_string(),
+            None => "<config>".to_string(),
+        };
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            file, self.line, self.column, severity, self.message
+        )
+    }
+}
+
+/// Formats a config can be written in. Files are read as the format their extension names,
+/// and as YAML when it names none of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl From<&Path> for ConfigFormat {
+    fn from(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+}
+
+/// Check the config at `path`, and every config it includes, reporting every problem in them
+/// rather than stopping at the first
+pub fn lint_file(path: &Path) -> io::Result<Vec<Diagnostic>> {
+    lint_file_with(path, &OperationRegistry::default())
+}
+
+/// [lint_file], for a config that may also name `operations`
+pub fn lint_file_with(path: &Path, operations: &OperationRegistry) -> io::Result<Vec<Diagnostic>> {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            error!(
+                "Could not open transform file: {} \n Path: {}",
+                e,
+                path.display()
+            );
+            return Err(e);
+        }
+    };
+
+    Ok(check(
+        source.as_str(),
+        ConfigFormat::from(path),
+        Some(path),
+        operations,
+    )
+    .1)
+}
+
+/// Check config `source`, reporting every problem in it rather than stopping at the first.
+/// It includes configs from `./cfg`.
+pub fn lint(source: &str, format: ConfigFormat) -> Vec<Diagnostic> {
+    lint_with(source, format, &OperationRegistry::default())
+}
+
+/// [lint], for a config that may also name `operations`
+pub fn lint_with(
+    source: &str,
+    format: ConfigFormat,
+    operations: &OperationRegistry,
+) -> Vec<Diagnostic> {
+    check(source, format, None, operations).1
+}
+
+/// Read and check a config, giving its transformations (if it could be read at all) and
+/// every problem found, in order
+pub(crate) fn check(
+    source: &str,
+    format: ConfigFormat,
+    file: Option<&Path>,
+    operations: &OperationRegistry,
+) -> (Option<Transformations>, Vec<Diagnostic>) {
+    let mut diagnostics = vec![];
+    let transformations = read(source, format, file, &mut diagnostics)
+        .map(|raw| Transformations::from_raw(raw, operations, &mut diagnostics));
+    for diagnostic in &mut diagnostics {
+        if diagnostic.file.is_none() {
+            diagnostic.file = file.map(Path::to_path_buf);
+        }
+    }
+    diagnostics.sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+    (transformations, diagnostics)
+}
+
+/// A config: the configs it includes, its vars, and transformation labels in order, each
+/// with its settings. An empty label (`label:` with nothing under it in YAML) has no settings.
+#[derive(Default)]
+struct ConfigFile {
+    include: Vec<String>,
+    vars: Vec<(String, String)>,
+    transformations: Vec<(String, Option<TransformationConfig>)>,
+}
+
+impl<'de> Deserialize<'de> for ConfigFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ConfigFileVisitor;
+
+        impl<'de> Visitor<'de> for ConfigFileVisitor {
+            type Value = ConfigFile;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("transformation labels, each with its settings")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut config = ConfigFile::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "include" => config.include.extend(map.next_value::<StringList>()?.0),
+                        "vars" => config.vars.extend(
+                            map.next_value::<Vars>()?
+                                .0
+                                .into_iter()
+                                .map(|(name, Scalar(value))| (name, value)),
+                        ),
+                        _ => config.transformations.push((key, map.next_value()?)),
+                    }
+                }
+                Ok(config)
+            }
+        }
+
+        deserializer.deserialize_map(ConfigFileVisitor)
+    }
+}
+
+/// `vars:`, in the order they are written
+struct Vars(Vec<(String, Scalar)>);
+
+impl<'de> Deserialize<'de> for Vars {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VarsVisitor;
+
+        impl<'de> Visitor<'de> for VarsVisitor {
+            type Value = Vars;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("var names, each with its value")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut vars = vec![];
+                while let Some(entry) = map.next_entry()? {
+                    vars.push(entry);
+                }
+                Ok(Vars(vars))
+            }
+        }
+
+        deserializer.deserialize_map(VarsVisitor)
+    }
+}
+
+/// The settings of one transformation. Keys other than these are kept so they can be reported.
+#[derive(Debug, Deserialize)]
+struct TransformationConfig {
+    operation: Option<StringList>,
+    on: Option<StringList>,
+    #[serde(rename = "where")]
+    conditions: Option<StringList>,
+    by: Option<StringList>,
+    entries_by: Option<StringList>,
+    batches_by: Option<StringList>,
+    effective_date: Option<Scalar>,
+    immediate_dest: Option<Scalar>,
+    immediate_dest_name: Option<Scalar>,
+    immediate_orig: Option<Scalar>,
+    odfi_id: Option<Scalar>,
+    script: Option<Scalar>,
+    outputs: Option<Outputs>,
+    offset_name: Option<Scalar>,
+    renumber: Option<Scalar>,
+    accounts: Option<Accounts>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+impl TransformationConfig {
+    /// Every setting, in the order the keys are listed in [TransformationConfig]. Each output
+    /// is a setting of its own, `outputs.<name>`, with its conditions as `outputs.<name>.where`,
+    /// and so is each value of an account, e.g. `accounts.<company_id>.routing`.
+    fn settings(self) -> Vec<(String, Vec<String>)> {
+        let lists = [
+            ("operation", self.operation),
+            ("on", self.on),
+            ("where", self.conditions),
+            ("by", self.by),
+            ("entries_by", self.entries_by),
+            ("batches_by", self.batches_by),
+        ];
+        let scalars = [
+            ("effective_date", self.effective_date),
+            ("immediate_dest", self.immediate_dest),
+            ("immediate_dest_name", self.immediate_dest_name),
+            ("immediate_orig", self.immediate_orig),
+            ("odfi_id", self.odfi_id),
+            ("script", self.script),
+            ("offset_name", self.offset_name),
+            ("renumber", self.renumber),
+        ];
+
+        let mut settings: Vec<_> = lists
+            .into_iter()
+            .filter_map(|(key, list)| Some((key.to_string(), list?.0)))
+            .collect();
+        settings.extend(
+            scalars
+                .into_iter()
+                .filter_map(|(key, scalar)| Some((key.to_string(), vec![scalar?.0]))),
+        );
+        for (name, output) in self.outputs.map(|o| o.0).unwrap_or_default() {
+            settings.push((format!("outputs.{}", name), vec![output.path.0]));
+            if let Some(conditions) = output.conditions {
+                settings.push((format!("outputs.{}.where", name), conditions.0));
+            }
+        }
+        for (Scalar(company_id), account) in self.accounts.map(|a| a.0).unwrap_or_default() {
+            for (key, value) in [
+                ("routing", Some(account.routing)),
+                ("account", Some(account.account)),
+                ("account_type", account.account_type),
+            ] {
+                if let Some(Scalar(value)) = value {
+                    settings.push((format!("accounts.{}.{}", company_id, key), vec![value]));
+                }
+            }
+        }
+        settings.extend(self.unknown.into_keys().map(|key| (key, vec![])));
+        settings
+    }
+}
+
+/// `outputs:` of a SPLIT, in the order they are written
+#[derive(Debug)]
+struct Outputs(Vec<(String, Output)>);
+
+impl<'de> Deserialize<'de> for Outputs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OutputsVisitor;
+
+        impl<'de> Visitor<'de> for OutputsVisitor {
+            type Value = Outputs;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("output names, each with where it is written")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut outputs = vec![];
+                while let Some(entry) = map.next_entry()? {
+                    outputs.push(entry);
+                }
+                Ok(Outputs(outputs))
+            }
+        }
+
+        deserializer.deserialize_map(OutputsVisitor)
+    }
+}
+
+/// One output of a SPLIT: just its path, or its path and the conditions batches sent to it meet
+#[derive(Debug)]
+struct Output {
+    path: Scalar,
+    conditions: Option<StringList>,
+}
+
+impl<'de> Deserialize<'de> for Output {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OutputVisitor;
+
+        impl<'de> Visitor<'de> for OutputVisitor {
+            type Value = Output;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("a path, or a map of path and where")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(Output {
+                    path: Scalar(v.to_string()),
+                    conditions: None,
+                })
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut path = None;
+                let mut conditions = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "path" => path = Some(map.next_value()?),
+                        "where" => conditions = Some(map.next_value()?),
+                        key => return Err(de::Error::unknown_field(key, &["path", "where"])),
+                    }
+                }
+                Ok(Output {
+                    path: path.ok_or_else(|| de::Error::missing_field("path"))?,
+                    conditions,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(OutputVisitor)
+    }
+}
+
+/// `accounts:` of an OFFSET, by company ID, in the order they are written
+#[derive(Debug)]
+struct Accounts(Vec<(Scalar, Account)>);
+
+impl<'de> Deserialize<'de> for Accounts {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AccountsVisitor;
+
+        impl<'de> Visitor<'de> for AccountsVisitor {
+            type Value = Accounts;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("company IDs, each with its settlement account")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut accounts = vec![];
+                while let Some(entry) = map.next_entry()? {
+                    accounts.push(entry);
+                }
+                Ok(Accounts(accounts))
+            }
+        }
+
+        deserializer.deserialize_map(AccountsVisitor)
+    }
+}
+
+/// The settlement account a company's batches are offset against
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Account {
+    routing: Scalar,
+    account: Scalar,
+    /// `checking`, the default, or `savings`
+    account_type: Option<Scalar>,
+}
+
+/// A string, or a number or boolean taken as the text it was written as
+#[derive(Debug)]
+struct Scalar(String);
+
+struct ScalarVisitor;
+
+impl<'de> Visitor<'de> for ScalarVisitor {
+    type Value = Scalar;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("a string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(Scalar(v.to_string()))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(Scalar(v.to_string()))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(Scalar(v.to_string()))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(Scalar(v.to_string()))
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(Scalar(v.to_string()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Scalar {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ScalarVisitor)
+    }
+}
+
+/// A single value or a list of them, so `on: EntryDetail` and `on: [EntryDetail]` mean the same
+#[derive(Debug)]
+struct StringList(Vec<String>);
+
+impl<'de> Deserialize<'de> for StringList {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StringListVisitor;
+
+        impl<'de> Visitor<'de> for StringListVisitor {
+            type Value = StringList;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("a string or a list of strings")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(StringList(vec![v.to_string()]))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                Ok(StringList(vec![v.to_string()]))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut items = vec![];
+                while let Some(Scalar(item)) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(StringList(items))
+            }
+        }
+
+        deserializer.deserialize_any(StringListVisitor)
+    }
+}
+
+/// Text from a config, and the line and column it starts at
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RawValue {
+    pub text: String,
+    line: usize,
+    column: usize,
+}
+
+impl RawValue {
+    pub fn at(&self) -> (usize, usize) {
+        (self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RawEntry {
+    pub key: RawValue,
+    /// The value, or each item of a list. Empty for keys the schema does not know.
+    pub values: Vec<RawValue>,
+}
+
+/// A label and its `key: value` settings
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RawTransformation {
+    /// Config the transformation was read from, when it was read from a file
+    pub file: Option<PathBuf>,
+    pub label: RawValue,
+    pub entries: Vec<RawEntry>,
+}
+
+/// Read a config as `format` along with every config it includes, finding where each label,
+/// key and value was written so later problems can point at them, and fill in the vars and
+/// environment variables values refer to. Gives nothing if any of the configs could not be read
+/// at all.
+pub(crate) fn read(
+    source: &str,
+    format: ConfigFormat,
+    file: Option<&Path>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<Vec<RawTransformation>> {
+    let mut reader = Reader {
+        base: match file {
+            Some(file) => file.parent().unwrap_or(Path::new(".")).to_path_buf(),
+            None => PathBuf::from("./cfg"),
+        },
+        including: file.map(canonical).into_iter().collect(),
+        vars: BTreeMap::new(),
+        transformations: vec![],
+        diagnostics,
+        failed: false,
+    };
+    reader.read(source, format, file);
+    if reader.failed {
+        return None;
+    }
+    if reader.transformations.is_empty() {
+        reader.diagnostics.push(Diagnostic {
+            file: file.map(Path::to_path_buf),
+            ..Diagnostic::error((1, 1), "config has no transformations".to_string())
+        });
+    }
+
+    // A transformation whose values cannot all be filled in is not checked any further
+    let mut transformations = reader.transformations;
+    transformations.retain_mut(|transformation| {
+        let mut filled = true;
+        for entry in &mut transformation.entries {
+            for value in &mut entry.values {
+                match interpolate(
+                    &value.text,
+                    &reader.vars,
+                    &|name| env::var(name).ok(),
+                    &mut vec![],
+                ) {
+                    Ok(text) => value.text = text,
+                    Err(message) => {
+                        reader.diagnostics.push(Diagnostic {
+                            file: transformation.file.clone(),
+                            ..Diagnostic::error(
+                                value.at(),
+                                format!("'{}': {}", transformation.label.text, message),
+                            )
+                        });
+                        filled = false;
+                    }
+                }
+            }
+        }
+        filled
+    });
+
+    // Scripts, like includes, are relative to the config
+    for entry in transformations
+        .iter_mut()
+        .flat_map(|t| &mut t.entries)
+        .filter(|e| e.key.text == "script")
+    {
+        for value in &mut entry.values {
+            value.text = reader.base.join(&value.text).display().to_string();
+        }
+    }
+    Some(transformations)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Reads a config and, before it, each config it includes
+struct Reader<'a> {
+    /// Directory includes are relative to
+    base: PathBuf,
+    /// Configs being read, outermost first, to catch a config that includes itself
+    including: Vec<PathBuf>,
+    /// Later configs' vars replace those of the same name from configs they include
+    vars: BTreeMap<String, String>,
+    transformations: Vec<RawTransformation>,
+    diagnostics: &'a mut Vec<Diagnostic>,
+    failed: bool,
+}
+
+impl Reader<'_> {
+    fn read(&mut self, source: &str, format: ConfigFormat, file: Option<&Path>) {
+        let locator = Locator::new(source);
+        let parsed = match format {
+            ConfigFormat::Yaml => serde_yaml::from_str::<Option<ConfigFile>>(source)
+                .map(Option::unwrap_or_default)
+                .map_err(|e| {
+                    let at = e.location().map(|l| (l.line(), l.column()));
+                    (at.unwrap_or((1, 1)), e.to_string())
+                }),
+            ConfigFormat::Toml => toml::from_str::<ConfigFile>(source).map_err(|e| {
+                let at = e.span().map(|s| locator.position(s.start));
+                (at.unwrap_or((1, 1)), e.message().to_string())
+            }),
+            ConfigFormat::Json => serde_json::from_str::<ConfigFile>(source)
+                .map_err(|e| ((e.line(), e.column()), e.to_string())),
+        };
+        let config = match parsed {
+            Ok(config) => config,
+            Err((at, message)) => return self.fail(file, at, message),
+        };
+
+        let include_start = locator.find_top_level_key("include", 0).unwrap_or(0);
+        let mut from = include_start;
+        for include in &config.include {
+            let at = locator
+                .find(include, from, source.len())
+                .unwrap_or(include_start);
+            from = at.max(from);
+            self.include(include, file, locator.position(at));
+        }
+
+        let vars_start = locator.find_top_level_key("vars", 0).unwrap_or(0);
+        for (name, value) in config.vars {
+            let at = locator
+                .find_key(&name, vars_start, source.len())
+                .unwrap_or(vars_start);
+            if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                self.diagnostics.push(Diagnostic {
+                    file: file.map(Path::to_path_buf),
+                    ..Diagnostic::error(
+                        locator.position(at),
+                        format!("'{}' is not a valid var name", name),
+                    )
+                });
+            }
+            self.vars.insert(name, value);
+        }
+
+        // Each label's settings are looked for between it and the next label
+        let mut label_starts = vec![];
+        let mut from = 0;
+        for (label, _) in &config.transformations {
+            let start = locator.find_top_level_key(label, from).unwrap_or(from);
+            label_starts.push(start);
+            from = start + label.len();
+        }
+
+        for (i, (label, settings)) in config.transformations.into_iter().enumerate() {
+            let start = label_starts[i];
+            let end = label_starts.get(i + 1).copied().unwrap_or(source.len());
+            let label = locator.value(label, start);
+
+            let mut entries = vec![];
+            for (key, values) in settings
+                .map(TransformationConfig::settings)
+                .unwrap_or_default()
+            {
+                let key_start = locator
+                    .find_key_path(&key, start + label.text.len(), end)
+                    .unwrap_or(start);
+                let mut from = key_start + key.rsplit('.').next().unwrap_or_default().len();
+                let values = values
+                    .into_iter()
+                    .map(|value| {
+                        let value_start = locator.find(&value, from, end).unwrap_or(key_start);
+                        from = value_start.max(from);
+                        locator.value(value, value_start)
+                    })
+                    .collect();
+                entries.push(RawEntry {
+                    key: locator.value(key, key_start),
+                    values,
+                });
+            }
+            entries.sort_by_key(|e| e.key.at());
+
+            self.transformations.push(RawTransformation {
+                file: file.map(Path::to_path_buf),
+                label,
+                entries,
+            });
+        }
+    }
+
+    /// Read the config `include`, written at `at` in `file`
+    fn include(&mut self, include: &str, file: Option<&Path>, at: (usize, usize)) {
+        let path = self.base.join(include);
+        let path_id = canonical(&path);
+        if let Some(i) = self.including.iter().position(|p| *p == path_id) {
+            let cycle: Vec<_> = self.including[i..]
+                .iter()
+                .chain([&path_id])
+                .map(|p| p.display().to_string())
+                .collect();
+            return self.fail(file, at, format!("include cycle: {}", cycle.join(" -> ")));
+        }
+
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) => {
+                return self.fail(
+                    file,
+                    at,
+                    format!("could not include '{}': {}", path.display(), e),
+                )
+            }
+        };
+        self.including.push(path_id);
+        self.read(&source, ConfigFormat::from(path.as_path()), Some(&path));
+        self.including.pop();
+    }
+
+    fn fail(&mut self, file: Option<&Path>, at: (usize, usize), message: String) {
+        self.diagnostics.push(Diagnostic {
+            file: file.map(Path::to_path_buf),
+            ..Diagnostic::error(at, message)
+        });
+        self.failed = true;
+    }
+}
+
+/// Replace each `${NAME}` in `text` with the var `NAME`, or the environment variable `NAME`
+/// as `env` looks it up when there is no such var. `${NAME:-default}` gives `default` when
+/// there is neither. `resolving` holds the vars whose values are being filled in, to catch one
+/// that refers back to itself.
+fn interpolate(
+    text: &str,
+    vars: &BTreeMap<String, String>,
+    env: &dyn Fn(&str) -> Option<String>,
+    resolving: &mut Vec<String>,
+) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("'{}' has a '${{' that is never closed", text)),
+        };
+        let reference = &rest[start + 2..end];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+
+        if resolving.iter().any(|r| r == name) {
+            resolving.push(name.to_string());
+            return Err(format!("var cycle: {}", resolving.join(" -> ")));
+        }
+        let value = match (vars.get(name), env(name), default) {
+            (Some(value), _, _) => {
+                resolving.push(name.to_string());
+                let value = interpolate(value, vars, env, resolving)?;
+                resolving.pop();
+                value
+            }
+            (None, Some(value), _) => value,
+            (None, None, Some(default)) => default.to_string(),
+            (None, None, None) => {
+                return Err(format!(
+                    "'${{{}}}' is not a var or an environment variable, and has no default",
+                    name
+                ))
+            }
+        };
+        result.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// What a byte of a config is part of
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Lexeme {
+    Code,
+    /// Inside a quoted string, whose opening quote is at the offset held
+    Quoted(usize),
+    Comment,
+}
+
+/// Finds where text the deserializer handed back was written in the source
+struct Locator<'a> {
+    source: &'a str,
+    /// What each byte of `source` is part of, and how many brackets it is inside
+    lexemes: Vec<(Lexeme, usize)>,
+}
+
+impl<'a> Locator<'a> {
+    /// A quote opens a string only where a key or value can start, so the `'` of `O'Brien`
+    /// does not, and a `#` starts a comment only after whitespace. Strings and comments end
+    /// with their line.
+    fn new(source: &'a str) -> Self {
+        let mut lexemes = Vec::with_capacity(source.len());
+        let (mut quote, mut escaped, mut comment, mut depth) = (None, false, false, 0usize);
+        let mut previous = b'\n';
+        for (i, &b) in source.as_bytes().iter().enumerate() {
+            let lexeme = match (quote, b) {
+                (_, b'\n') => {
+                    (quote, comment) = (None, false);
+                    Lexeme::Code
+                }
+                _ if comment => Lexeme::Comment,
+                (Some(start), _) => {
+                    let opening = source.as_bytes()[start];
+                    if escaped {
+                        escaped = false;
+                    } else if b == b'\\' && opening == b'"' {
+                        escaped = true;
+                    } else if b == opening {
+                        quote = None;
+                    }
+                    Lexeme::Quoted(start)
+                }
+                (None, b'"' | b'\'')
+                    if previous.is_ascii_whitespace() || b"[{,:=".contains(&previous) =>
+                {
+                    quote = Some(i);
+                    Lexeme::Quoted(i)
+                }
+                (None, b'#') if previous.is_ascii_whitespace() => {
+                    comment = true;
+                    Lexeme::Comment
+                }
+                (None, b'[' | b'{') => {
+                    depth += 1;
+                    Lexeme::Code
+                }
+                (None, b']' | b'}') => {
+                    depth = depth.saturating_sub(1);
+                    Lexeme::Code
+                }
+                _ => Lexeme::Code,
+            };
+            lexemes.push((lexeme, depth));
+            previous = b;
+        }
+        Locator { source, lexemes }
+    }
+
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let before = &self.source[..offset];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        (before.matches('\n').count() + 1, offset - line_start + 1)
+    }
+
+    fn value(&self, text: String, offset: usize) -> RawValue {
+        let (line, column) = self.position(offset);
+        RawValue { text, line, column }
+    }
+
+    /// First `needle` in `source[from..to]` outside a comment
+    fn find(&self, needle: &str, from: usize, to: usize) -> Option<usize> {
+        let mut from = from.min(to);
+        while let Some(i) = self.source[from..to].find(needle) {
+            let start = from + i;
+            if self.lexemes[start].0 != Lexeme::Comment {
+                return Some(start);
+            }
+            from = start + needle.len();
+        }
+        None
+    }
+
+    /// First `key` in `source[from..to]` written as a key: whole, unquoted or the whole of a
+    /// quoted string, or in a TOML `[table]` header, and followed by `:` or `=`
+    fn find_key(&self, key: &str, from: usize, to: usize) -> Option<usize> {
+        let mut from = from.min(to);
+        while let Some(start) = self.find(key, from, to) {
+            let end = start + key.len();
+            let unquoted = match self.lexemes[start].0 {
+                Lexeme::Code => true,
+                Lexeme::Quoted(opening) => {
+                    opening + 1 == start
+                        && self.source.as_bytes().get(end) == Some(&self.source.as_bytes()[opening])
+                }
+                Lexeme::Comment => false,
+            };
+            let before = self.source[..start].chars().next_back();
+            let after = self.source[end..]
+                .trim_start_matches(['"', '\''])
+                .trim_start_matches([' ', '\t']);
+            let whole = !before.is_some_and(|c| c.is_alphanumeric() || c == '_');
+            if unquoted && whole && (after.starts_with([':', '=']) || after.starts_with(']')) {
+                return Some(start);
+            }
+            from = end;
+        }
+        None
+    }
+
+    /// [Locator::find_key] for a key of the config itself rather than of one of its
+    /// transformations: the first from `from` that is inside no more than the config's own
+    /// brackets, or that starts its line
+    fn find_top_level_key(&self, key: &str, from: usize) -> Option<usize> {
+        let mut from = from;
+        while let Some(start) = self.find_key(key, from, self.source.len()) {
+            let line_start = self.source[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+            let indent = self.source[line_start..start].trim_start_matches(['"', '\'', '[']);
+            if self.lexemes[start].1 == 1 || indent.is_empty() {
+                return Some(start);
+            }
+            from = start + key.len();
+        }
+        None
+    }
+
+    /// [Locator::find_key] for a key nested under others, written `outer.inner`. Gives where
+    /// the innermost key is.
+    fn find_key_path(&self, path: &str, from: usize, to: usize) -> Option<usize> {
+        let mut start = from;
+        for key in path.split('.') {
+            start = self.find_key(key, start, to)?;
+        }
+        Some(start)
+    }
+}
+
+#[cfg(test)]
+mod ach_config_tests {
+    use crate::ach_config::{interpolate, lint, lint_file, read, ConfigFormat, Severity};
+    use crate::ach_file::AchFile;
+    use crate::ach_transformations::Transformations;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    #[test]
+    fn test_parse_keeps_positions() {
+        let source = "---\n# comment\nhold:\n    operation: drop # inline\n    on: [EntryDetail,\n         CompanyBatchHeader]\n    where: individual_name == \"A:B\"\n";
+        let mut diagnostics = vec![];
+        let raw = read(source, ConfigFormat::Yaml, None, &mut diagnostics).unwrap();
+
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(raw.len(), 1);
+        assert_eq!(raw[0].label.text, "hold");
+        assert_eq!(raw[0].label.at(), (3, 1));
+
+        let entries = &raw[0].entries;
+        assert_eq!(entries[0].values[0].text, "drop");
+        assert_eq!(entries[0].values[0].at(), (4, 16));
+
+        let on = &entries[1].values;
+        assert_eq!(on[0].text, "EntryDetail");
+        assert_eq!(on[1].text, "CompanyBatchHeader");
+        assert_eq!(on[1].at(), (6, 10));
+
+        assert_eq!(entries[2].values[0].text, "individual_name == \"A:B\"");
+    }
+
+    #[test]
+    fn test_positions_skip_quoted_text() {
+        let source = "\
+hold:
+    operation: drop
+    where: [individual_id == \"#1\", 'individual_name == \"on: x\"', amount == 0]
+    on: EntryDetail
+on:
+    operation: drop # on: nothing
+    on: EntryDetail
+";
+        let mut diagnostics = vec![];
+        let raw = read(source, ConfigFormat::Yaml, None, &mut diagnostics).unwrap();
+
+        let hold = &raw[0].entries;
+        assert_eq!(hold[1].key.text, "where");
+        let at: Vec<_> = hold[1].values.iter().map(|v| v.at()).collect();
+        assert_eq!(at, [(3, 13), (3, 37), (3, 66)]);
+        assert_eq!(hold[2].key.text, "on");
+        assert_eq!(hold[2].key.at(), (4, 5));
+
+        // A label named like a key is found where it is a label
+        assert_eq!(raw[1].label.text, "on");
+        assert_eq!(raw[1].label.at(), (5, 1));
+        assert_eq!(raw[1].entries[1].key.at(), (7, 5));
+    }
+
+    #[test]
+    fn test_formats_load_the_same() {
+        let yaml = "\
+drop_zero_dollar:
+    operation: drop
+    on: EntryDetail
+    where: [amount == 0,
+            individual_name == \"A:B\"]
+backup_odfi:
+    operation: reroute
+    immediate_dest: 021000021
+    immediate_dest_name: Backup Bank NA
+";
+        let toml = r#"
+[drop_zero_dollar]
+operation = "drop"
+on = "EntryDetail"
+where = ["amount == 0", 'individual_name == "A:B"']
+
+[backup_odfi]
+operation = "reroute"
+immediate_dest = "021000021"
+immediate_dest_name = "Backup Bank NA"
+"#;
+        let json = r#"{
+    "drop_zero_dollar": {
+        "operation": "drop",
+        "on": ["EntryDetail"],
+        "where": ["amount == 0", "individual_name == \"A:B\""]
+    },
+    "backup_odfi": {
+        "operation": ["reroute"],
+        "immediate_dest": "021000021",
+        "immediate_dest_name": "Backup Bank NA"
+    }
+}"#;
+
+        let load = |source, format| {
+            format!(
+                "{:?}",
+                Transformations::from_config(source, format).unwrap()
+            )
+        };
+        let expected = load(yaml, ConfigFormat::Yaml);
+        assert!(expected.contains("A:B"));
+        assert_eq!(load(toml, ConfigFormat::Toml), expected);
+        assert_eq!(load(json, ConfigFormat::Json), expected);
+    }
+
+    #[test]
+    fn test_lint_reports_every_problem() {
+        let source = "\
+hold:
+    operation: quarantine
+    on: EntryDetial
+    where: amount == ten
+drop_test:
+    operation: drop
+    on: EntryDetail
+    where: [company_id == 1, individual_id == 0123456789012345]
+    colour: blue
+empty:
+";
+        let found: Vec<_> = lint(source, ConfigFormat::Yaml)
+            .into_iter()
+            .map(|d| (d.line, d.column, d.severity, d.message))
+            .collect();
+
+        assert_eq!(found[0].0, 3);
+        assert_eq!(found[0].1, 9);
+        assert!(found[0].3.contains("unknown record type 'EntryDetial'"));
+        assert_eq!((found[1].0, found[1].1), (8, 13));
+        assert!(found[1].3.contains("EntryDetail has no field 'company_id'"));
+        assert_eq!(
+            (found[2].0, found[2].1, found[2].2),
+            (8, 30, Severity::Warning)
+        );
+        assert_eq!((found[3].0, found[3].1), (9, 5));
+        assert!(found[3].3.contains("unknown key 'colour'"));
+        assert_eq!((found[4].0, found[4].1), (10, 1));
+        assert!(found[4].3.contains("'empty' has no settings"));
+        assert_eq!(found.len(), 5);
+
+        let mismatched = lint(
+            "hold:\n    operation: drop\n    on: EntryDetail\n    where: amount == ten\n",
+            ConfigFormat::Yaml,
+        );
+        assert!(mismatched[0].message.contains("numeric"));
+        assert_eq!((mismatched[0].line, mismatched[0].column), (4, 12));
+    }
+
+    #[test]
+    fn test_lint_schema_errors() {
+        let diagnostics = lint("hold:\n    operation: [drop\n", ConfigFormat::Yaml);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 3);
+
+        let diagnostics = lint(
+            "[hold]\noperation = \"drop\"\non = \"EntryDetail\"\nwhere = \"amount == 0\"\neffective_date = [1]\n",
+            ConfigFormat::Toml,
+        );
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (5, 18));
+        assert!(diagnostics[0].message.contains("expected a string"));
+
+        let diagnostics = lint("{\"hold\": {\"on\": {}}}", ConfigFormat::Json);
+        assert!(diagnostics[0]
+            .message
+            .contains("a string or a list of strings"));
+    }
+
+    #[test]
+    fn test_includes_and_vars() {
+        let path = Path::new("test_data/cfg/client.yml");
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let outcome = Transformations::try_from(path)
+            .unwrap()
+            .apply(&mut ach)
+            .unwrap();
+
+        let written = ach.to_string();
+        assert!(!written.contains("ZERO DOLLAR TEST"));
+        assert!(written.contains("BACKUP BANK NA"));
+        assert_eq!(outcome.trace_map.len(), 3);
+        assert!(outcome.quarantine.unwrap().to_string().contains("JANE ROE"));
+
+        // Without the client's vars the shared rules cannot be filled in
+        let diagnostics = lint_file(Path::new("test_data/cfg/shared_rules.yml")).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (8, 21));
+        assert!(diagnostics[0]
+            .message
+            .contains("'${BACKUP_DEST}' is not a var"));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let vars = BTreeMap::from([
+            ("HOLD_ACCOUNT".to_string(), "${HOLD:-555000111}".to_string()),
+            ("NAME".to_string(), "Backup".to_string()),
+        ]);
+        let env = |name: &str| (name == "HOLD").then(|| "444000222".to_string());
+        let unset = |_: &str| None;
+
+        assert_eq!(
+            interpolate("== ${HOLD_ACCOUNT}", &vars, &env, &mut vec![]),
+            Ok("== 444000222".to_string())
+        );
+        assert_eq!(
+            interpolate("== ${HOLD_ACCOUNT}", &vars, &unset, &mut vec![]),
+            Ok("== 555000111".to_string())
+        );
+        // Vars come before the environment
+        let env = |_: &str| Some("Environment".to_string());
+        assert_eq!(
+            interpolate("${NAME} Bank ${BRANCH:-NA}", &vars, &env, &mut vec![]),
+            Ok("Backup Bank Environment".to_string())
+        );
+        assert!(interpolate("${MISSING", &vars, &unset, &mut vec![])
+            .unwrap_err()
+            .contains("never closed"));
+    }
+
+    #[test]
+    fn test_include_and_var_cycles() {
+        let diagnostics = lint_file(Path::new("test_data/cfg/cycle_a.toml")).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].file.as_deref(),
+            Some(Path::new("test_data/cfg/cycle_b.yml"))
+        );
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (1, 11));
+        assert!(diagnostics[0].message.starts_with("include cycle: "));
+        assert!(diagnostics[0].message.ends_with("cycle_a.toml"));
+
+        let diagnostics = lint(
+            "vars:\n    A: ${B}\n    B: x ${A}\nhold:\n    operation: drop\n    on: EntryDetail\n    where: individual_name == ${A}\n",
+            ConfigFormat::Yaml,
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (7, 12));
+        assert!(diagnostics[0].message.contains("var cycle: A -> B -> A"));
+    }
+
+    #[test]
+    fn test_lint_empty_config() {
+        for (source, format) in [
+            ("# nothing here\n", ConfigFormat::Yaml),
+            ("", ConfigFormat::Toml),
+            ("{}", ConfigFormat::Json),
+        ] {
+            let diagnostics = lint(source, format);
+            assert_eq!(diagnostics.len(), 1);
+            assert!(diagnostics[0].message.contains("no transformations"));
+        }
+    }
+}
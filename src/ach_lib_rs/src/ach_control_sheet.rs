@@ -0,0 +1,258 @@
+use crate::ach_file::{AchFile, RecordId};
+use crate::ach_report::{dollars, ControlReport};
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+use sha2::{Digest, Sha256};
+
+/// US Letter, in points
+const PAGE_WIDTH: f32 = 612.0;
+const PAGE_HEIGHT: f32 = 792.0;
+const MARGIN: f32 = 54.0;
+const FONT_SIZE: f32 = 9.0;
+const LEADING: f32 = 12.0;
+/// Lines kept free at the foot of the last page for the signatures
+const SIGNATURE_LINES: usize = 10;
+
+/// SHA-256 of `bytes`, in lower case hex
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The lines of the sheet above the signatures
+fn sheet_lines(ach_file: &AchFile, file_bytes: &[u8]) -> Vec<String> {
+    let header = ach_file.record_fields(RecordId::Header).unwrap();
+    let field = |name| {
+        header
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.trim())
+            .unwrap_or_default()
+    };
+    let report = ControlReport::from(ach_file);
+
+    let mut lines = vec![
+        "ACH FILE CONTROL SHEET".to_string(),
+        String::new(),
+        format!(
+            "Immediate destination  {}  {}",
+            field("immediate_dest"),
+            field("immediate_dest_name")
+        ),
+        format!(
+            "Immediate origin       {}  {}",
+            field("immediate_orig"),
+            field("immediate_orig_name")
+        ),
+        format!(
+            "Created                {} {}",
+            field("file_creation_date"),
+            field("file_creation_time")
+        ),
+        format!("File ID modifier       {}", field("file_id_modifier")),
+        format!("SHA-256                {}", sha256_hex(file_bytes)),
+        format!("Size                   {} bytes", file_bytes.len()),
+        String::new(),
+        format!(
+            "{:<7}  {:<16}  {:<10}  {:<3}  {:<9}  {:>7}  {:>14}  {:>14}",
+            "Batch", "Company", "Company ID", "SEC", "Effective", "Entries", "Debits", "Credits"
+        ),
+    ];
+    for batch in &report.batches {
+        lines.push(format!(
+            "{:<7}  {:<16}  {:<10}  {:<3}  {:<9}  {:>7}  {:>14}  {:>14}",
+            batch.batch_number,
+            batch.company_name,
+            batch.company_id,
+            batch.sec,
+            batch.effective_entry_date,
+            batch.entries,
+            dollars(batch.total_debits.computed),
+            dollars(batch.total_credits.computed)
+        ));
+    }
+    lines.extend([
+        String::new(),
+        format!("Batches                {}", report.batch_count.computed),
+        format!(
+            "Entries and addenda    {}",
+            report.entry_and_addenda_count.computed
+        ),
+        format!("Entry hash             {}", report.entry_hash.computed),
+        format!(
+            "Total debits           {}",
+            dollars(report.total_debits.computed)
+        ),
+        format!(
+            "Total credits          {}",
+            dollars(report.total_credits.computed)
+        ),
+    ]);
+    let mismatches = report.mismatches();
+    if !mismatches.is_empty() {
+        lines.push(String::new());
+        lines.push("TRAILERS DO NOT ADD UP:".to_string());
+        lines.extend(mismatches);
+    }
+    lines
+}
+
+/// A PDF control sheet for approving `ach_file` before it is sent: its header, the totals of
+/// each batch and the file, the SHA-256 of `file_bytes`, which should be the exact bytes that
+/// are sent, and lines for a preparer and an approver to sign
+pub fn control_sheet(ach_file: &AchFile, file_bytes: &[u8]) -> Vec<u8> {
+    let lines = sheet_lines(ach_file, file_bytes);
+    let per_page = ((PAGE_HEIGHT - 2.0 * MARGIN) / LEADING) as usize;
+    let mut pages: Vec<&[String]> = lines.chunks(per_page).collect();
+    if pages.last().unwrap().len() + SIGNATURE_LINES > per_page {
+        pages.push(&[]);
+    }
+
+    let catalog_id = Ref::new(1);
+    let page_tree_id = Ref::new(2);
+    let font_id = Ref::new(3);
+    let info_id = Ref::new(4);
+    let page_ids: Vec<Ref> = (0..pages.len() as i32)
+        .map(|i| Ref::new(5 + 2 * i))
+        .collect();
+    let font_name = Name(b"F1");
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog_id).pages(page_tree_id);
+    pdf.pages(page_tree_id)
+        .kids(page_ids.iter().copied())
+        .count(pages.len() as i32);
+    pdf.type1_font(font_id).base_font(Name(b"Courier"));
+    pdf.document_info(info_id)
+        .title(TextStr("ACH file control sheet"));
+
+    for (i, (page_lines, page_id)) in pages.iter().zip(&page_ids).enumerate() {
+        let content_id = Ref::new(page_id.get() + 1);
+        let mut page = pdf.page(*page_id);
+        page.media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+            .parent(page_tree_id)
+            .contents(content_id);
+        page.resources().fonts().pair(font_name, font_id);
+        page.finish();
+
+        let mut content = Content::new();
+        content.begin_text().set_font(font_name, FONT_SIZE);
+        content.next_line(MARGIN, PAGE_HEIGHT - MARGIN);
+        for line in page_lines.iter() {
+            content.show(Str(line.as_bytes()));
+            content.next_line(0.0, -LEADING);
+        }
+        content.end_text();
+
+        let footer = format!("Page {} of {}", i + 1, pages.len());
+        content
+            .begin_text()
+            .set_font(font_name, FONT_SIZE)
+            .next_line(MARGIN, MARGIN / 2.0)
+            .show(Str(footer.as_bytes()))
+            .end_text();
+
+        if i == pages.len() - 1 {
+            signatures(&mut content, font_name);
+        }
+        pdf.stream(content_id, &content.finish());
+    }
+    pdf.finish()
+}
+
+/// Name, signature and date lines for the preparer and approver, at the foot of the page
+fn signatures(content: &mut Content, font_name: Name) {
+    let mut y = MARGIN + (SIGNATURE_LINES as f32 - 2.0) * LEADING;
+    for role in ["Prepared by", "Approved by"] {
+        content
+            .begin_text()
+            .set_font(font_name, FONT_SIZE)
+            .next_line(MARGIN, y)
+            .show(Str(role.as_bytes()))
+            .end_text();
+        y -= 2.0 * LEADING;
+        for (x, width, label) in [
+            (MARGIN, 180.0, "Name"),
+            (MARGIN + 200.0, 180.0, "Signature"),
+            (MARGIN + 400.0, 100.0, "Date"),
+        ] {
+            content
+                .set_line_width(0.5)
+                .move_to(x, y)
+                .line_to(x + width, y)
+                .stroke();
+            content
+                .begin_text()
+                .set_font(font_name, FONT_SIZE - 2.0)
+                .next_line(x, y - LEADING * 0.8)
+                .show(Str(label.as_bytes()))
+                .end_text();
+        }
+        y -= 2.0 * LEADING;
+    }
+}
+
+impl AchFile {
+    /// [control_sheet] for the file as [AchFile] writes it out
+    pub fn control_sheet(&self) -> Vec<u8> {
+        control_sheet(self, self.to_string().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod ach_control_sheet_tests {
+    use crate::ach_control_sheet::sha256_hex;
+    use crate::ach_file::AchFile;
+    use crate::ach_generate::{generate, GenerateOptions};
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    fn contains(pdf: &[u8], text: &str) -> bool {
+        pdf.windows(text.len()).any(|w| w == text.as_bytes())
+    }
+
+    #[test]
+    fn test_control_sheet() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let pdf = ach.control_sheet();
+        assert!(pdf.starts_with(b"%PDF-"));
+        assert!(contains(
+            &pdf,
+            &format!("(SHA-256                {})", sha256_hex(SAMPLE.as_bytes()))
+        ));
+        assert!(contains(
+            &pdf,
+            "(Immediate destination  091000019  DEST BANK)"
+        ));
+        assert!(contains(&pdf, "(File ID modifier       A)"));
+        assert!(contains(&pdf, "(Total debits           100.00)"));
+        assert!(contains(&pdf, "(Approved by)"));
+        assert!(contains(&pdf, "(Page 1 of 1)"));
+        assert!(!contains(&pdf, "TRAILERS DO NOT ADD UP"));
+        assert_eq!(pdf, ach.control_sheet());
+
+        let edited: AchFile = SAMPLE
+            .replace("0000007500INV100", "0000008000INV100")
+            .parse()
+            .unwrap();
+        assert!(contains(&edited.control_sheet(), "TRAILERS DO NOT ADD UP"));
+    }
+
+    #[test]
+    fn test_control_sheet_pages() {
+        let options = GenerateOptions {
+            batches: 70,
+            entries: 1,
+            ..Default::default()
+        };
+        let pdf = generate(&options).unwrap().control_sheet();
+        assert!(contains(&pdf, "(Page 2 of 2)"));
+        assert!(!contains(&pdf, "(Page 1 of 1)"));
+    }
+}
@@ -0,0 +1,446 @@
+use crate::ach_file::{AchFile, AchRecord, AchRecordType, CompanyBatch, EntryDetail};
+use crate::ach_report::dollars;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+/// One field that differs, with its padding trimmed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange {
+    /// Name of the field, prefixed with `addenda N.` for a field of an entry's Nth addenda
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Display for FieldChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: '{}' -> '{}'",
+            self.field, self.expected, self.actual
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffStatus {
+    /// Only in the actual file
+    Added,
+    /// Only in the expected file
+    Removed,
+    Changed,
+}
+
+impl Display for DiffStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiffStatus::Added => write!(f, "added"),
+            DiffStatus::Removed => write!(f, "removed"),
+            DiffStatus::Changed => write!(f, "changed"),
+        }
+    }
+}
+
+/// An entry that differs, matched by trace number
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EntryDiff {
+    pub trace: String,
+    pub individual_name: String,
+    pub amount: u64,
+    pub status: DiffStatus,
+    /// Fields that changed, empty for added and removed entries
+    pub fields: Vec<FieldChange>,
+}
+
+/// A batch that differs, matched by batch number and company ID
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchDiff {
+    pub batch_number: String,
+    pub company_id: String,
+    pub status: DiffStatus,
+    pub header: Vec<FieldChange>,
+    /// Entries that differ, or every entry of an added or removed batch
+    pub entries: Vec<EntryDiff>,
+    pub trailer: Vec<FieldChange>,
+}
+
+/// How one file differs from another, batch by batch and entry by entry, rather than line by
+/// line. Changes to padding alone are not differences.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileDiff {
+    pub header: Vec<FieldChange>,
+    pub batches: Vec<BatchDiff>,
+    pub trailer: Vec<FieldChange>,
+}
+
+/// `value` without its padding: spaces, and for numeric fields the zeros in front
+fn unpadded(value: &str, numeric: bool) -> &str {
+    let value = value.trim();
+    match numeric {
+        true => match value.trim_start_matches('0') {
+            "" if !value.is_empty() => "0",
+            digits => digits,
+        },
+        false => value,
+    }
+}
+
+/// The fields of two records of `record_type` that differ in more than padding
+fn field_changes(
+    record_type: AchRecordType,
+    expected: &dyn AchRecord,
+    actual: &dyn AchRecord,
+    prefix: &str,
+) -> Vec<FieldChange> {
+    record_type
+        .layout()
+        .iter()
+        .filter_map(|spec| {
+            let expected = expected.field(spec.name)?.to_string();
+            let actual = actual.field(spec.name)?.to_string();
+            (unpadded(&expected, spec.numeric) != unpadded(&actual, spec.numeric)).then(|| {
+                FieldChange {
+                    field: format!("{}{}", prefix, spec.name),
+                    expected: expected.trim().to_string(),
+                    actual: actual.trim().to_string(),
+                }
+            })
+        })
+        .collect()
+}
+
+fn field(record: &dyn AchRecord, name: &str) -> String {
+    record
+        .field(name)
+        .map(|f| f.to_string().trim().to_string())
+        .unwrap_or_default()
+}
+
+fn entry_diff(entry: &EntryDetail, status: DiffStatus, fields: Vec<FieldChange>) -> EntryDiff {
+    EntryDiff {
+        trace: field(entry, "trace"),
+        individual_name: field(entry, "individual_name"),
+        amount: entry.amount(),
+        status,
+        fields,
+    }
+}
+
+/// Pair off `expected` with `actual` by each key in turn, most exact first, as indices into
+/// each. Whatever is left over has no partner.
+fn pair<T>(
+    expected: &[T],
+    actual: &[T],
+    keys: &[&dyn Fn(&T) -> String],
+) -> Vec<(Option<usize>, Option<usize>)> {
+    let mut partner: Vec<Option<usize>> = vec![None; expected.len()];
+    let mut taken = vec![false; actual.len()];
+    for key in keys {
+        for (e, item) in expected.iter().enumerate() {
+            if partner[e].is_some() {
+                continue;
+            }
+            let found = (0..actual.len()).find(|a| !taken[*a] && key(&actual[*a]) == key(item));
+            if let Some(a) = found {
+                partner[e] = Some(a);
+                taken[a] = true;
+            }
+        }
+    }
+
+    let mut pairs: Vec<_> = partner
+        .into_iter()
+        .enumerate()
+        .map(|(e, a)| (Some(e), a))
+        .collect();
+    pairs.extend(
+        taken
+            .into_iter()
+            .enumerate()
+            .filter(|(_, taken)| !taken)
+            .map(|(a, _)| (None, Some(a))),
+    );
+    pairs
+}
+
+/// Indices of the entries of `expected` paired with those of `actual`, by trace number
+pub(crate) fn paired_entries(
+    expected: &CompanyBatch,
+    actual: &CompanyBatch,
+) -> Vec<(Option<usize>, Option<usize>)> {
+    let trace = |e: &EntryDetail| field(e, "trace");
+    pair(expected.entries(), actual.entries(), &[&trace])
+}
+
+fn batch_diff(expected: Option<&CompanyBatch>, actual: Option<&CompanyBatch>) -> Option<BatchDiff> {
+    let either = expected.or(actual).unwrap();
+    let mut diff = BatchDiff {
+        batch_number: field(either.header(), "batch_number"),
+        company_id: field(either.header(), "company_id"),
+        status: DiffStatus::Changed,
+        header: vec![],
+        entries: vec![],
+        trailer: vec![],
+    };
+
+    let (expected, actual) = match (expected, actual) {
+        (Some(expected), Some(actual)) => (expected, actual),
+        (Some(only), None) | (None, Some(only)) => {
+            diff.status = match actual {
+                Some(_) => DiffStatus::Added,
+                None => DiffStatus::Removed,
+            };
+            diff.entries = only
+                .entries()
+                .iter()
+                .map(|e| entry_diff(e, diff.status, vec![]))
+                .collect();
+            return Some(diff);
+        }
+        (None, None) => return None,
+    };
+
+    diff.header = field_changes(
+        AchRecordType::CompanyBatchHeader,
+        expected.header(),
+        actual.header(),
+        "",
+    );
+    diff.trailer = field_changes(
+        AchRecordType::CompanyBatchTrailer,
+        expected.trailer(),
+        actual.trailer(),
+        "",
+    );
+    for (e, a) in paired_entries(expected, actual) {
+        let pair = (
+            e.map(|e| &expected.entries()[e]),
+            a.map(|a| &actual.entries()[a]),
+        );
+        match pair {
+            (Some(expected), Some(actual)) => {
+                let mut fields = field_changes(AchRecordType::EntryDetail, expected, actual, "");
+                for i in 0..expected.addenda().len().max(actual.addenda().len()) {
+                    let prefix = format!("addenda {}.", i + 1);
+                    match (expected.addenda().get(i), actual.addenda().get(i)) {
+                        (Some(e), Some(a)) => {
+                            fields.extend(field_changes(AchRecordType::Addenda, e, a, &prefix))
+                        }
+                        (e, a) => fields.push(FieldChange {
+                            field: format!("{}record", prefix),
+                            expected: e
+                                .map(|e| field(e, "payment_related_info"))
+                                .unwrap_or_default(),
+                            actual: a
+                                .map(|a| field(a, "payment_related_info"))
+                                .unwrap_or_default(),
+                        }),
+                    }
+                }
+                if !fields.is_empty() {
+                    diff.entries
+                        .push(entry_diff(actual, DiffStatus::Changed, fields));
+                }
+            }
+            (Some(removed), None) => {
+                diff.entries
+                    .push(entry_diff(removed, DiffStatus::Removed, vec![]))
+            }
+            (None, Some(added)) => diff
+                .entries
+                .push(entry_diff(added, DiffStatus::Added, vec![])),
+            (None, None) => {}
+        }
+    }
+
+    let unchanged = diff.header.is_empty() && diff.entries.is_empty() && diff.trailer.is_empty();
+    (!unchanged).then_some(diff)
+}
+
+impl AchFile {
+    /// How `actual` differs from this file. Batches are matched by batch number and company
+    /// ID, then by company ID alone, then by batch number alone, so a renumbered batch is still
+    /// compared with the one it was. Entries are matched by trace number within their batch.
+    pub fn diff(&self, actual: &AchFile) -> FileDiff {
+        FileDiff {
+            header: field_changes(AchRecordType::Header, self.header(), actual.header(), ""),
+            batches: self
+                .paired_batches(actual)
+                .into_iter()
+                .filter_map(|(e, a)| {
+                    batch_diff(
+                        e.map(|e| &self.batches()[e]),
+                        a.map(|a| &actual.batches()[a]),
+                    )
+                })
+                .collect(),
+            trailer: field_changes(AchRecordType::Trailer, self.trailer(), actual.trailer(), ""),
+        }
+    }
+
+    /// Indices of the batches of this file paired with those of `actual`, as [AchFile::diff]
+    /// pairs them
+    pub(crate) fn paired_batches(&self, actual: &AchFile) -> Vec<(Option<usize>, Option<usize>)> {
+        let number_and_company = |b: &CompanyBatch| {
+            format!(
+                "{} {}",
+                field(b.header(), "batch_number"),
+                field(b.header(), "company_id")
+            )
+        };
+        let company = |b: &CompanyBatch| field(b.header(), "company_id");
+        let number = |b: &CompanyBatch| field(b.header(), "batch_number");
+        pair(
+            self.batches(),
+            actual.batches(),
+            &[&number_and_company, &company, &number],
+        )
+    }
+}
+
+impl FileDiff {
+    /// Whether the files are the same but for padding
+    pub fn is_empty(&self) -> bool {
+        self.header.is_empty() && self.batches.is_empty() && self.trailer.is_empty()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// A line for each change to the header, each batch and entry that differs with the fields
+/// that changed under it, then each change to the trailer
+impl Display for FileDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for change in &self.header {
+            writeln!(f, "header {}", change)?;
+        }
+        for batch in &self.batches {
+            writeln!(
+                f,
+                "batch {} ({}) {}",
+                batch.batch_number, batch.company_id, batch.status
+            )?;
+            for change in &batch.header {
+                writeln!(f, "  header {}", change)?;
+            }
+            for entry in &batch.entries {
+                writeln!(
+                    f,
+                    "  entry {} {} {} {}",
+                    entry.trace,
+                    entry.individual_name,
+                    dollars(entry.amount),
+                    entry.status
+                )?;
+                for change in &entry.fields {
+                    writeln!(f, "    {}", change)?;
+                }
+            }
+            for change in &batch.trailer {
+                writeln!(f, "  trailer {}", change)?;
+            }
+        }
+        for change in &self.trailer {
+            writeln!(f, "trailer {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod ach_diff_tests {
+    use crate::ach_diff::DiffStatus;
+    use crate::ach_file::{AchFile, EntryId, RecordId};
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    #[test]
+    fn test_diff() {
+        let expected: AchFile = SAMPLE.parse().unwrap();
+        assert!(expected.diff(&expected.clone()).is_empty());
+
+        let mut actual = expected.clone();
+        let john = EntryId { batch: 0, entry: 0 };
+        actual
+            .set_field(RecordId::Entry(john), "amount", "1200")
+            .unwrap();
+        actual
+            .set_field(
+                RecordId::Addenda(EntryId { batch: 0, entry: 2 }, 0),
+                "payment_related_info",
+                "INVOICE 43",
+            )
+            .unwrap();
+        actual.remove_entries(&[EntryId { batch: 0, entry: 1 }]);
+        // Padding alone is no change
+        actual
+            .set_field(RecordId::Header, "immediate_dest_name", "DEST BANK")
+            .unwrap();
+        // Renumbered, but still matched by company ID
+        actual
+            .set_field(RecordId::BatchHeader(1), "batch_number", "7")
+            .unwrap();
+        actual.recompute_controls();
+
+        let diff = expected.diff(&actual);
+        assert!(diff.header.is_empty());
+        assert_eq!(diff.batches.len(), 2);
+        let acme = &diff.batches[0];
+        assert_eq!(acme.status, DiffStatus::Changed);
+        assert_eq!(acme.entries.len(), 3);
+        assert_eq!(acme.entries[0].trace, "091000010000001");
+        assert_eq!(
+            acme.entries[0].fields[0].to_string(),
+            "amount: '0000001000' -> '0000001200'"
+        );
+        assert_eq!(acme.entries[1].status, DiffStatus::Removed);
+        assert_eq!(
+            acme.entries[2].fields[0].field,
+            "addenda 1.payment_related_info"
+        );
+        let beta = &diff.batches[1];
+        assert_eq!(beta.company_id, "9876543210");
+        assert_eq!(
+            beta.header[0].to_string(),
+            "batch_number: '0000002' -> '0000007'"
+        );
+        assert!(beta.entries.is_empty());
+
+        let text = diff.to_string();
+        assert!(text.starts_with(
+            "batch 0000001 (1234567890) changed\n  \
+             entry 091000010000001 JOHN DOE 12.00 changed\n    \
+             amount: '0000001000' -> '0000001200'\n"
+        ));
+        assert!(text.contains("  entry 091000010000002 ZERO DOLLAR TEST 0.00 removed\n"));
+        assert!(
+            text.contains("\ntrailer entry_and_addenda_count: '00000005' -> '00000004'\n"),
+            "{}",
+            text
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&diff.to_json()).unwrap();
+        assert_eq!(json["batches"][0]["entries"][1]["status"], "removed");
+    }
+
+    #[test]
+    fn test_diff_added_batch() {
+        let expected: AchFile = SAMPLE.parse().unwrap();
+        let (actual, _) = AchFile::merge(vec![expected.clone(), expected.clone()]).unwrap();
+        let diff = expected.diff(&actual);
+        let added: Vec<_> = diff
+            .batches
+            .iter()
+            .filter(|b| b.status == DiffStatus::Added)
+            .collect();
+        assert_eq!(added.len(), 2);
+        assert_eq!(added[0].entries.len(), 3);
+        assert!(added[0]
+            .entries
+            .iter()
+            .all(|e| e.status == DiffStatus::Added));
+    }
+}
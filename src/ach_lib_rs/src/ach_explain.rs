@@ -0,0 +1,202 @@
+use crate::ach_file::{record_line, AchFile, AchRecordType};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+/// One field of an [ExplainedRecord], where it sits on the line and what validation makes of it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExplainedField {
+    pub name: &'static str,
+    /// 1-based column the field starts at
+    pub start: usize,
+    pub size: usize,
+    pub value: String,
+    /// Problems [AchFile::validate] found with this field
+    pub problems: Vec<String>,
+}
+
+/// A record as written, broken down into its fields
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ExplainedRecord {
+    /// 1-based line of the record, as the file is written out
+    pub line: usize,
+    pub record_type: AchRecordType,
+    pub text: String,
+    pub fields: Vec<ExplainedField>,
+}
+
+impl AchFile {
+    /// Every record with its fields laid out and the validation problems of each attached, for
+    /// reading a file field by field. The filler that pads out the last block is left out.
+    pub fn explain(&self) -> Vec<ExplainedRecord> {
+        let issues = self.validate();
+        self.records()
+            .into_iter()
+            .enumerate()
+            .map(|(i, (record_type, record))| {
+                let line = i + 1;
+                let mut start = 1;
+                let fields = record_type
+                    .layout()
+                    .iter()
+                    .filter_map(|spec| {
+                        // Placed by the width the field has, so a field of the wrong width
+                        // does not throw those after it out of line with the text
+                        let value = record.field(spec.name)?.to_string();
+                        let field = ExplainedField {
+                            name: spec.name,
+                            start,
+                            size: value.len(),
+                            value,
+                            problems: issues
+                                .iter()
+                                .filter(|issue| issue.line == line && issue.field == spec.name)
+                                .map(|issue| issue.message.clone())
+                                .collect(),
+                        };
+                        start += field.size;
+                        Some(field)
+                    })
+                    .collect();
+
+                ExplainedRecord {
+                    line,
+                    record_type,
+                    text: record_line(record_type, record),
+                    fields,
+                }
+            })
+            .collect()
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+/// Fields take these colours in turn, so neighbouring fields stand apart
+const FIELD_COLOURS: &[&str] = &["\x1b[36m", "\x1b[33m"];
+const PROBLEM_COLOUR: &str = "\x1b[1;41m";
+
+impl ExplainedRecord {
+    /// The record under a column ruler, with a `|` under the first column of each field and `^`
+    /// under fields with problems, then a row per field. With `colour` each field is coloured
+    /// in turn and those with problems highlighted, using ANSI escapes.
+    pub fn render(&self, colour: bool) -> String {
+        let width = self.text.len().max(94);
+        let tens: String = (1..=width)
+            .map(|c| match c % 10 {
+                0 => char::from_digit((c / 10 % 10) as u32, 10).unwrap(),
+                _ => ' ',
+            })
+            .collect();
+        let units: String = (1..=width)
+            .map(|c| char::from_digit((c % 10) as u32, 10).unwrap())
+            .collect();
+
+        let paint = |i: usize, field: &ExplainedField, text: &str| match colour {
+            true if !field.problems.is_empty() => format!("{}{}{}", PROBLEM_COLOUR, text, RESET),
+            true => format!(
+                "{}{}{}",
+                FIELD_COLOURS[i % FIELD_COLOURS.len()],
+                text,
+                RESET
+            ),
+            false => text.to_string(),
+        };
+
+        let mut text = String::new();
+        let mut marks = String::new();
+        for (i, field) in self.fields.iter().enumerate() {
+            let value = &self.text[field.start - 1..field.start - 1 + field.size];
+            text.push_str(&paint(i, field, value));
+            let (first, rest) = match field.problems.is_empty() {
+                true => ('|', ' '),
+                false => ('^', '^'),
+            };
+            marks.push(first);
+            marks.extend(std::iter::repeat_n(rest, field.size.saturating_sub(1)));
+        }
+
+        let mut out = format!("line {}: {:?}\n", self.line, self.record_type);
+        for row in [tens.trim_end(), &units, &text, marks.trim_end()] {
+            out.push_str("    ");
+            out.push_str(row);
+            out.push('\n');
+        }
+        for (i, field) in self.fields.iter().enumerate() {
+            let columns = format!("{}-{}", field.start, field.start + field.size - 1);
+            out.push_str(&format!(
+                "    {:>5}  {}  '{}'\n",
+                columns,
+                paint(i, field, &format!("{:<26}", field.name)),
+                field.value
+            ));
+            for problem in &field.problems {
+                out.push_str(&format!("           ! {}\n", problem));
+            }
+        }
+        out
+    }
+}
+
+/// [ExplainedRecord::render] without colour
+impl Display for ExplainedRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(false))
+    }
+}
+
+#[cfg(test)]
+mod ach_explain_tests {
+    use crate::ach_file::{AchFile, AchRecordType};
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    #[test]
+    fn test_explain() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let records = ach.explain();
+        assert_eq!(records.len(), 11);
+        let entry = &records[2];
+        assert_eq!(entry.record_type, AchRecordType::EntryDetail);
+        let amount = entry.fields.iter().find(|f| f.name == "amount").unwrap();
+        assert_eq!((amount.start, amount.size), (30, 10));
+        assert_eq!(amount.value, "0000001000");
+        assert!(records
+            .iter()
+            .all(|r| r.fields.iter().all(|f| f.problems.is_empty())));
+
+        let text = entry.to_string();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "line 3: EntryDetail");
+        assert!(lines[2].starts_with("    1234567890123"));
+        assert_eq!(
+            lines[3],
+            "    622076401251123456789        0000001000EMP001         JOHN DOE                0091000010000001"
+        );
+        assert_eq!(
+            lines[4],
+            "    || |       ||                |         |              |                     | ||"
+        );
+        assert_eq!(
+            lines[10],
+            "    30-39  amount                      '0000001000'"
+        );
+    }
+
+    #[test]
+    fn test_explain_problems() {
+        let ach: AchFile = SAMPLE
+            .replace("622076401251123456789", "622076401259123456789")
+            .parse()
+            .unwrap();
+        let entry = &ach.explain()[2];
+        let text = entry.to_string();
+        assert!(text.contains("    || |       ^|"), "{}", text);
+        assert!(text.contains(
+            "    12-12  check_digit                 '9'\n           ! does not match \
+             receiving_dfi_id 07640125\n"
+        ));
+
+        let coloured = entry.render(true);
+        assert!(coloured.contains("\x1b[1;41m9\x1b[0m"));
+        assert!(coloured.contains("\x1b[36m6\x1b[0m\x1b[33m22\x1b[0m"));
+    }
+}
@@ -1,19 +1,25 @@
 // https://achdevguide.nacha.org/ach-file-details
 
 use crate::string_reader::StringReader;
-use log::{error, info};
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{BufRead, BufReader, Cursor, Read, Seek};
 use std::path::Path;
+use std::str::FromStr;
 
 #[derive(Debug)]
 pub struct AchError {}
 
-pub trait AchRecord: std::fmt::Debug {}
+pub(crate) trait AchRecord: std::fmt::Debug {
+    /// Look up a field of this record by its name, as listed in [AchRecordType::layout]
+    fn field(&self, name: &str) -> Option<&Field>;
+}
 
-#[derive(Debug)]
-pub(crate) enum AchRecordType {
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum AchRecordType {
     Header,
     CompanyBatchHeader,
     EntryDetail,
@@ -37,7 +43,198 @@ impl From<&str> for AchRecordType {
     }
 }
 
-fn checked_read_line(file: &mut BufReader<File>) -> Result<String, AchError> {
+/// A record as the single line it is written as, without any addenda that follow it
+pub(crate) fn record_line(record_type: AchRecordType, record: &dyn AchRecord) -> String {
+    record_type
+        .layout()
+        .iter()
+        .filter_map(|spec| record.field(spec.name))
+        .map(|field| field.to_string())
+        .collect()
+}
+
+/// Name, width and format of one field of a record
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldSpec {
+    pub name: &'static str,
+    pub size: usize,
+    /// Numeric fields are zero padded digits, everything else is left justified text
+    pub numeric: bool,
+}
+
+impl FieldSpec {
+    /// `value` as it is written in this field: digits zero padded for numeric fields, anything
+    /// else left justified. Fails if it does not fit.
+    pub fn format(&self, value: &str) -> Result<String, String> {
+        if value.len() > self.size {
+            return Err(format!(
+                "{} '{}' is longer than {} characters",
+                self.name, value, self.size
+            ));
+        }
+        if self.numeric {
+            if !value.chars().all(|c| c.is_ascii_digit()) {
+                return Err(format!("{} '{}' is not numeric", self.name, value));
+            }
+            Ok(format!("{:0>size$}", value, size = self.size))
+        } else {
+            Ok(format!("{:<size$}", value, size = self.size))
+        }
+    }
+}
+
+/// The line of a `record_type` record with the field `name` in it set to `value`
+fn with_field(
+    record_type: AchRecordType,
+    line: &str,
+    name: &str,
+    value: &str,
+) -> Result<String, String> {
+    let mut start = 0;
+    for spec in record_type.layout() {
+        if spec.name == name {
+            let mut line = line.to_string();
+            line.replace_range(start..start + spec.size, &spec.format(value)?);
+            return Ok(line);
+        }
+        start += spec.size;
+    }
+    Err(format!("{:?} has no field '{}'", record_type, name))
+}
+
+const fn numeric(name: &'static str, size: usize) -> FieldSpec {
+    FieldSpec {
+        name,
+        size,
+        numeric: true,
+    }
+}
+
+const fn alphanumeric(name: &'static str, size: usize) -> FieldSpec {
+    FieldSpec {
+        name,
+        size,
+        numeric: false,
+    }
+}
+
+const HEADER_LAYOUT: &[FieldSpec] = &[
+    numeric("record_type_code", 1),
+    numeric("priority_code", 2),
+    alphanumeric("immediate_dest", 10),
+    alphanumeric("immediate_orig", 10),
+    numeric("file_creation_date", 6),
+    numeric("file_creation_time", 4),
+    alphanumeric("file_id_modifier", 1),
+    numeric("record_size", 3),
+    numeric("blocking_factor", 2),
+    numeric("format_code", 1),
+    alphanumeric("immediate_dest_name", 23),
+    alphanumeric("immediate_orig_name", 23),
+    alphanumeric("reference_code", 8),
+];
+
+const COMPANY_BATCH_HEADER_LAYOUT: &[FieldSpec] = &[
+    numeric("record_type_code", 1),
+    numeric("service_class_code", 3),
+    alphanumeric("company_name", 16),
+    alphanumeric("company_discretionary_data", 20),
+    alphanumeric("company_id", 10),
+    alphanumeric("sec", 3),
+    alphanumeric("entry_desc", 10),
+    alphanumeric("company_descriptive_date", 6),
+    numeric("effective_entry_date", 6),
+    alphanumeric("settlement_date", 3),
+    alphanumeric("originator_status_code", 1),
+    numeric("odfi_id", 8),
+    numeric("batch_number", 7),
+];
+
+const ENTRY_DETAIL_LAYOUT: &[FieldSpec] = &[
+    numeric("record_type_code", 1),
+    numeric("transactions_code", 2),
+    numeric("receiving_dfi_id", 8),
+    numeric("check_digit", 1),
+    alphanumeric("dfi_account", 17),
+    numeric("amount", 10),
+    alphanumeric("individual_id", 15),
+    alphanumeric("individual_name", 22),
+    alphanumeric("discretionary_data", 2),
+    numeric("addenda_indicator", 1),
+    numeric("trace", 15),
+];
+
+const ADDENDA_LAYOUT: &[FieldSpec] = &[
+    numeric("record_type_code", 1),
+    numeric("addenda_type", 2),
+    alphanumeric("payment_related_info", 80),
+    numeric("addenda_sequence", 4),
+    numeric("batch", 7),
+];
+
+const COMPANY_BATCH_TRAILER_LAYOUT: &[FieldSpec] = &[
+    numeric("record_type_code", 1),
+    numeric("service_class_code", 3),
+    numeric("entry_and_addenda_count", 6),
+    numeric("entry_hash", 10),
+    numeric("total_debit_amount", 12),
+    numeric("total_credit_amount", 12),
+    alphanumeric("company_id", 10),
+    alphanumeric("message_auth_code", 19),
+    alphanumeric("reserved", 6),
+    numeric("originating_dfi_id_num", 8),
+    numeric("batch_num", 7),
+];
+
+const TRAILER_LAYOUT: &[FieldSpec] = &[
+    numeric("record_type_code", 1),
+    numeric("batch_count", 6),
+    numeric("block_count", 6),
+    numeric("entry_and_addenda_count", 8),
+    numeric("entry_hash", 10),
+    numeric("total_debits", 12),
+    numeric("total_credits", 12),
+    alphanumeric("reserved", 39),
+];
+
+/// Highest sequence the last 7 digits of a trace can hold, after the 8 of the ODFI id
+pub const MAX_TRACE_SEQUENCE: u64 = 9_999_999;
+
+impl AchRecordType {
+    /// The fields of this record type in the order they appear on the line. Their sizes add
+    /// up to the 94 characters of a record.
+    pub fn layout(&self) -> &'static [FieldSpec] {
+        match self {
+            AchRecordType::Header => HEADER_LAYOUT,
+            AchRecordType::CompanyBatchHeader => COMPANY_BATCH_HEADER_LAYOUT,
+            AchRecordType::EntryDetail => ENTRY_DETAIL_LAYOUT,
+            AchRecordType::Addenda => ADDENDA_LAYOUT,
+            AchRecordType::CompanyBatchTrailer => COMPANY_BATCH_TRAILER_LAYOUT,
+            AchRecordType::Trailer => TRAILER_LAYOUT,
+            AchRecordType::Unknown => &[],
+        }
+    }
+
+    /// The `record_type_code` a record of this type is written with
+    pub fn code(&self) -> &'static str {
+        match self {
+            AchRecordType::Header => "1",
+            AchRecordType::CompanyBatchHeader => "5",
+            AchRecordType::EntryDetail => "6",
+            AchRecordType::Addenda => "7",
+            AchRecordType::CompanyBatchTrailer => "8",
+            AchRecordType::Trailer => "9",
+            AchRecordType::Unknown => "",
+        }
+    }
+
+    /// Whether [AchRecord::field] resolves `name` for this record type
+    pub fn has_field(&self, name: &str) -> bool {
+        self.layout().iter().any(|f| f.name == name)
+    }
+}
+
+fn checked_read_line<R: Read>(file: &mut BufReader<R>) -> Result<String, AchError> {
     let mut line = "".to_string();
     match file.read_line(&mut line) {
         Ok(s) => {
@@ -52,7 +249,7 @@ fn checked_read_line(file: &mut BufReader<File>) -> Result<String, AchError> {
     Ok(line)
 }
 
-fn checked_read_type(file: &mut BufReader<File>) -> Result<char, AchError> {
+fn checked_read_type<R: Read>(file: &mut BufReader<R>) -> Result<char, AchError> {
     let mut record_type_code: [u8; 1] = [0];
     match file.read_exact(&mut record_type_code) {
         Ok(_) => {
@@ -75,6 +272,38 @@ impl Display for AchError {
 
 impl std::error::Error for AchError {}
 
+/// Where an entry is in a file: the batch it is in and its place within that batch, both 0-based
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EntryId {
+    pub batch: usize,
+    pub entry: usize,
+}
+
+/// Where a record is in a file. Batches, entries and addenda are counted from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordId {
+    Header,
+    BatchHeader(usize),
+    Entry(EntryId),
+    /// One of the addenda of an entry, by its place among them
+    Addenda(EntryId, usize),
+    BatchTrailer(usize),
+    Trailer,
+}
+
+impl RecordId {
+    pub fn record_type(&self) -> AchRecordType {
+        match self {
+            RecordId::Header => AchRecordType::Header,
+            RecordId::BatchHeader(_) => AchRecordType::CompanyBatchHeader,
+            RecordId::Entry(_) => AchRecordType::EntryDetail,
+            RecordId::Addenda(_, _) => AchRecordType::Addenda,
+            RecordId::BatchTrailer(_) => AchRecordType::CompanyBatchTrailer,
+            RecordId::Trailer => AchRecordType::Trailer,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct AchFile {
     header: Header,
@@ -144,23 +373,822 @@ impl AchFile {
         false
     }
 
-    pub fn split(&self, company_ids: Vec<String>) -> Result<(AchFile, AchFile), AchError> {
-        let mut ach_files = (AchFile::default(), AchFile::default());
-        ach_files.0.header = self.header.clone();
-        ach_files.1.header = self.header.clone();
+    /// Take every batch `route` gives a key out of this file, gathering the batches given the
+    /// same key into a file of their own under a copy of this file's header. Controls of this
+    /// file and every new one are recomputed.
+    pub(crate) fn split<K, F>(&mut self, mut route: F) -> Vec<(K, AchFile)>
+    where
+        K: PartialEq,
+        F: FnMut(&Header, &CompanyBatchHeader) -> Option<K>,
+    {
+        let mut files: Vec<(K, Vec<CompanyBatch>)> = vec![];
+        let mut kept = vec![];
+        for batch in std::mem::take(&mut self.records) {
+            match route(&self.header, &batch.batch_header) {
+                Some(key) => match files.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, batches)) => batches.push(batch),
+                    None => files.push((key, vec![batch])),
+                },
+                None => kept.push(batch),
+            }
+        }
+        self.records = kept;
+        self.recompute_controls();
+
+        files
+            .into_iter()
+            .map(|(key, batches)| (key, AchFile::from_batches(self.header.clone(), batches)))
+            .collect()
+    }
 
-        for company_batch in &self.records {
-            if company_ids.contains(&company_batch.batch_header.company_id.content) {
-                println!("found company id {}", company_batch.batch_header.company_id)
-            } else {
-                //println!("company id {} does not match any of {:?}", company_batch.batch_header.company_id, company_ids)
+    /// Put the batches of every one of `files` into one file under the header of the first.
+    /// Batches and traces are renumbered, since they are only unique within the file they came
+    /// from, and the map from each old trace to its new one returned. The files must all be
+    /// for the same immediate destination.
+    pub fn merge(files: Vec<AchFile>) -> Result<(AchFile, TraceMap), String> {
+        let mut files = files.into_iter();
+        let mut merged = files.next().ok_or("no files to merge")?;
+        for (i, file) in files.enumerate() {
+            if file.header.immediate_dest.as_str() != merged.header.immediate_dest.as_str() {
+                return Err(format!(
+                    "file {} is for immediate destination '{}', not '{}'",
+                    i + 2,
+                    file.header.immediate_dest.as_str().trim(),
+                    merged.header.immediate_dest.as_str().trim()
+                ));
             }
+            merged.records.extend(file.records);
         }
 
-        Ok(ach_files)
+        let trace_map = merged.renumber()?;
+        Ok((merged, trace_map))
+    }
+
+    pub fn batch_count(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Every entry in the file, in the order they are written
+    pub fn entry_ids(&self) -> Vec<EntryId> {
+        self.records
+            .iter()
+            .enumerate()
+            .flat_map(|(batch, b)| {
+                (0..b.batch_records.len()).map(move |entry| EntryId { batch, entry })
+            })
+            .collect()
+    }
+
+    /// Field `name` of the header of `batch`, as written
+    pub fn batch_field(&self, batch: usize, name: &str) -> Option<&str> {
+        let field = self.records.get(batch)?.batch_header.field(name)?;
+        Some(field.as_str())
+    }
+
+    /// Field `name` of `entry`, as written
+    pub fn entry_field(&self, entry: EntryId, name: &str) -> Option<&str> {
+        let field = self
+            .records
+            .get(entry.batch)?
+            .batch_records
+            .get(entry.entry)?
+            .field(name)?;
+        Some(field.as_str())
+    }
+
+    /// Set field `name` of the header of `batch`, padding `value` as the field requires.
+    /// Controls are not recomputed.
+    pub fn set_batch_field(&mut self, batch: usize, name: &str, value: &str) -> Result<(), String> {
+        self.set_field(RecordId::BatchHeader(batch), name, value)
+    }
+
+    /// Set field `name` of `entry`, padding `value` as the field requires. Controls are not
+    /// recomputed.
+    pub fn set_entry_field(
+        &mut self,
+        entry: EntryId,
+        name: &str,
+        value: &str,
+    ) -> Result<(), String> {
+        self.set_field(RecordId::Entry(entry), name, value)
+    }
+
+    /// Every record in the order it is written, so the record at index `i` is the one
+    /// [AchFile::validate] reports as line `i + 1`
+    pub fn record_ids(&self) -> Vec<RecordId> {
+        let mut ids = vec![RecordId::Header];
+        for (batch, b) in self.records.iter().enumerate() {
+            ids.push(RecordId::BatchHeader(batch));
+            for (entry, e) in b.batch_records.iter().enumerate() {
+                let entry = EntryId { batch, entry };
+                ids.push(RecordId::Entry(entry));
+                ids.extend((0..e.addenda.len()).map(|a| RecordId::Addenda(entry, a)));
+            }
+            ids.push(RecordId::BatchTrailer(batch));
+        }
+        ids.push(RecordId::Trailer);
+        ids
+    }
+
+    fn record(&self, id: RecordId) -> Option<&dyn AchRecord> {
+        let entry = |e: EntryId| self.records.get(e.batch)?.batch_records.get(e.entry);
+        Some(match id {
+            RecordId::Header => &self.header,
+            RecordId::BatchHeader(batch) => &self.records.get(batch)?.batch_header,
+            RecordId::Entry(e) => entry(e)?,
+            RecordId::Addenda(e, a) => entry(e)?.addenda.get(a)?,
+            RecordId::BatchTrailer(batch) => &self.records.get(batch)?.batch_trailer,
+            RecordId::Trailer => &self.trailer,
+        })
+    }
+
+    /// Each field of the record `id` by name, as written, in the order of its layout
+    pub fn record_fields(&self, id: RecordId) -> Option<Vec<(&'static str, &str)>> {
+        let record = self.record(id)?;
+        let layout = id.record_type().layout();
+        Some(
+            layout
+                .iter()
+                .filter_map(|spec| Some((spec.name, record.field(spec.name)?.as_str())))
+                .collect(),
+        )
+    }
+
+    /// Set field `name` of the record `id`, padding `value` as the field requires. Controls are
+    /// not recomputed, and setting them by hand only lasts until they are.
+    pub fn set_field(&mut self, id: RecordId, name: &str, value: &str) -> Result<(), String> {
+        let record_type = id.record_type();
+        let line = record_line(
+            record_type,
+            self.record(id)
+                .ok_or_else(|| format!("no record {:?}", id))?,
+        );
+        let line = with_field(record_type, &line, name, value)?;
+        let reader = StringReader::new(line[1..].to_string());
+
+        // `record` found it, so every index below is in range
+        match id {
+            RecordId::Header => self.header = Header::from(reader),
+            RecordId::BatchHeader(batch) => {
+                self.records[batch].batch_header = CompanyBatchHeader::from(reader)
+            }
+            RecordId::Entry(e) => {
+                let entry = &mut self.records[e.batch].batch_records[e.entry];
+                let addenda = std::mem::take(&mut entry.addenda);
+                *entry = EntryDetail::from(reader);
+                entry.addenda = addenda;
+            }
+            RecordId::Addenda(e, a) => {
+                self.records[e.batch].batch_records[e.entry].addenda[a] = Addenda::from(reader)
+            }
+            RecordId::BatchTrailer(batch) => {
+                self.records[batch].batch_trailer = CompanyBatchTrailer::from(reader)
+            }
+            RecordId::Trailer => self.trailer = Trailer::from(reader),
+        }
+        Ok(())
+    }
+
+    /// Add the entry written as `line` to the end of `batch`. Controls are not recomputed.
+    pub fn insert_entry(&mut self, batch: usize, line: &str) -> Result<EntryId, String> {
+        if line.len() != 94 || !line.starts_with('6') {
+            return Err(format!("'{}' is not a 94 character entry record", line));
+        }
+        let entries = &mut self
+            .records
+            .get_mut(batch)
+            .ok_or_else(|| format!("no batch {}", batch))?
+            .batch_records;
+        entries.push(EntryDetail::from(StringReader::new(line[1..].to_string())));
+        Ok(EntryId {
+            batch,
+            entry: entries.len() - 1,
+        })
+    }
+
+    /// Remove each of `entries` and return them, with their addenda, as a file of their own under
+    /// copies of this file's header and their batch headers. Batches are kept in this file even
+    /// if they are left empty, and its controls are not recomputed.
+    pub fn take_entries(&mut self, entries: &[EntryId]) -> AchFile {
+        let mut taken = vec![];
+        for (b, batch) in self.records.iter_mut().enumerate() {
+            let mut e = 0;
+            let (matched, kept) = batch.batch_records.drain(..).partition::<Vec<_>, _>(|_| {
+                e += 1;
+                entries.contains(&EntryId {
+                    batch: b,
+                    entry: e - 1,
+                })
+            });
+            batch.batch_records = kept;
+            if !matched.is_empty() {
+                taken.push(CompanyBatch::from_entries(
+                    batch.batch_header.clone(),
+                    matched,
+                ));
+            }
+        }
+        AchFile::from_batches(self.header.clone(), taken)
+    }
+
+    /// Add the batches of `other` to this file as they are, such as entries [AchFile::take_entries]
+    /// took earlier. A batch with the same header as one here joins it, keeping its entries in
+    /// trace order. Nothing is renumbered, and controls are recomputed.
+    pub fn append(&mut self, other: AchFile) {
+        for batch in other.records {
+            let header = record_line(AchRecordType::CompanyBatchHeader, &batch.batch_header);
+            match self
+                .records
+                .iter_mut()
+                .find(|b| record_line(AchRecordType::CompanyBatchHeader, &b.batch_header) == header)
+            {
+                Some(existing) => {
+                    existing.batch_records.extend(batch.batch_records);
+                    existing
+                        .batch_records
+                        .sort_by(|a, b| a.trace.as_str().cmp(b.trace.as_str()));
+                }
+                None => self.records.push(batch),
+            }
+        }
+        self.recompute_controls();
+    }
+
+    /// Remove each of `entries`, along with their addenda. Batches are kept even if they are
+    /// left empty, and controls are not recomputed.
+    pub fn remove_entries(&mut self, entries: &[EntryId]) {
+        for (b, batch) in self.records.iter_mut().enumerate() {
+            let mut e = 0;
+            batch.batch_records.retain(|_| {
+                e += 1;
+                !entries.contains(&EntryId {
+                    batch: b,
+                    entry: e - 1,
+                })
+            });
+        }
+    }
+
+    /// Remove batches with no entries left, returning how many there were. Controls are not
+    /// recomputed.
+    pub fn remove_empty_batches(&mut self) -> usize {
+        let before = self.records.len();
+        self.records.retain(|b| !b.batch_records.is_empty());
+        before - self.records.len()
+    }
+
+    pub(crate) fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub(crate) fn batches(&self) -> &Vec<CompanyBatch> {
+        &self.records
+    }
+
+    pub(crate) fn batches_mut(&mut self) -> &mut Vec<CompanyBatch> {
+        &mut self.records
+    }
+
+    pub(crate) fn trailer(&self) -> &Trailer {
+        &self.trailer
+    }
+
+    /// Build a new file around an existing header, with controls computed from `batches`
+    pub(crate) fn from_batches(header: Header, batches: Vec<CompanyBatch>) -> Self {
+        let mut ach_file = AchFile {
+            header,
+            records: batches,
+            trailer: Default::default(),
+        };
+        ach_file.recompute_controls();
+        ach_file
+    }
+
+    /// Every record in the order it is written, without the filler that pads out the last block
+    pub(crate) fn records(&self) -> Vec<(AchRecordType, &dyn AchRecord)> {
+        let mut records: Vec<(AchRecordType, &dyn AchRecord)> =
+            vec![(AchRecordType::Header, &self.header)];
+        for batch in &self.records {
+            records.extend(batch.records());
+        }
+        records.push((AchRecordType::Trailer, &self.trailer));
+        records
+    }
+
+    /// Point the file at a different bank: the immediate destination and origin in the header,
+    /// the ODFI of every batch and the ODFI prefix of every trace number are rewritten.
+    pub(crate) fn reroute(&mut self, profile: &BankProfile) -> TraceMap {
+        let mut trace_map = TraceMap::default();
+
+        self.header.immediate_dest = Field::from(profile.immediate_dest.as_str());
+        self.header.immediate_dest_name = Field::text(&profile.immediate_dest_name, 23);
+        if let Some(immediate_orig) = &profile.immediate_orig {
+            self.header.immediate_orig = Field::from(immediate_orig.as_str());
+        }
+
+        for batch in &mut self.records {
+            batch.batch_header.odfi_id = Field::from(profile.odfi_id.as_str());
+            batch.batch_trailer.originating_dfi_id_num = Field::from(profile.odfi_id.as_str());
+            for entry in &mut batch.batch_records {
+                let old_trace = entry.trace.as_str().to_string();
+                let sequence = old_trace.get(8..).unwrap_or_default();
+                entry.trace = Field::from(format!("{}{}", profile.odfi_id, sequence));
+                trace_map.insert(&old_trace, entry.trace.as_str());
+            }
+        }
+
+        trace_map
+    }
+
+    /// Merge each batch into the first one before it whose header agrees on company ID, SEC code,
+    /// service class, entry description and effective entry date, as well as ODFI, since the
+    /// traces of its entries start with it. Entries keep their order and traces. Batches are
+    /// numbered from 1 and controls recomputed. Gives how many batches were merged away.
+    ///
+    /// The first batch's company name, discretionary data and descriptive date are kept, and
+    /// a warning names each merged batch that had others.
+    pub fn consolidate_batches(&mut self) -> usize {
+        let key = |h: &CompanyBatchHeader| {
+            [
+                &h.company_id,
+                &h.sec,
+                &h.service_class_code,
+                &h.entry_desc,
+                &h.effective_entry_date,
+                &h.odfi_id,
+            ]
+            .map(|f| f.as_str().to_string())
+        };
+        let before = self.records.len();
+        let mut batches: Vec<CompanyBatch> = vec![];
+        for batch in self.records.drain(..) {
+            match batches
+                .iter_mut()
+                .find(|b| key(&b.batch_header) == key(&batch.batch_header))
+            {
+                Some(existing) => {
+                    for name in [
+                        "company_name",
+                        "company_discretionary_data",
+                        "company_descriptive_date",
+                    ] {
+                        let kept = existing.batch_header.field(name).unwrap().as_str();
+                        let dropped = batch.batch_header.field(name).unwrap().as_str();
+                        if kept != dropped {
+                            warn!(
+                                "Batch {} merged into batch {}, whose {} '{}' replaces '{}'",
+                                batch.batch_header.batch_number.as_str(),
+                                existing.batch_header.batch_number.as_str(),
+                                name,
+                                kept.trim(),
+                                dropped.trim()
+                            );
+                        }
+                    }
+                    existing.batch_records.extend(batch.batch_records)
+                }
+                None => batches.push(batch),
+            }
+        }
+        self.records = batches;
+
+        for (i, batch) in self.records.iter_mut().enumerate() {
+            batch.batch_header.batch_number = Field::numeric(i as u64 + 1, 7);
+        }
+        self.recompute_controls();
+        before - self.records.len()
+    }
+
+    /// Regroup every entry into new batches, one for each distinct `key` among the entries of
+    /// batches that share a company, SEC code, entry description and ODFI, and effective entry
+    /// date unless `effective_entry_date` replaces it, so entries never move to a batch that
+    /// would send them differently. Each new batch header is copied from the first batch the
+    /// group draws entries from, with its service class code set to match the entries it ends
+    /// up holding, and its effective entry date replaced when `effective_entry_date` is given.
+    /// Batches and traces are renumbered.
+    pub(crate) fn rebatch<F>(
+        &mut self,
+        key: F,
+        effective_entry_date: Option<&str>,
+    ) -> Result<TraceMap, String>
+    where
+        F: Fn(&CompanyBatchHeader, &EntryDetail) -> String,
+    {
+        let mut groups: Vec<(String, CompanyBatchHeader, Vec<EntryDetail>)> = vec![];
+
+        for batch in self.records.drain(..) {
+            let batch_header = batch.batch_header;
+            let date = match effective_entry_date {
+                Some(_) => "",
+                None => batch_header.effective_entry_date.as_str(),
+            };
+            let batch_key = [
+                batch_header.company_id.as_str(),
+                batch_header.sec.as_str(),
+                batch_header.entry_desc.as_str(),
+                batch_header.odfi_id.as_str(),
+                date,
+            ]
+            .join("|");
+            for entry in batch.batch_records {
+                // Entries sent differently never share a batch, whatever the key says
+                let group_key = format!("{}|{}", batch_key, key(&batch_header, &entry));
+                match groups.iter_mut().find(|(k, _, _)| *k == group_key) {
+                    Some((_, _, entries)) => entries.push(entry),
+                    None => groups.push((group_key, batch_header.clone(), vec![entry])),
+                }
+            }
+        }
+
+        self.records = groups
+            .into_iter()
+            .map(|(_, mut batch_header, entries)| {
+                batch_header.service_class_code = Field::from(service_class_code(&entries));
+                if let Some(date) = effective_entry_date {
+                    batch_header.effective_entry_date = Field::from(date);
+                }
+                CompanyBatch::from_entries(batch_header, entries)
+            })
+            .collect();
+
+        self.renumber()
+    }
+
+    /// Number batches sequentially from 1, in their headers and trailers, and give every entry a
+    /// trace number made of its batch's ODFI id and a sequence running through the whole file.
+    /// Each addenda takes its entry's sequence and is numbered from 1 after it. Gives the
+    /// original and new trace of every entry whose trace changed.
+    pub fn renumber(&mut self) -> Result<TraceMap, String> {
+        self.renumber_from(1)
+    }
+
+    /// [AchFile::renumber], with the trace sequence starting at `first_sequence`. Fails, leaving
+    /// the file as it was, when the sequence would run past the 7 digits a trace has for it.
+    pub fn renumber_from(&mut self, first_sequence: u64) -> Result<TraceMap, String> {
+        let entries = self.entry_ids().len() as u64;
+        let last_sequence = first_sequence.checked_add(entries.saturating_sub(1));
+        if last_sequence.is_none_or(|last| last > MAX_TRACE_SEQUENCE) {
+            return Err(format!(
+                "{} entries numbered from {} would run past trace sequence {}",
+                entries, first_sequence, MAX_TRACE_SEQUENCE
+            ));
+        }
+
+        let mut trace_map = TraceMap::default();
+        let mut sequence = first_sequence;
+
+        for (i, batch) in self.records.iter_mut().enumerate() {
+            batch.batch_header.batch_number = Field::numeric(i as u64 + 1, 7);
+            for entry in &mut batch.batch_records {
+                let entry_sequence = Field::numeric(sequence, 7);
+                sequence += 1;
+                let old_trace = entry.trace.as_str().to_string();
+                entry.trace =
+                    Field::from(format!("{}{}", batch.batch_header.odfi_id, entry_sequence));
+                trace_map.insert(&old_trace, entry.trace.as_str());
+                for (j, addenda) in entry.addenda.iter_mut().enumerate() {
+                    addenda.addenda_sequence = Field::numeric(j as u64 + 1, 4);
+                    addenda.batch = entry_sequence.clone();
+                }
+            }
+        }
+
+        self.recompute_controls();
+        Ok(trace_map)
+    }
+
+    /// Rebuild every [CompanyBatchTrailer] and the file [Trailer] from the records they summarize.
+    /// Must be called after adding, removing or changing entries.
+    pub fn recompute_controls(&mut self) {
+        let mut entry_and_addenda_count = 0;
+        let mut entry_hash = 0;
+        let mut total_debits = 0;
+        let mut total_credits = 0;
+
+        for batch in &mut self.records {
+            batch.recompute_trailer();
+            entry_and_addenda_count += batch.entry_and_addenda_count();
+            entry_hash += batch.entry_hash();
+            total_debits += batch.total_debits();
+            total_credits += batch.total_credits();
+        }
+
+        self.trailer = Trailer {
+            record_type_code: Field::from("9"),
+            batch_count: Field::numeric(self.records.len() as u64, 6),
+            block_count: Field::numeric(self.len().div_ceil(10) as u64, 6),
+            entry_and_addenda_count: Field::numeric(entry_and_addenda_count, 8),
+            entry_hash: Field::numeric(entry_hash, 10),
+            total_debits: Field::numeric(total_debits, 12),
+            total_credits: Field::numeric(total_credits, 12),
+            reserved: Field::from(39),
+        };
     }
 }
 
+/// The identifiers of the bank a file is sent through, as used by [AchFile::reroute].
+/// Build one with [BankProfile::new], which checks and pads each value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BankProfile {
+    /// Routing number of the receiving bank, with the leading blank: ` 021000021`
+    pub immediate_dest: String,
+    pub immediate_dest_name: String,
+    /// Our identifier at that bank, if it differs from the one already in the file
+    pub immediate_orig: Option<String>,
+    /// First 8 digits of the ODFI routing number, which also prefix every trace number
+    pub odfi_id: String,
+}
+
+impl BankProfile {
+    /// `immediate_dest` is a 9 digit routing number, with or without the leading blank. The ODFI
+    /// defaults to the first 8 digits of that routing number.
+    pub fn new(
+        immediate_dest: &str,
+        immediate_dest_name: &str,
+        immediate_orig: Option<&str>,
+        odfi_id: Option<&str>,
+    ) -> Result<Self, String> {
+        let routing = immediate_dest.trim();
+        if !is_routing_number(routing) {
+            return Err(format!(
+                "immediate_dest '{}' is not a valid routing number",
+                routing
+            ));
+        }
+        if immediate_dest_name.len() > 23 {
+            return Err(format!(
+                "immediate_dest_name '{}' is longer than 23 characters",
+                immediate_dest_name
+            ));
+        }
+        let immediate_orig = match immediate_orig.map(str::trim) {
+            Some(orig) if orig.len() == 9 && orig.chars().all(|c| c.is_ascii_digit()) => {
+                Some(format!(" {}", orig))
+            }
+            Some(orig) if orig.len() == 10 => Some(orig.to_string()),
+            Some(orig) => {
+                return Err(format!(
+                    "immediate_orig '{}' must be 9 digits or 10 characters",
+                    orig
+                ))
+            }
+            None => None,
+        };
+        let odfi_id = odfi_id.map(str::trim).unwrap_or(&routing[..8]).to_string();
+        if odfi_id.len() != 8 || !odfi_id.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!("odfi_id '{}' must be 8 digits", odfi_id));
+        }
+
+        Ok(BankProfile {
+            immediate_dest: format!(" {}", routing),
+            immediate_dest_name: immediate_dest_name.to_uppercase(),
+            immediate_orig,
+            odfi_id,
+        })
+    }
+}
+
+/// Check the ABA check digit of a 9 digit routing number
+pub(crate) fn is_routing_number(routing: &str) -> bool {
+    routing.len() == 9
+        && routing.chars().all(|c| c.is_ascii_digit())
+        && routing_check_digit(&routing[..8]) == routing[8..].parse().ok()
+}
+
+/// The ABA check digit for the first 8 digits of a routing number
+pub(crate) fn routing_check_digit(dfi_id: &str) -> Option<u32> {
+    let mut sum = 0;
+    for (digit, weight) in dfi_id.chars().zip([3, 7, 1].iter().cycle()) {
+        sum += digit.to_digit(10)? * weight;
+    }
+    Some((10 - sum % 10) % 10)
+}
+
+/// Original and rewritten trace numbers, in the order the entries were changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TraceMap {
+    traces: Vec<(String, String)>,
+    /// Where in `traces` each original trace first appears
+    index: HashMap<String, usize>,
+}
+
+impl TraceMap {
+    /// Record that the entry traced as `old` is now traced as `new`
+    pub fn insert(&mut self, old: &str, new: &str) {
+        if old != new {
+            self.index
+                .entry(old.to_string())
+                .or_insert(self.traces.len());
+            self.traces.push((old.to_string(), new.to_string()));
+        }
+    }
+
+    /// Follow this map with the changes of a later step. Where the later step renumbered an
+    /// entry this map already covers, the existing mapping is carried forward, so the map
+    /// always leads from the trace the entry originally had.
+    pub fn extend(&mut self, later: TraceMap) {
+        let current = first_positions(self.traces.iter().map(|(_, new)| new));
+        for (old, new) in later.traces {
+            match current.get(&old) {
+                Some(i) => self.traces[*i].1 = new,
+                None => self.traces.push((old, new)),
+            }
+        }
+        self.traces.retain(|(old, new)| old != new);
+        self.index = first_positions(self.traces.iter().map(|(old, _)| old));
+    }
+
+    pub fn get(&self, original: &str) -> Option<&str> {
+        self.index.get(original).map(|i| self.traces[*i].1.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.traces
+            .iter()
+            .map(|(old, new)| (old.as_str(), new.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.traces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.traces.is_empty()
+    }
+}
+
+/// Where each of `traces` first appears among them
+fn first_positions<'a>(traces: impl Iterator<Item = &'a String>) -> HashMap<String, usize> {
+    let mut positions = HashMap::new();
+    for (i, trace) in traces.enumerate() {
+        positions.entry(trace.clone()).or_insert(i);
+    }
+    positions
+}
+
+/// One `original_trace,new_trace` line per entry, for reconciliation
+impl Display for TraceMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "original_trace,new_trace")?;
+        for (old, new) in self.iter() {
+            writeln!(f, "{},{}", old, new)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_trace_map_follows_chained_changes() {
+    let mut traces = TraceMap::default();
+    traces.insert("091000010000001", "021000020000001");
+    traces.insert("091000010000002", "021000020000002");
+    let mut later = TraceMap::default();
+    later.insert("021000020000002", "021000020000001");
+    later.insert("021000020000001", "021000020000002");
+    traces.extend(later);
+
+    assert_eq!(traces.len(), 2);
+    assert_eq!(traces.get("091000010000001"), Some("021000020000002"));
+    assert_eq!(traces.get("091000010000002"), Some("021000020000001"));
+}
+
+#[test]
+fn test_bank_profile() {
+    let profile = BankProfile::new("021000021", "Backup Bank", None, None).unwrap();
+    assert_eq!(profile.immediate_dest, " 021000021");
+    assert_eq!(profile.odfi_id, "02100002");
+    assert_eq!(profile.immediate_dest_name, "BACKUP BANK");
+
+    assert!(BankProfile::new("021000022", "Backup Bank", None, None).is_err());
+    assert!(BankProfile::new("021000021", "Backup Bank", Some("12345"), None).is_err());
+}
+
+/// 220 for a batch of only credits, 225 for only debits, 200 when mixed
+pub(crate) fn service_class_code(entries: &[EntryDetail]) -> &'static str {
+    match (
+        entries.iter().any(|e| e.is_debit()),
+        entries.iter().any(|e| !e.is_debit()),
+    ) {
+        (true, false) => "225",
+        (false, true) => "220",
+        _ => "200",
+    }
+}
+
+#[test]
+fn test_achfile_rebatch() {
+    let mut ach: AchFile = include_str!("../test_data/sample.ach").parse().unwrap();
+    ach.rebatch(|_, entry| entry.is_debit().to_string(), Some("261021"))
+        .unwrap();
+
+    // The mixed ACME batch becomes a credit and a debit batch, BETA is left as it was
+    assert_eq!(ach.records.len(), 3);
+    let codes: Vec<&str> = ach
+        .records
+        .iter()
+        .map(|b| b.batch_header.service_class_code.as_str())
+        .collect();
+    assert_eq!(codes, vec!["220", "225", "225"]);
+    assert_eq!(ach.records[1].batch_header.company_id, "1234567890");
+    assert_eq!(ach.records[1].batch_header.batch_number, "0000002");
+    assert_eq!(ach.records[1].batch_header.effective_entry_date, "261021");
+    assert_eq!(ach.records[1].batch_trailer.service_class_code, "225");
+
+    let jane = &ach.records[1].batch_records[0];
+    assert_eq!(jane.trace, "091000010000003");
+    assert_eq!(jane.addenda[0].batch, "0000003");
+    assert_eq!(ach.records[2].batch_records[0].trace, "091000010000004");
+    assert_eq!(ach.trailer.batch_count, "000003");
+}
+
+#[test]
+fn test_achfile_rebatch_keeps_sec_apart() {
+    let mut ach: AchFile = include_str!("../test_data/sample.ach").parse().unwrap();
+    // BETA's batch becomes a CCD batch of ACME on the same date
+    ach.set_batch_field(1, "company_id", "1234567890").unwrap();
+    ach.set_batch_field(1, "sec", "CCD").unwrap();
+    ach.set_batch_field(1, "effective_entry_date", "261019")
+        .unwrap();
+    ach.rebatch(|_, entry| entry.is_debit().to_string(), None)
+        .unwrap();
+
+    // The PPD and CCD debits stay in batches of their own
+    let batches: Vec<(&str, &str, usize)> = ach
+        .records
+        .iter()
+        .map(|b| {
+            (
+                b.batch_header.sec.as_str(),
+                b.batch_header.service_class_code.as_str(),
+                b.batch_records.len(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        batches,
+        vec![("PPD", "220", 2), ("PPD", "225", 1), ("CCD", "225", 1)]
+    );
+    assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+}
+
+#[test]
+fn test_achfile_consolidate_batches() {
+    let mut ach: AchFile = include_str!("../test_data/sample.ach").parse().unwrap();
+    // One batch per invoice: JANE ROE's entry in a batch of its own under the same header
+    let held = ach.take_entries(&[EntryId { batch: 0, entry: 2 }]);
+    ach.records.extend(held.records);
+    ach.records.swap(1, 2);
+    ach.recompute_controls();
+    assert_eq!(ach.batch_count(), 3);
+
+    assert_eq!(ach.consolidate_batches(), 1);
+    assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+    assert_eq!(ach.batch_count(), 2);
+    assert_eq!(ach.batch_field(1, "batch_number"), Some("0000002"));
+    // Back to the batches the sample started with
+    assert_eq!(ach.to_string(), include_str!("../test_data/sample.ach"));
+
+    // A different effective date keeps a batch apart
+    let held = ach.take_entries(&[EntryId { batch: 0, entry: 0 }]);
+    ach.records.extend(held.records);
+    ach.set_batch_field(2, "effective_entry_date", "261021")
+        .unwrap();
+    assert_eq!(ach.consolidate_batches(), 0);
+    assert_eq!(ach.batch_count(), 3);
+
+    // A different company name does not, and the first batch's is kept
+    ach.set_batch_field(2, "effective_entry_date", "261019")
+        .unwrap();
+    ach.set_batch_field(2, "company_name", "ACME PAYROLL")
+        .unwrap();
+    assert_eq!(ach.consolidate_batches(), 1);
+    assert_eq!(ach.batch_field(0, "company_name"), Some("ACME CORP       "));
+    assert_eq!(ach.batch_count(), 2);
+}
+
+#[test]
+fn test_achfile_recompute_controls() {
+    let mut ach: AchFile = include_str!("../test_data/sample.ach").parse().unwrap();
+    let before = format!("{}", ach);
+
+    ach.records[0].batch_records.remove(0);
+    ach.recompute_controls();
+    assert_eq!(
+        ach.records[0].batch_trailer.entry_and_addenda_count,
+        "000003"
+    );
+    assert_eq!(
+        ach.records[0].batch_trailer.total_credit_amount,
+        "000000000000"
+    );
+    assert_eq!(ach.trailer.entry_and_addenda_count, "00000004");
+    assert_eq!(ach.trailer.entry_hash, "0017380252");
+
+    let mut ach: AchFile = before.parse().unwrap();
+    ach.recompute_controls();
+    assert_eq!(before, format!("{}", ach))
+}
+
 /*impl Default for AchFile {
     fn default() -> Self {
         AchFile {
@@ -180,7 +1208,7 @@ impl Display for AchFile {
 
         writeln!(f, "{}", self.trailer)?;
 
-        for _ in 0..(10 - (self.len() % 10)) {
+        for _ in 0..((10 - (self.len() % 10)) % 10) {
             writeln!(f, "{}", "9".repeat(94))?;
         }
         write!(f, "")
@@ -200,6 +1228,22 @@ impl TryFrom<&Path> for AchFile {
             }
         });
 
+        AchFile::try_from(&mut file)
+    }
+}
+
+impl FromStr for AchFile {
+    type Err = AchError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AchFile::try_from(&mut BufReader::new(Cursor::new(s.as_bytes())))
+    }
+}
+
+impl<R: Read + Seek> TryFrom<&mut BufReader<R>> for AchFile {
+    type Error = AchError;
+
+    fn try_from(file: &mut BufReader<R>) -> Result<Self, Self::Error> {
         let mut header = Header {
             ..Default::default()
         };
@@ -207,13 +1251,13 @@ impl TryFrom<&Path> for AchFile {
         let trailer;
 
         loop {
-            let record_type_code = checked_read_type(&mut file)?;
+            let record_type_code = checked_read_type(&mut *file)?;
 
             match record_type_code {
-                '1' => header = Header::try_from(&mut file)?,
-                '5' => records.push(CompanyBatch::try_from(&mut file)?),
+                '1' => header = Header::try_from(&mut *file)?,
+                '5' => records.push(CompanyBatch::try_from(&mut *file)?),
                 '9' => {
-                    trailer = Trailer::try_from(&mut file)?;
+                    trailer = Trailer::try_from(&mut *file)?;
                     break; // Assume end of file, break
                 }
                 t => {
@@ -243,6 +1287,187 @@ impl Field {
         self.left_justified = justification;
         self
     }
+
+    /// A zero padded numeric field. Values too wide for `size` keep their rightmost digits,
+    /// which is how NACHA truncates hash totals.
+    pub(crate) fn numeric(value: u64, size: usize) -> Self {
+        let digits = format!("{:0size$}", value, size = size);
+        Field::from(&digits[digits.len() - size..])
+    }
+
+    /// A left justified text field, padded with blanks to `size`
+    pub(crate) fn text(content: &str, size: usize) -> Self {
+        Field::from(format!("{:<size$}", content, size = size))
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        &self.content
+    }
+
+    pub(crate) fn as_u64(&self) -> Option<u64> {
+        self.content.trim().parse().ok()
+    }
+}
+
+#[test]
+fn test_entry_fields() {
+    let mut ach: AchFile = include_str!("../test_data/sample.ach").parse().unwrap();
+    let ids = ach.entry_ids();
+    assert_eq!(ach.batch_count(), 2);
+    assert_eq!(ids.len(), 4);
+    assert_eq!(ids[3], EntryId { batch: 1, entry: 0 });
+    assert_eq!(ach.batch_field(1, "company_name"), Some("BETA LLC        "));
+    assert_eq!(ach.entry_field(ids[0], "amount"), Some("0000001000"));
+    assert_eq!(ach.entry_field(ids[0], "colour"), None);
+
+    ach.set_entry_field(ids[2], "amount", "2400").unwrap();
+    assert_eq!(ach.entry_field(ids[2], "amount"), Some("0000002400"));
+    assert_eq!(ach.batches()[0].entries()[2].addenda().len(), 1);
+    ach.set_batch_field(0, "company_name", "ACME INC").unwrap();
+    assert_eq!(ach.batch_field(0, "company_name"), Some("ACME INC        "));
+    assert_eq!(
+        ach.set_entry_field(ids[0], "amount", "1.5"),
+        Err("amount '1.5' is not numeric".to_string())
+    );
+    assert_eq!(
+        ach.set_batch_field(0, "company_name", "A NAME TOO LONG FOR IT"),
+        Err("company_name 'A NAME TOO LONG FOR IT' is longer than 16 characters".to_string())
+    );
+
+    ach.remove_entries(&ids[..2]);
+    let line = ach.to_string().lines().nth(6).unwrap().to_string();
+    assert_eq!(
+        ach.insert_entry(1, &line),
+        Ok(EntryId { batch: 1, entry: 1 })
+    );
+    assert!(ach.insert_entry(1, "6 too short").is_err());
+    ach.set_entry_field(EntryId { batch: 1, entry: 1 }, "trace", "091000010000005")
+        .unwrap();
+    ach.recompute_controls();
+    assert_eq!(ach.entry_ids().len(), 3);
+    assert_eq!(ach.batch_field(1, "company_name"), Some("BETA LLC        "));
+    assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+}
+
+#[test]
+fn test_record_ids() {
+    let mut ach: AchFile = include_str!("../test_data/sample.ach").parse().unwrap();
+    let ids = ach.record_ids();
+    assert_eq!(ids.len(), ach.records().len());
+    let jane = EntryId { batch: 0, entry: 2 };
+    assert_eq!(ids[5], RecordId::Addenda(jane, 0));
+    assert_eq!(ids[10], RecordId::Trailer);
+
+    let fields = ach.record_fields(RecordId::Addenda(jane, 0)).unwrap();
+    assert_eq!(fields[2].0, "payment_related_info");
+    assert!(fields[2].1.starts_with("INVOICE 42"));
+    assert_eq!(ach.record_fields(RecordId::BatchHeader(2)), None);
+
+    ach.set_field(
+        RecordId::Addenda(jane, 0),
+        "payment_related_info",
+        "INVOICE 43",
+    )
+    .unwrap();
+    ach.set_field(RecordId::Header, "immediate_dest_name", "OTHER BANK")
+        .unwrap();
+    assert!(ach.to_string().contains("705INVOICE 43  "));
+    assert!(ach
+        .to_string()
+        .contains("094101OTHER BANK             ORIGIN CO"));
+    assert_eq!(
+        ach.set_field(RecordId::Entry(jane), "check_digit", "12"),
+        Err("check_digit '12' is longer than 1 characters".to_string())
+    );
+
+    let held = ach.take_entries(&[jane]);
+    ach.recompute_controls();
+    assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+    assert!(held.validate().is_empty(), "{:?}", held.validate());
+    assert_eq!(held.entry_ids(), vec![EntryId { batch: 0, entry: 0 }]);
+    assert!(held.to_string().contains("JANE ROE"));
+    assert!(!ach.to_string().contains("JANE ROE"));
+
+    let mut held = held;
+    held.append(ach.take_entries(&[
+        EntryId { batch: 0, entry: 0 },
+        EntryId { batch: 1, entry: 0 },
+    ]));
+    assert_eq!(held.batch_count(), 2);
+    assert_eq!(held.entry_ids().len(), 3);
+    assert!(held.validate().is_empty(), "{:?}", held.validate());
+    assert!(held.to_string().contains("0091000010000004"));
+    assert_eq!(ach.remove_empty_batches(), 1);
+    assert_eq!(ach.batch_count(), 1);
+}
+
+#[test]
+fn test_achfile_merge() {
+    let sample: AchFile = include_str!("../test_data/sample.ach").parse().unwrap();
+    let (merged, trace_map) = AchFile::merge(vec![sample.clone(), sample.clone()]).unwrap();
+    assert_eq!(merged.batch_count(), 4);
+    assert!(merged.validate().is_empty(), "{:?}", merged.validate());
+    assert_eq!(merged.batch_field(3, "batch_number"), Some("0000004"));
+    assert_eq!(trace_map.len(), 4);
+
+    let mut other = sample.clone();
+    other.header.immediate_dest = Field::from(" 021000021");
+    assert_eq!(
+        AchFile::merge(vec![sample, other]).unwrap_err(),
+        "file 2 is for immediate destination '021000021', not '091000019'"
+    );
+}
+
+#[test]
+fn test_achfile_renumber() {
+    let mut ach: AchFile = include_str!("../test_data/sample.ach").parse().unwrap();
+    // Batch numbers that skip, a trace out of order and addenda that no longer follow their entry
+    let jane = EntryId { batch: 0, entry: 2 };
+    ach.set_batch_field(1, "batch_number", "5").unwrap();
+    ach.set_field(RecordId::BatchTrailer(1), "batch_num", "5")
+        .unwrap();
+    ach.set_entry_field(jane, "trace", "091000010000009")
+        .unwrap();
+    ach.set_field(RecordId::Addenda(jane, 0), "addenda_sequence", "2")
+        .unwrap();
+    ach.set_field(RecordId::Addenda(jane, 0), "batch", "9")
+        .unwrap();
+
+    let trace_map = ach.renumber().unwrap();
+    assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+    let written = ach.to_string();
+    assert_eq!(ach.batch_field(0, "batch_number"), Some("0000001"));
+    assert_eq!(ach.batch_field(1, "batch_number"), Some("0000002"));
+    assert!(written.contains("9876543210                         091000010000002\n"));
+    assert!(written.contains("INVOICE 42"));
+    assert!(written.contains("00010000003\n"));
+    assert_eq!(
+        trace_map.to_string(),
+        "original_trace,new_trace\n091000010000009,091000010000003\n"
+    );
+
+    let trace_map = ach.renumber_from(100).unwrap();
+    assert_eq!(trace_map.len(), 4);
+    assert_eq!(trace_map.get("091000010000004"), Some("091000010000103"));
+    assert!(ach.to_string().contains("00010000102\n"));
+
+    // The last of the 4 entries takes the last sequence there is, and one further is too far
+    ach.renumber_from(9_999_996).unwrap();
+    assert!(ach.to_string().contains("091000019999999\n"));
+    let before = ach.to_string();
+    assert_eq!(
+        ach.renumber_from(9_999_997).unwrap_err(),
+        "4 entries numbered from 9999997 would run past trace sequence 9999999"
+    );
+    assert!(ach.renumber_from(u64::MAX).is_err());
+    assert_eq!(ach.to_string(), before);
+}
+
+#[test]
+fn test_field_numeric() {
+    assert_eq!(Field::numeric(42, 6), "000042");
+    assert_eq!(Field::numeric(12345678901, 10), "2345678901");
+    assert_eq!(Field::from(" 0000001000").as_u64(), Some(1000));
 }
 
 impl PartialEq<&str> for Field {
@@ -255,15 +1480,15 @@ impl Display for Field {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut out = String::new();
         if self.left_justified {
-            out.push_str(&*self.content);
-            for _ in 0..(self.size - self.content.len()) {
+            out.push_str(&self.content);
+            for _ in 0..self.size.saturating_sub(self.content.len()) {
                 out.push(' ');
             }
         } else {
-            for _ in 0..(self.size - self.content.len()) {
+            for _ in 0..self.size.saturating_sub(self.content.len()) {
                 out.push(' ');
             }
-            out.push_str(&*self.content);
+            out.push_str(&self.content);
         }
 
         write!(f, "{}", out)
@@ -338,7 +1563,26 @@ pub(crate) struct Header {
     reference_code: Field,      // content: "", size: 8
 }
 
-impl AchRecord for Header {}
+impl AchRecord for Header {
+    fn field(&self, name: &str) -> Option<&Field> {
+        match name {
+            "record_type_code" => Some(&self.record_type_code),
+            "priority_code" => Some(&self.priority_code),
+            "immediate_dest" => Some(&self.immediate_dest),
+            "immediate_orig" => Some(&self.immediate_orig),
+            "file_creation_date" => Some(&self.file_creation_date),
+            "file_creation_time" => Some(&self.file_creation_time),
+            "file_id_modifier" => Some(&self.file_id_modifier),
+            "record_size" => Some(&self.record_size),
+            "blocking_factor" => Some(&self.blocking_factor),
+            "format_code" => Some(&self.format_code),
+            "immediate_dest_name" => Some(&self.immediate_dest_name),
+            "immediate_orig_name" => Some(&self.immediate_orig_name),
+            "reference_code" => Some(&self.reference_code),
+            _ => None,
+        }
+    }
+}
 
 impl Display for Header {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -398,10 +1642,10 @@ impl From<StringReader> for Header {
     }
 }
 
-impl TryFrom<&mut BufReader<File>> for Header {
+impl<R: Read + Seek> TryFrom<&mut BufReader<R>> for Header {
     type Error = AchError;
 
-    fn try_from(file: &mut BufReader<File>) -> Result<Self, Self::Error> {
+    fn try_from(file: &mut BufReader<R>) -> Result<Self, Self::Error> {
         info!("Trying to build Header from file");
 
         Ok(Header::from(StringReader::new(checked_read_line(
@@ -425,6 +1669,100 @@ impl CompanyBatch {
         }
         len
     }
+
+    /// Start a new batch from a copy of `batch_header`, with a trailer computed from `entries`
+    pub(crate) fn from_entries(
+        batch_header: CompanyBatchHeader,
+        entries: Vec<EntryDetail>,
+    ) -> Self {
+        let mut batch = CompanyBatch {
+            batch_header,
+            batch_records: entries,
+            batch_trailer: Default::default(),
+        };
+        batch.recompute_trailer();
+        batch
+    }
+
+    pub(crate) fn header(&self) -> &CompanyBatchHeader {
+        &self.batch_header
+    }
+
+    pub(crate) fn entries(&self) -> &Vec<EntryDetail> {
+        &self.batch_records
+    }
+
+    pub(crate) fn entries_mut(&mut self) -> &mut Vec<EntryDetail> {
+        &mut self.batch_records
+    }
+
+    pub(crate) fn trailer(&self) -> &CompanyBatchTrailer {
+        &self.batch_trailer
+    }
+
+    /// The header, each entry with its addenda, and the trailer, in the order they are written
+    pub(crate) fn records(&self) -> Vec<(AchRecordType, &dyn AchRecord)> {
+        let mut records: Vec<(AchRecordType, &dyn AchRecord)> =
+            vec![(AchRecordType::CompanyBatchHeader, &self.batch_header)];
+        for entry in &self.batch_records {
+            records.extend(entry.records());
+        }
+        records.push((AchRecordType::CompanyBatchTrailer, &self.batch_trailer));
+        records
+    }
+
+    pub(crate) fn entry_and_addenda_count(&self) -> u64 {
+        (self.len() - 2) as u64
+    }
+
+    /// Sum of the [EntryDetail.receiving_dfi_id]s, truncated to the 10 digits the trailers can hold
+    pub(crate) fn entry_hash(&self) -> u64 {
+        self.batch_records
+            .iter()
+            .map(|e| e.receiving_dfi_id.as_u64().unwrap_or(0))
+            .sum::<u64>()
+            % 10_000_000_000
+    }
+
+    pub(crate) fn total_debits(&self) -> u64 {
+        self.batch_records
+            .iter()
+            .filter(|e| e.is_debit())
+            .map(|e| e.amount())
+            .sum()
+    }
+
+    pub(crate) fn total_credits(&self) -> u64 {
+        self.batch_records
+            .iter()
+            .filter(|e| !e.is_debit())
+            .map(|e| e.amount())
+            .sum()
+    }
+
+    /// Rebuild the [CompanyBatchTrailer] from the batch header and entries.
+    /// The message authentication code is carried over, since it cannot be derived.
+    pub(crate) fn recompute_trailer(&mut self) {
+        let message_auth_code = if self.batch_trailer.message_auth_code.size == 19 {
+            self.batch_trailer.message_auth_code.clone()
+        } else {
+            Field::from(19)
+        };
+
+        self.batch_trailer = CompanyBatchTrailer {
+            record_type_code: Field::from("8"),
+            service_class_code: self.batch_header.service_class_code.clone(),
+            entry_and_addenda_count: Field::numeric(self.entry_and_addenda_count(), 6),
+            entry_hash: Field::numeric(self.entry_hash(), 10),
+            total_debit_amount: Field::numeric(self.total_debits(), 12),
+            total_credit_amount: Field::numeric(self.total_credits(), 12),
+            company_id: self.batch_header.company_id.clone(),
+            message_auth_code,
+            reserved: Field::from(6),
+            originating_dfi_id_num: self.batch_header.odfi_id.clone(),
+            batch_num: self.batch_header.batch_number.clone(),
+        };
+    }
 }
 
 impl Display for CompanyBatch {
@@ -437,10 +1775,10 @@ impl Display for CompanyBatch {
     }
 }
 
-impl TryFrom<&mut BufReader<File>> for CompanyBatch {
+impl<R: Read + Seek> TryFrom<&mut BufReader<R>> for CompanyBatch {
     type Error = AchError;
 
-    fn try_from(file: &mut BufReader<File>) -> Result<Self, Self::Error> {
+    fn try_from(file: &mut BufReader<R>) -> Result<Self, Self::Error> {
         info!("Trying to build CompanyBatch from file");
 
         let line = checked_read_line(&mut *file)?;
@@ -489,7 +1827,26 @@ pub(crate) struct CompanyBatchHeader {
     batch_number: Field,               // size: 7
 }
 
-impl AchRecord for CompanyBatchHeader {}
+impl AchRecord for CompanyBatchHeader {
+    fn field(&self, name: &str) -> Option<&Field> {
+        match name {
+            "record_type_code" => Some(&self.record_type_code),
+            "service_class_code" => Some(&self.service_class_code),
+            "company_name" => Some(&self.company_name),
+            "company_discretionary_data" => Some(&self.company_discretionary_data),
+            "company_id" => Some(&self.company_id),
+            "sec" => Some(&self.sec),
+            "entry_desc" => Some(&self.entry_desc),
+            "company_descriptive_date" => Some(&self.company_descriptive_date),
+            "effective_entry_date" => Some(&self.effective_entry_date),
+            "settlement_date" => Some(&self.settlement_date),
+            "originator_status_code" => Some(&self.originator_status_code),
+            "odfi_id" => Some(&self.odfi_id),
+            "batch_number" => Some(&self.batch_number),
+            _ => None,
+        }
+    }
+}
 
 impl Display for CompanyBatchHeader {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -546,7 +1903,24 @@ pub(crate) struct EntryDetail {
     addenda: Vec<Addenda>,
 }
 
-impl AchRecord for EntryDetail {}
+impl AchRecord for EntryDetail {
+    fn field(&self, name: &str) -> Option<&Field> {
+        match name {
+            "record_type_code" => Some(&self.record_type_code),
+            "transactions_code" => Some(&self.transactions_code),
+            "receiving_dfi_id" => Some(&self.receiving_dfi_id),
+            "check_digit" => Some(&self.check_digit),
+            "dfi_account" => Some(&self.dfi_account),
+            "amount" => Some(&self.amount),
+            "individual_id" => Some(&self.individual_id),
+            "individual_name" => Some(&self.individual_name),
+            "discretionary_data" => Some(&self.discretionary_data),
+            "addenda_indicator" => Some(&self.addenda_indicator),
+            "trace" => Some(&self.trace),
+            _ => None,
+        }
+    }
+}
 
 impl EntryDetail {
     fn len(&self) -> usize {
@@ -556,6 +1930,37 @@ impl EntryDetail {
         }
         len
     }
+
+    /// Transaction codes ending in 5-9 debit the receiver; 0-4 are credits.
+    pub(crate) fn is_debit(&self) -> bool {
+        matches!(
+            self.transactions_code.as_str().chars().nth(1),
+            Some('5'..='9')
+        )
+    }
+
+    pub(crate) fn amount(&self) -> u64 {
+        self.amount.as_u64().unwrap_or(0)
+    }
+
+    pub(crate) fn addenda(&self) -> &Vec<Addenda> {
+        &self.addenda
+    }
+
+    /// This entry followed by its addenda
+    pub(crate) fn records(&self) -> Vec<(AchRecordType, &dyn AchRecord)> {
+        let mut records: Vec<(AchRecordType, &dyn AchRecord)> =
+            vec![(AchRecordType::EntryDetail, self)];
+        for addenda in &self.addenda {
+            records.push((AchRecordType::Addenda, addenda));
+        }
+        records
+    }
+
+    #[cfg(feature = "scripting")]
+    pub(crate) fn addenda_mut(&mut self) -> &mut Vec<Addenda> {
+        &mut self.addenda
+    }
 }
 
 impl Display for EntryDetail {
@@ -609,10 +2014,10 @@ impl From<StringReader> for EntryDetail {
     }
 }
 
-impl TryFrom<&mut BufReader<File>> for EntryDetail {
+impl<R: Read + Seek> TryFrom<&mut BufReader<R>> for EntryDetail {
     type Error = AchError;
 
-    fn try_from(file: &mut BufReader<File>) -> Result<Self, Self::Error> {
+    fn try_from(file: &mut BufReader<R>) -> Result<Self, Self::Error> {
         info!("Trying to build EntryDetail from file");
 
         let line = checked_read_line(&mut *file)?;
@@ -645,7 +2050,18 @@ pub(crate) struct Addenda {
     batch: Field,                // size: 7
 }
 
-impl AchRecord for Addenda {}
+impl AchRecord for Addenda {
+    fn field(&self, name: &str) -> Option<&Field> {
+        match name {
+            "record_type_code" => Some(&self.record_type_code),
+            "addenda_type" => Some(&self.addenda_type),
+            "payment_related_info" => Some(&self.payment_related_info),
+            "addenda_sequence" => Some(&self.addenda_sequence),
+            "batch" => Some(&self.batch),
+            _ => None,
+        }
+    }
+}
 
 impl Display for Addenda {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -669,10 +2085,10 @@ impl From<StringReader> for Addenda {
     }
 }
 
-impl TryFrom<&mut BufReader<File>> for Addenda {
+impl<R: Read + Seek> TryFrom<&mut BufReader<R>> for Addenda {
     type Error = AchError;
 
-    fn try_from(file: &mut BufReader<File>) -> Result<Self, Self::Error> {
+    fn try_from(file: &mut BufReader<R>) -> Result<Self, Self::Error> {
         info!("Trying to build Addenda from file");
 
         Ok(Addenda::from(StringReader::new(checked_read_line(
@@ -696,7 +2112,24 @@ pub(crate) struct CompanyBatchTrailer {
     batch_num: Field,           // size: 7
 }
 
-impl AchRecord for CompanyBatchTrailer {}
+impl AchRecord for CompanyBatchTrailer {
+    fn field(&self, name: &str) -> Option<&Field> {
+        match name {
+            "record_type_code" => Some(&self.record_type_code),
+            "service_class_code" => Some(&self.service_class_code),
+            "entry_and_addenda_count" => Some(&self.entry_and_addenda_count),
+            "entry_hash" => Some(&self.entry_hash),
+            "total_debit_amount" => Some(&self.total_debit_amount),
+            "total_credit_amount" => Some(&self.total_credit_amount),
+            "company_id" => Some(&self.company_id),
+            "message_auth_code" => Some(&self.message_auth_code),
+            "reserved" => Some(&self.reserved),
+            "originating_dfi_id_num" => Some(&self.originating_dfi_id_num),
+            "batch_num" => Some(&self.batch_num),
+            _ => None,
+        }
+    }
+}
 
 impl Display for CompanyBatchTrailer {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -732,10 +2165,10 @@ impl From<StringReader> for CompanyBatchTrailer {
     }
 }
 
-impl TryFrom<&mut BufReader<File>> for CompanyBatchTrailer {
+impl<R: Read + Seek> TryFrom<&mut BufReader<R>> for CompanyBatchTrailer {
     type Error = AchError;
 
-    fn try_from(file: &mut BufReader<File>) -> Result<Self, Self::Error> {
+    fn try_from(file: &mut BufReader<R>) -> Result<Self, Self::Error> {
         info!("Trying to build CompanyBatchTrailer from file");
 
         Ok(CompanyBatchTrailer::from(StringReader::new(
@@ -756,7 +2189,21 @@ pub(crate) struct Trailer {
     reserved: Field,                // size: 39
 }
 
-impl AchRecord for Trailer {}
+impl AchRecord for Trailer {
+    fn field(&self, name: &str) -> Option<&Field> {
+        match name {
+            "record_type_code" => Some(&self.record_type_code),
+            "batch_count" => Some(&self.batch_count),
+            "block_count" => Some(&self.block_count),
+            "entry_and_addenda_count" => Some(&self.entry_and_addenda_count),
+            "entry_hash" => Some(&self.entry_hash),
+            "total_debits" => Some(&self.total_debits),
+            "total_credits" => Some(&self.total_credits),
+            "reserved" => Some(&self.reserved),
+            _ => None,
+        }
+    }
+}
 
 impl Display for Trailer {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -786,10 +2233,10 @@ impl From<StringReader> for Trailer {
     }
 }
 
-impl TryFrom<&mut BufReader<File>> for Trailer {
+impl<R: Read + Seek> TryFrom<&mut BufReader<R>> for Trailer {
     type Error = AchError;
 
-    fn try_from(file: &mut BufReader<File>) -> Result<Self, Self::Error> {
+    fn try_from(file: &mut BufReader<R>) -> Result<Self, Self::Error> {
         info!("Trying to build Trailer from file");
 
         Ok(Trailer::from(StringReader::new(checked_read_line(
@@ -0,0 +1,206 @@
+use crate::ach_file::{
+    is_routing_number, routing_check_digit, AchFile, AchRecordType, BankProfile,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What [generate] builds a file from. The defaults make a valid file dated today, sent
+/// through the bank of the sample files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenerateOptions {
+    pub bank: BankProfile,
+    pub immediate_orig_name: String,
+    pub company_name: String,
+    pub company_id: String,
+    pub batches: usize,
+    /// Entries in each batch
+    pub entries: usize,
+    /// YYMMDD, which is also used for the effective entry date
+    pub file_creation_date: String,
+    /// HHMM
+    pub file_creation_time: String,
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        let (date, time) = now();
+        GenerateOptions {
+            bank: BankProfile::new("091000019", "DEST BANK", Some("123456789"), None).unwrap(),
+            immediate_orig_name: "ORIGIN CO".to_string(),
+            company_name: "ACME CORP".to_string(),
+            company_id: "1234567890".to_string(),
+            batches: 1,
+            entries: 10,
+            file_creation_date: date,
+            file_creation_time: time,
+        }
+    }
+}
+
+/// Banks receiving the generated entries, in turn
+const RDFIS: &[&str] = &["07640125", "02100002", "01100001"];
+
+/// The line of a `record_type` record with each of `values` in its field, and every other field
+/// but its record type code blank or zero
+fn record(record_type: AchRecordType, values: &[(&str, &str)]) -> Result<String, String> {
+    record_type
+        .layout()
+        .iter()
+        .map(
+            |spec| match values.iter().find(|(name, _)| *name == spec.name) {
+                Some((_, value)) => spec.format(value),
+                None if spec.name == "record_type_code" => Ok(record_type.code().to_string()),
+                None => spec.format(""),
+            },
+        )
+        .collect()
+}
+
+/// A file of `options.batches` PPD credit batches with `options.entries` entries each, for
+/// testing whatever the file is fed to. Amounts, accounts and names are made up but the same on
+/// every run, and the controls add up.
+pub fn generate(options: &GenerateOptions) -> Result<AchFile, String> {
+    let bank = &options.bank;
+    if !is_routing_number(bank.immediate_dest.trim()) {
+        return Err(format!(
+            "immediate_dest '{}' is not a valid routing number",
+            bank.immediate_dest
+        ));
+    }
+    let immediate_orig = bank
+        .immediate_orig
+        .as_deref()
+        .unwrap_or(&bank.immediate_dest);
+    let date = options.file_creation_date.as_str();
+
+    let mut lines = vec![record(
+        AchRecordType::Header,
+        &[
+            ("priority_code", "1"),
+            ("immediate_dest", &bank.immediate_dest),
+            ("immediate_orig", immediate_orig),
+            ("file_creation_date", date),
+            ("file_creation_time", &options.file_creation_time),
+            ("file_id_modifier", "A"),
+            ("record_size", "94"),
+            ("blocking_factor", "10"),
+            ("format_code", "1"),
+            ("immediate_dest_name", &bank.immediate_dest_name),
+            ("immediate_orig_name", &options.immediate_orig_name),
+        ],
+    )?];
+
+    let mut sequence = 0;
+    for b in 1..=options.batches {
+        lines.push(record(
+            AchRecordType::CompanyBatchHeader,
+            &[
+                ("service_class_code", "220"),
+                ("company_name", &options.company_name),
+                ("company_id", &options.company_id),
+                ("sec", "PPD"),
+                ("entry_desc", "PAYROLL"),
+                ("effective_entry_date", date),
+                ("originator_status_code", "1"),
+                ("odfi_id", &bank.odfi_id),
+                ("batch_number", &b.to_string()),
+            ],
+        )?);
+
+        for _ in 0..options.entries {
+            sequence += 1;
+            let rdfi = RDFIS[sequence % RDFIS.len()];
+            lines.push(record(
+                AchRecordType::EntryDetail,
+                &[
+                    ("transactions_code", "22"),
+                    ("receiving_dfi_id", rdfi),
+                    (
+                        "check_digit",
+                        &routing_check_digit(rdfi).unwrap().to_string(),
+                    ),
+                    ("dfi_account", &format!("{:09}", sequence * 7919)),
+                    ("amount", &(sequence * 1234 % 100_000 + 100).to_string()),
+                    ("individual_id", &format!("GEN{:06}", sequence)),
+                    ("individual_name", &format!("TEST RECEIVER {}", sequence)),
+                    ("addenda_indicator", "0"),
+                    ("trace", &format!("{}{:07}", bank.odfi_id, sequence)),
+                ],
+            )?);
+        }
+        lines.push(record(AchRecordType::CompanyBatchTrailer, &[])?);
+    }
+    lines.push(record(AchRecordType::Trailer, &[])?);
+
+    let mut ach_file: AchFile = (lines.join("\n") + "\n")
+        .parse()
+        .map_err(|_| "generated records do not make up an ACH file".to_string())?;
+    ach_file.recompute_controls();
+    Ok(ach_file)
+}
+
+/// The current UTC date as YYMMDD and time as HHMM
+pub(crate) fn now() -> (String, String) {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    (
+        format!("{:02}{:02}{:02}", year % 100, month, day),
+        format!("{:02}{:02}", of_day / 3600, of_day % 3600 / 60),
+    )
+}
+
+#[cfg(test)]
+mod ach_generate_tests {
+    use crate::ach_generate::{generate, GenerateOptions};
+
+    #[test]
+    fn test_generate() {
+        let options = GenerateOptions {
+            batches: 2,
+            entries: 3,
+            file_creation_date: "261018".to_string(),
+            file_creation_time: "1200".to_string(),
+            ..Default::default()
+        };
+        let ach = generate(&options).unwrap();
+        assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+        assert_eq!(ach.batch_count(), 2);
+        assert_eq!(ach.entry_ids().len(), 6);
+        assert_eq!(ach.to_string(), generate(&options).unwrap().to_string());
+        assert!(ach
+            .to_string()
+            .starts_with("101 091000019 1234567892610181200A094101DEST BANK"));
+
+        let options = GenerateOptions {
+            company_name: "A COMPANY NAME TOO LONG".to_string(),
+            ..options
+        };
+        assert_eq!(
+            generate(&options).unwrap_err(),
+            "company_name 'A COMPANY NAME TOO LONG' is longer than 16 characters"
+        );
+    }
+
+    #[test]
+    fn test_default_date() {
+        let options = GenerateOptions::default();
+        assert_eq!(options.file_creation_date.len(), 6);
+        assert_eq!(options.file_creation_time.len(), 4);
+        assert!(generate(&options).unwrap().validate().is_empty());
+    }
+}
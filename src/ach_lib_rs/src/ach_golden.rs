@@ -0,0 +1,474 @@
+use crate::ach_diff::paired_entries;
+use crate::ach_file::{record_line, AchFile, AchRecord, AchRecordType, CompanyBatch};
+use crate::ach_transformations::Transformations;
+use log::{error, info};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// One field that differs between an expected and an actual file. A record only one of the
+/// files has is reported with the field `record` and the whole line.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldMismatch {
+    /// 1-based line of the record, ignoring filler
+    pub line: usize,
+    pub record_type: AchRecordType,
+    pub field: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Display for FieldMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}: {:?}.{}: expected '{}', got '{}'",
+            self.line, self.record_type, self.field, self.expected, self.actual
+        )
+    }
+}
+
+/// Compare `actual` with `expected` field by field. The file headers and trailers are
+/// compared with each other, batches and entries are paired as [AchFile::diff] pairs them, and
+/// an entry's addenda are compared in order. Lines are those of `expected`, or of `actual` for
+/// a record only it has.
+pub fn compare(expected: &AchFile, actual: &AchFile) -> Vec<FieldMismatch> {
+    let mut mismatches = vec![];
+    compare_fields(
+        &mut mismatches,
+        1,
+        AchRecordType::Header,
+        expected.header(),
+        actual.header(),
+    );
+
+    let trailer_line = expected.records().len();
+    let first_lines = (batch_lines(expected), batch_lines(actual));
+    for pair in expected.paired_batches(actual) {
+        let (e, a) = match pair {
+            (Some(e), Some(a)) => (e, a),
+            (e, a) => {
+                let (batch, line) = match e {
+                    Some(e) => (&expected.batches()[e], first_lines.0[e]),
+                    None => (&actual.batches()[a.unwrap()], first_lines.1[a.unwrap()]),
+                };
+                unmatched(&mut mismatches, line, batch.records(), e.is_some());
+                continue;
+            }
+        };
+        let (expected, actual) = (&expected.batches()[e], &actual.batches()[a]);
+        let line = first_lines.0[e];
+        compare_fields(
+            &mut mismatches,
+            line,
+            AchRecordType::CompanyBatchHeader,
+            expected.header(),
+            actual.header(),
+        );
+
+        let entry_lines = (
+            entry_lines(expected, line),
+            entry_lines(actual, first_lines.1[a]),
+        );
+        for pair in paired_entries(expected, actual) {
+            let (e, a) = match pair {
+                (Some(e), Some(a)) => (e, a),
+                (e, a) => {
+                    let (entry, line) = match e {
+                        Some(e) => (&expected.entries()[e], entry_lines.0[e]),
+                        None => (&actual.entries()[a.unwrap()], entry_lines.1[a.unwrap()]),
+                    };
+                    unmatched(&mut mismatches, line, entry.records(), e.is_some());
+                    continue;
+                }
+            };
+            // The entry, then its addenda in order
+            let lines = (entry_lines.0[e], entry_lines.1[a]);
+            let records = (
+                expected.entries()[e].records(),
+                actual.entries()[a].records(),
+            );
+            for i in 0..records.0.len().max(records.1.len()) {
+                match (records.0.get(i), records.1.get(i)) {
+                    (Some((record_type, expected)), Some((_, actual))) => compare_fields(
+                        &mut mismatches,
+                        lines.0 + i,
+                        *record_type,
+                        *expected,
+                        *actual,
+                    ),
+                    (Some(only), None) => {
+                        unmatched(&mut mismatches, lines.0 + i, vec![*only], true)
+                    }
+                    (None, Some(only)) => {
+                        unmatched(&mut mismatches, lines.1 + i, vec![*only], false)
+                    }
+                    (None, None) => {}
+                }
+            }
+        }
+
+        compare_fields(
+            &mut mismatches,
+            line + expected.records().len() - 1,
+            AchRecordType::CompanyBatchTrailer,
+            expected.trailer(),
+            actual.trailer(),
+        );
+    }
+
+    compare_fields(
+        &mut mismatches,
+        trailer_line,
+        AchRecordType::Trailer,
+        expected.trailer(),
+        actual.trailer(),
+    );
+    mismatches
+}
+
+/// Each field that differs between two records of `record_type`
+fn compare_fields(
+    mismatches: &mut Vec<FieldMismatch>,
+    line: usize,
+    record_type: AchRecordType,
+    expected: &dyn AchRecord,
+    actual: &dyn AchRecord,
+) {
+    for spec in record_type.layout() {
+        let (expected, actual) = match (expected.field(spec.name), actual.field(spec.name)) {
+            (Some(expected), Some(actual)) => (expected.to_string(), actual.to_string()),
+            _ => continue,
+        };
+        if expected != actual {
+            mismatches.push(FieldMismatch {
+                line,
+                record_type,
+                field: spec.name,
+                expected,
+                actual,
+            });
+        }
+    }
+}
+
+/// `records` from line `line` on, which only the expected file has, or only the actual one
+fn unmatched(
+    mismatches: &mut Vec<FieldMismatch>,
+    line: usize,
+    records: Vec<(AchRecordType, &dyn AchRecord)>,
+    expected: bool,
+) {
+    for (i, (record_type, record)) in records.into_iter().enumerate() {
+        let whole = record_line(record_type, record);
+        let (expected, actual) = match expected {
+            true => (whole, String::new()),
+            false => (String::new(), whole),
+        };
+        mismatches.push(FieldMismatch {
+            line: line + i,
+            record_type,
+            field: "record",
+            expected,
+            actual,
+        });
+    }
+}
+
+/// Line of the header of each batch of `file`
+fn batch_lines(file: &AchFile) -> Vec<usize> {
+    let mut line = 2;
+    file.batches()
+        .iter()
+        .map(|batch| {
+            line += batch.records().len();
+            line - batch.records().len()
+        })
+        .collect()
+}
+
+/// Line of each entry of `batch`, whose header is on line `header`
+fn entry_lines(batch: &CompanyBatch, header: usize) -> Vec<usize> {
+    let mut line = header + 1;
+    batch
+        .entries()
+        .iter()
+        .map(|entry| {
+            line += entry.records().len();
+            line - entry.records().len()
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoldenResult {
+    Passed,
+    Failed(Vec<FieldMismatch>),
+    /// The expected output was (re)written from the actual output
+    Blessed,
+    /// There is no expected output to compare with
+    Missing,
+    /// The input or expected output could not be read, or the transformations failed
+    Error(String),
+}
+
+/// One sample input, and how its output compared with what was expected
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenCase {
+    pub input: PathBuf,
+    pub expected: PathBuf,
+    pub result: GoldenResult,
+}
+
+impl GoldenCase {
+    pub fn passed(&self) -> bool {
+        matches!(self.result, GoldenResult::Passed | GoldenResult::Blessed)
+    }
+}
+
+/// Summary line, followed by one indented line per mismatch
+impl Display for GoldenCase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let input = self.input.display();
+        match &self.result {
+            GoldenResult::Passed => write!(f, "PASS {}", input),
+            GoldenResult::Blessed => write!(f, "BLESS {} -> {}", input, self.expected.display()),
+            GoldenResult::Missing => write!(
+                f,
+                "FAIL {}: no expected output {}, run with --bless to create it",
+                input,
+                self.expected.display()
+            ),
+            GoldenResult::Error(e) => write!(f, "FAIL {}: {}", input, e),
+            GoldenResult::Failed(mismatches) => {
+                write!(f, "FAIL {}", input)?;
+                for mismatch in mismatches {
+                    write!(f, "\n    {}", mismatch)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Expected output of `input`, the file beside it named `<stem>.expected.<extension>`
+pub fn expected_path(input: &Path) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    match input.extension() {
+        Some(extension) => {
+            input.with_file_name(format!("{}.expected.{}", stem, extension.to_string_lossy()))
+        }
+        None => input.with_file_name(format!("{}.expected", stem)),
+    }
+}
+
+/// Run `transformations` over every `.ach` file in `dir` (other than expected outputs) and
+/// compare each output with its expected output. With `bless` the expected outputs are
+/// written from the actual outputs instead.
+pub fn run_golden(
+    transformations: &Transformations,
+    dir: &Path,
+    bless: bool,
+) -> io::Result<Vec<GoldenCase>> {
+    let mut inputs = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.extension().is_some_and(|e| e == "ach") && !name.ends_with(".expected.ach") {
+            inputs.push(path);
+        }
+    }
+    inputs.sort();
+    if inputs.is_empty() {
+        error!("No .ach files to test in {}", dir.display());
+    }
+
+    Ok(inputs
+        .into_iter()
+        .map(|input| {
+            let expected = expected_path(&input);
+            let result = run_case(transformations, &input, &expected, bless);
+            info!("{:?}: {:?}", input, result);
+            GoldenCase {
+                input,
+                expected,
+                result,
+            }
+        })
+        .collect())
+}
+
+fn run_case(
+    transformations: &Transformations,
+    input: &Path,
+    expected: &Path,
+    bless: bool,
+) -> GoldenResult {
+    let mut actual = match AchFile::try_from(input) {
+        Ok(ach) => ach,
+        Err(e) => return GoldenResult::Error(format!("could not read input: {}", e)),
+    };
+    if let Err(e) = transformations.apply(&mut actual) {
+        return GoldenResult::Error(e.to_string());
+    }
+
+    if bless {
+        return match fs::write(expected, actual.to_string()) {
+            Ok(_) => GoldenResult::Blessed,
+            Err(e) => GoldenResult::Error(format!("could not write expected output: {}", e)),
+        };
+    }
+    if !expected.exists() {
+        return GoldenResult::Missing;
+    }
+    let expected = match AchFile::try_from(expected) {
+        Ok(ach) => ach,
+        Err(e) => return GoldenResult::Error(format!("could not read expected output: {}", e)),
+    };
+
+    let mismatches = compare(&expected, &actual);
+    if mismatches.is_empty() {
+        GoldenResult::Passed
+    } else {
+        GoldenResult::Failed(mismatches)
+    }
+}
+
+#[cfg(test)]
+mod ach_golden_tests {
+    use crate::ach_file::{AchFile, AchRecordType, EntryId};
+    use crate::ach_golden::{compare, run_golden, GoldenResult};
+    use crate::ach_transformations::Transformations;
+    use std::fs;
+    use std::path::Path;
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    fn drop_zero_dollar() -> Transformations {
+        Transformations::try_from(Path::new("test_data/cfg/drop_zero_dollar.yml")).unwrap()
+    }
+
+    #[test]
+    fn test_compare() {
+        let expected: AchFile = SAMPLE.parse().unwrap();
+        let actual: AchFile = SAMPLE
+            .replace("0000001000EMP001", "0000001001EMP001")
+            .parse()
+            .unwrap();
+        assert_eq!(compare(&expected, &expected), vec![]);
+
+        let mismatches = compare(&expected, &actual);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].line, 3);
+        assert_eq!(mismatches[0].field, "amount");
+        assert_eq!(
+            mismatches[0].to_string(),
+            "line 3: EntryDetail.amount: expected '0000001000', got '0000001001'"
+        );
+
+        let mut shorter = expected.clone();
+        shorter.batches_mut().pop();
+        shorter.recompute_controls();
+        let mismatches = compare(&expected, &shorter);
+        let found: Vec<_> = mismatches
+            .iter()
+            .map(|m| (m.line, m.record_type, m.field))
+            .collect();
+        assert_eq!(
+            found[..3],
+            [
+                (8, AchRecordType::CompanyBatchHeader, "record"),
+                (9, AchRecordType::EntryDetail, "record"),
+                (10, AchRecordType::CompanyBatchTrailer, "record"),
+            ]
+        );
+        assert!(mismatches[0].actual.is_empty());
+        assert_eq!(found[3], (11, AchRecordType::Trailer, "batch_count"));
+    }
+
+    #[test]
+    fn test_compare_pairs_entries_by_trace() {
+        // Without ZERO DOLLAR TEST, JANE ROE is a line earlier but still matches
+        let expected: AchFile = SAMPLE.parse().unwrap();
+        let mut actual = expected.clone();
+        actual.remove_entries(&[EntryId { batch: 0, entry: 1 }]);
+        actual.recompute_controls();
+
+        let found: Vec<_> = compare(&expected, &actual)
+            .into_iter()
+            .map(|m| (m.line, m.record_type, m.field))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (4, AchRecordType::EntryDetail, "record"),
+                (
+                    7,
+                    AchRecordType::CompanyBatchTrailer,
+                    "entry_and_addenda_count"
+                ),
+                (7, AchRecordType::CompanyBatchTrailer, "entry_hash"),
+                (11, AchRecordType::Trailer, "block_count"),
+                (11, AchRecordType::Trailer, "entry_and_addenda_count"),
+                (11, AchRecordType::Trailer, "entry_hash"),
+            ]
+        );
+
+        // A field that changed is reported on the expected file's line
+        let mut actual = expected.clone();
+        actual.remove_entries(&[EntryId { batch: 0, entry: 1 }]);
+        let jane = EntryId { batch: 0, entry: 1 };
+        actual
+            .set_entry_field(jane, "individual_name", "JANE DOE")
+            .unwrap();
+        let mismatches = compare(&expected, &actual);
+        assert_eq!(
+            (mismatches[1].line, mismatches[1].field),
+            (5, "individual_name")
+        );
+    }
+
+    #[test]
+    fn test_run_golden() {
+        let cases = run_golden(&drop_zero_dollar(), Path::new("test_data/golden"), false).unwrap();
+        assert_eq!(cases.len(), 1);
+        assert_eq!(cases[0].result, GoldenResult::Passed);
+
+        let dir = std::env::temp_dir().join(format!("ach_rs_golden_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("payroll.ach"), SAMPLE).unwrap();
+
+        let cases = run_golden(&drop_zero_dollar(), &dir, false).unwrap();
+        assert_eq!(cases[0].result, GoldenResult::Missing);
+        assert!(!cases[0].passed());
+
+        let cases = run_golden(&drop_zero_dollar(), &dir, true).unwrap();
+        assert_eq!(cases[0].result, GoldenResult::Blessed);
+        assert_eq!(
+            fs::read_to_string(dir.join("payroll.expected.ach")).unwrap(),
+            fs::read_to_string("test_data/golden/payroll.expected.ach").unwrap()
+        );
+
+        // A config change that also drops JANE ROE shows up record by record
+        let stricter = Transformations::try_from(vec![
+            "drop:".to_string(),
+            "    operation: drop".to_string(),
+            "    on: EntryDetail".to_string(),
+            "    where: amount < 3000".to_string(),
+        ])
+        .unwrap();
+        let cases = run_golden(&stricter, &dir, false).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        match &cases[0].result {
+            GoldenResult::Failed(mismatches) => {
+                // ACME CORP's batch is gone entirely
+                assert_eq!(mismatches[0].line, 2);
+                assert_eq!(mismatches[0].record_type, AchRecordType::CompanyBatchHeader);
+            }
+            result => panic!("expected a failure, got {:?}", result),
+        }
+        assert!(cases[0].to_string().starts_with("FAIL "));
+    }
+}
@@ -0,0 +1,218 @@
+use crate::ach_file::{AchFile, AchRecord, AchRecordType};
+use serde_json::{Map, Value};
+
+/// Each field of `record` by name, as written, in the order of its layout
+fn record_json(record_type: AchRecordType, record: &dyn AchRecord) -> Map<String, Value> {
+    record_type
+        .layout()
+        .iter()
+        .filter_map(|spec| {
+            Some((
+                spec.name.to_string(),
+                record.field(spec.name)?.to_string().into(),
+            ))
+        })
+        .collect()
+}
+
+/// The line `record` is written as. Values as wide as their field are taken as written, so
+/// padding survives a round trip, anything shorter is padded as the field requires. The record
+/// type code can be left out.
+fn record_line(record_type: AchRecordType, record: &Value, at: &str) -> Result<String, String> {
+    let record = record
+        .as_object()
+        .ok_or_else(|| format!("{} is not an object", at))?;
+    for name in record.keys() {
+        if name != "addenda" && !record_type.has_field(name) {
+            return Err(format!("{} has unknown field '{}'", at, name));
+        }
+    }
+
+    let mut line = String::new();
+    for spec in record_type.layout() {
+        let value = match record.get(spec.name) {
+            None if spec.name == "record_type_code" => record_type.code(),
+            None => "",
+            Some(Value::String(value)) => value.as_str(),
+            Some(_) => return Err(format!("{}.{} is not a string", at, spec.name)),
+        };
+        if value.len() == spec.size {
+            line.push_str(value);
+        } else {
+            line.push_str(&spec.format(value).map_err(|e| format!("{}.{}", at, e))?);
+        }
+    }
+    Ok(line)
+}
+
+fn array<'a>(value: Option<&'a Value>, at: &str) -> Result<&'a [Value], String> {
+    match value {
+        None => Ok(&[]),
+        Some(Value::Array(values)) => Ok(values),
+        Some(_) => Err(format!("{} is not an array", at)),
+    }
+}
+
+impl AchFile {
+    /// The file as JSON: the header, each batch with its header, entries (each with its
+    /// addenda) and trailer, then the file trailer. Every field is a string as written, padding
+    /// included.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.to_json_value()).unwrap()
+    }
+
+    /// [AchFile::to_json] before it is written out
+    pub(crate) fn to_json_value(&self) -> Value {
+        let batches: Vec<Value> = self
+            .batches()
+            .iter()
+            .map(|batch| {
+                let entries: Vec<Value> = batch
+                    .entries()
+                    .iter()
+                    .map(|entry| {
+                        let mut json = record_json(AchRecordType::EntryDetail, entry);
+                        let addenda = entry
+                            .addenda()
+                            .iter()
+                            .map(|a| record_json(AchRecordType::Addenda, a).into())
+                            .collect();
+                        json.insert("addenda".to_string(), Value::Array(addenda));
+                        json.into()
+                    })
+                    .collect();
+
+                let mut json = Map::new();
+                json.insert(
+                    "header".to_string(),
+                    record_json(AchRecordType::CompanyBatchHeader, batch.header()).into(),
+                );
+                json.insert("entries".to_string(), Value::Array(entries));
+                json.insert(
+                    "trailer".to_string(),
+                    record_json(AchRecordType::CompanyBatchTrailer, batch.trailer()).into(),
+                );
+                json.into()
+            })
+            .collect();
+
+        let mut json = Map::new();
+        json.insert(
+            "header".to_string(),
+            record_json(AchRecordType::Header, self.header()).into(),
+        );
+        json.insert("batches".to_string(), Value::Array(batches));
+        json.insert(
+            "trailer".to_string(),
+            record_json(AchRecordType::Trailer, self.trailer()).into(),
+        );
+        Value::Object(json)
+    }
+
+    /// Read a file written by [AchFile::to_json]. Fields left out are blank or zero. Trailers
+    /// are taken as given, unless any of them is left out, in which case every control is
+    /// recomputed.
+    pub fn from_json(json: &str) -> Result<AchFile, String> {
+        let json: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let mut lines = vec![record_line(
+            AchRecordType::Header,
+            json.get("header").ok_or("file has no header")?,
+            "header",
+        )?];
+        let mut recompute = false;
+
+        for (b, batch) in array(json.get("batches"), "batches")?.iter().enumerate() {
+            let at = format!("batch {}", b + 1);
+            let header = batch
+                .get("header")
+                .ok_or_else(|| format!("{} has no header", at))?;
+            lines.push(record_line(AchRecordType::CompanyBatchHeader, header, &at)?);
+
+            for (e, entry) in array(batch.get("entries"), &at)?.iter().enumerate() {
+                let at = format!("{} entry {}", at, e + 1);
+                lines.push(record_line(AchRecordType::EntryDetail, entry, &at)?);
+                for (a, addenda) in array(entry.get("addenda"), &at)?.iter().enumerate() {
+                    let at = format!("{} addenda {}", at, a + 1);
+                    lines.push(record_line(AchRecordType::Addenda, addenda, &at)?);
+                }
+            }
+
+            match batch.get("trailer") {
+                Some(trailer) => lines.push(record_line(
+                    AchRecordType::CompanyBatchTrailer,
+                    trailer,
+                    &at,
+                )?),
+                None => {
+                    recompute = true;
+                    lines.push(record_line(
+                        AchRecordType::CompanyBatchTrailer,
+                        &Value::Object(Map::new()),
+                        &at,
+                    )?)
+                }
+            }
+        }
+
+        let trailer = json.get("trailer");
+        recompute |= trailer.is_none();
+        let empty = Value::Object(Map::new());
+        lines.push(record_line(
+            AchRecordType::Trailer,
+            trailer.unwrap_or(&empty),
+            "trailer",
+        )?);
+
+        let mut ach_file: AchFile = (lines.join("\n") + "\n")
+            .parse()
+            .map_err(|_| "records do not make up an ACH file".to_string())?;
+        if recompute {
+            ach_file.recompute_controls();
+        }
+        Ok(ach_file)
+    }
+}
+
+#[cfg(test)]
+mod ach_json_tests {
+    use crate::ach_file::AchFile;
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    #[test]
+    fn test_round_trip() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let json = ach.to_json();
+        assert!(json.contains("\"individual_name\": \"JANE ROE              \""));
+        assert!(json.contains("\"payment_related_info\": \"INVOICE 42"));
+        let back = AchFile::from_json(&json).unwrap();
+        assert_eq!(back.to_string(), SAMPLE);
+    }
+
+    #[test]
+    fn test_from_json() {
+        // Short values are padded, and controls recomputed when the trailers are left out
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let mut json: serde_json::Value = serde_json::from_str(&ach.to_json()).unwrap();
+        json["batches"][1]["entries"][0]["amount"] = "8000".into();
+        json["batches"][1]
+            .as_object_mut()
+            .unwrap()
+            .remove("trailer");
+        json.as_object_mut().unwrap().remove("trailer");
+        let back = AchFile::from_json(&json.to_string()).unwrap();
+        assert!(back.validate().is_empty(), "{:?}", back.validate());
+        assert!(back.to_string().contains("0000008000INV100"));
+
+        json["batches"][0]["entries"][1]["colour"] = "red".into();
+        assert_eq!(
+            AchFile::from_json(&json.to_string()).unwrap_err(),
+            "batch 1 entry 2 has unknown field 'colour'"
+        );
+        json["batches"][0]["entries"][1] = serde_json::json!({"amount": "ten"});
+        assert_eq!(
+            AchFile::from_json(&json.to_string()).unwrap_err(),
+            "batch 1 entry 2.amount 'ten' is not numeric"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::ach_file::{
+    is_routing_number, service_class_code, AchFile, EntryId, MAX_TRACE_SEQUENCE,
+};
+use std::collections::BTreeMap;
+
+/// Our settlement account, which the entries of a company's batches are offset against
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OffsetAccount {
+    /// 9 digit routing number of the bank holding the account
+    pub routing: String,
+    pub account: String,
+    /// Offsets to a savings account use transaction codes 32 and 37 rather than 22 and 27
+    pub savings: bool,
+}
+
+impl OffsetAccount {
+    pub fn new(routing: &str, account: &str, savings: bool) -> Result<Self, String> {
+        let routing = routing.trim();
+        if !is_routing_number(routing) {
+            return Err(format!(
+                "routing '{}' is not a valid routing number",
+                routing
+            ));
+        }
+        let account = account.trim();
+        if account.is_empty() || account.len() > 17 {
+            return Err(format!("account '{}' must be 1 to 17 characters", account));
+        }
+        Ok(OffsetAccount {
+            routing: routing.to_string(),
+            account: account.to_string(),
+            savings,
+        })
+    }
+
+    fn holds(&self, ach_file: &AchFile, entry: EntryId) -> bool {
+        let field = |name| ach_file.entry_field(entry, name).unwrap_or_default().trim();
+        format!("{}{}", field("receiving_dfi_id"), field("check_digit")) == self.routing
+            && field("dfi_account") == self.account
+    }
+}
+
+/// How [AchFile::balance] offsets each batch
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OffsetOptions {
+    /// Settlement account for the batches of each company, by company ID
+    pub accounts: BTreeMap<String, OffsetAccount>,
+    /// `individual_name` of every offset. An entry to the company's settlement account with
+    /// this name is taken to be an offset added earlier.
+    pub individual_name: String,
+}
+
+/// Offsets [AchFile::balance] took out of a file and put back in
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BalanceOutcome {
+    pub removed: usize,
+    pub added: usize,
+}
+
+impl AchFile {
+    /// Every entry that is an offset added earlier by [AchFile::balance] with `options`
+    pub fn offsets(&self, options: &OffsetOptions) -> Vec<EntryId> {
+        self.entry_ids()
+            .into_iter()
+            .filter(|e| {
+                let company_id = self.batch_field(e.batch, "company_id").unwrap().trim();
+                let name = self.entry_field(*e, "individual_name").unwrap().trim();
+                name == options.individual_name.trim()
+                    && options
+                        .accounts
+                        .get(company_id)
+                        .is_some_and(|account| account.holds(self, *e))
+            })
+            .collect()
+    }
+
+    /// Add an entry to the end of each batch whose debits and credits differ, crediting or
+    /// debiting its company's settlement account by the difference, so that every batch
+    /// balances. Offsets added earlier are removed first, so a file edited since it was
+    /// balanced can be balanced again. Offsets take trace numbers after the highest in the
+    /// file, and nothing else is renumbered; when those would run past the last sequence a
+    /// trace can hold, the file is left unchanged and needs renumbering first.
+    pub fn balance(&mut self, options: &OffsetOptions) -> Result<BalanceOutcome, String> {
+        let name = options.individual_name.trim();
+        if name.is_empty() || name.len() > 22 {
+            return Err(format!(
+                "individual_name '{}' must be 1 to 22 characters",
+                name
+            ));
+        }
+        for b in 0..self.batch_count() {
+            let company_id = self.batch_field(b, "company_id").unwrap().trim();
+            if !options.accounts.contains_key(company_id) {
+                return Err(format!(
+                    "there is no settlement account for company {}",
+                    company_id
+                ));
+            }
+        }
+
+        // Work on a copy, so a file that cannot be balanced is left as it was
+        let mut balanced = self.clone();
+        let offsets = balanced.offsets(options);
+        balanced.remove_entries(&offsets);
+        let mut outcome = BalanceOutcome {
+            removed: offsets.len(),
+            added: 0,
+        };
+
+        let mut sequence = balanced
+            .entry_ids()
+            .iter()
+            .filter_map(|e| {
+                balanced
+                    .entry_field(*e, "trace")?
+                    .get(8..)?
+                    .parse::<u64>()
+                    .ok()
+            })
+            .max()
+            .unwrap_or(0);
+        for b in 0..balanced.batch_count() {
+            let entries = balanced.batches()[b].entries();
+            let total = |debit| {
+                entries
+                    .iter()
+                    .filter(|e| e.is_debit() == debit)
+                    .map(|e| e.amount())
+                    .sum::<u64>()
+            };
+            let (debits, credits) = (total(true), total(false));
+            if debits != credits {
+                let company_id = balanced.batch_field(b, "company_id").unwrap().trim();
+                let account = options.accounts[company_id].clone();
+                let code = match (account.savings, debits > credits) {
+                    (false, true) => "22",
+                    (false, false) => "27",
+                    (true, true) => "32",
+                    (true, false) => "37",
+                };
+                sequence += 1;
+                if sequence > MAX_TRACE_SEQUENCE {
+                    return Err(format!(
+                        "there is no trace sequence after {} for the offset of batch {}, \
+                         renumber the file first",
+                        MAX_TRACE_SEQUENCE,
+                        balanced.batch_field(b, "batch_number").unwrap()
+                    ));
+                }
+                let trace = format!(
+                    "{}{:07}",
+                    balanced.batch_field(b, "odfi_id").unwrap(),
+                    sequence
+                );
+
+                let entry = balanced.insert_entry(b, &format!("6{:93}", ""))?;
+                for (field, value) in [
+                    ("transactions_code", code),
+                    ("receiving_dfi_id", &account.routing[..8]),
+                    ("check_digit", &account.routing[8..]),
+                    ("dfi_account", &account.account),
+                    ("amount", &debits.abs_diff(credits).to_string()),
+                    ("individual_name", name),
+                    ("addenda_indicator", "0"),
+                    ("trace", &trace),
+                ] {
+                    balanced.set_entry_field(entry, field, value)?;
+                }
+                outcome.added += 1;
+            }
+            let service_class_code = service_class_code(balanced.batches()[b].entries());
+            balanced.set_batch_field(b, "service_class_code", service_class_code)?;
+        }
+
+        balanced.recompute_controls();
+        *self = balanced;
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod ach_offset_tests {
+    use crate::ach_file::{AchFile, EntryId};
+    use crate::ach_offset::{BalanceOutcome, OffsetAccount, OffsetOptions};
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    /// Settlement accounts for both companies of the sample, the second a savings account
+    pub(crate) fn options() -> OffsetOptions {
+        let mut options = OffsetOptions {
+            individual_name: "SETTLEMENT".to_string(),
+            ..Default::default()
+        };
+        options.accounts.insert(
+            "1234567890".to_string(),
+            OffsetAccount::new("091000019", "800100", false).unwrap(),
+        );
+        options.accounts.insert(
+            "9876543210".to_string(),
+            OffsetAccount::new("091000019", "800200", true).unwrap(),
+        );
+        options
+    }
+
+    #[test]
+    fn test_balance() {
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let outcome = ach.balance(&options()).unwrap();
+        assert_eq!(
+            outcome,
+            BalanceOutcome {
+                removed: 0,
+                added: 2
+            }
+        );
+        assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+
+        let text = ach.to_string();
+        // ACME debits 25.00 and credits 10.00, so its account is credited the difference
+        assert!(text.contains(
+            "622091000019800100           0000001500               SETTLEMENT              0091000010000005"
+        ), "{}", text);
+        assert!(text.contains(
+            "632091000019800200           0000007500               SETTLEMENT              0091000010000006"
+        ), "{}", text);
+        assert!(text.contains("5200BETA LLC"));
+        assert_eq!(ach.offsets(&options()).len(), 2);
+
+        // After an edit the old offsets are replaced rather than added to
+        let edited = text.replace("0000007500INV100", "0000008000INV100");
+        let mut ach: AchFile = edited.parse().unwrap();
+        let outcome = ach.balance(&options()).unwrap();
+        assert_eq!(
+            outcome,
+            BalanceOutcome {
+                removed: 2,
+                added: 2
+            }
+        );
+        assert_eq!(ach.offsets(&options()).len(), 2);
+        assert!(ach
+            .to_string()
+            .contains("0000008000               SETTLEMENT"));
+        assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+
+        // The trace an offset would take is past the last there is
+        ach.set_entry_field(EntryId { batch: 1, entry: 0 }, "trace", "091000019999999")
+            .unwrap();
+        let before = ach.to_string();
+        assert_eq!(
+            ach.balance(&options()).unwrap_err(),
+            "there is no trace sequence after 9999999 for the offset of batch 0000001, \
+             renumber the file first"
+        );
+        // The offsets removed to rebalance are still there
+        assert_eq!(ach.to_string(), before);
+        assert_eq!(ach.offsets(&options()).len(), 2);
+    }
+
+    #[test]
+    fn test_balance_needs_accounts() {
+        let mut options = options();
+        options.accounts.remove("9876543210");
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        assert_eq!(
+            ach.balance(&options).unwrap_err(),
+            "there is no settlement account for company 9876543210"
+        );
+        assert_eq!(ach.to_string(), SAMPLE);
+
+        assert_eq!(
+            OffsetAccount::new("091000018", "800100", false).unwrap_err(),
+            "routing '091000018' is not a valid routing number"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use crate::ach_change_log::ChangeLog;
+use crate::ach_file::{AchFile, EntryId};
+use crate::ach_transformations::{Operation, Transformation};
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// An operation defined outside this crate. Once added to an [OperationRegistry] it can be
+/// named in `operation:` like the built in ones, and takes the transformation's `on:` and
+/// `where:` settings.
+pub trait TransformOperation: Debug + Send + Sync {
+    /// Name the operation is given in `operation:`
+    fn name(&self) -> &str;
+
+    /// Change `context`'s file. Batches left without entries are dropped and the controls
+    /// recomputed afterwards. Anything changed is added to the change log whether or not it is
+    /// also logged here.
+    fn apply(&self, context: &mut OperationContext) -> Result<(), String>;
+}
+
+/// What a [TransformOperation] works on: the file, the transformation it was named in, and the
+/// change log
+pub struct OperationContext<'a> {
+    pub(crate) transformation: &'a Transformation,
+    pub(crate) ach_file: &'a mut AchFile,
+    pub(crate) change_log: &'a mut ChangeLog,
+}
+
+impl OperationContext<'_> {
+    /// Label of the transformation the operation was named in
+    pub fn label(&self) -> &str {
+        &self.transformation.label
+    }
+
+    pub fn ach_file(&self) -> &AchFile {
+        self.ach_file
+    }
+
+    pub fn ach_file_mut(&mut self) -> &mut AchFile {
+        self.ach_file
+    }
+
+    /// Whether `entry` matches the transformation's conditions, tested against the entry for
+    /// `on: EntryDetail` and against its batch header for `on: CompanyBatchHeader`. With no
+    /// `on:` they are tested against the entry.
+    pub fn matches(&self, entry: EntryId) -> bool {
+        self.transformation.matches_entry(self.ach_file, entry)
+    }
+
+    /// Every entry that [OperationContext::matches], in the order they are written
+    pub fn matching_entries(&self) -> Vec<EntryId> {
+        self.ach_file
+            .entry_ids()
+            .into_iter()
+            .filter(|e| self.matches(*e))
+            .collect()
+    }
+
+    /// Log of every change made so far, for the operation to add its own to
+    pub fn change_log(&mut self) -> &mut ChangeLog {
+        self.change_log
+    }
+}
+
+/// The operations a config can name besides the built in ones, by name
+#[derive(Debug, Default, Clone)]
+pub struct OperationRegistry {
+    operations: BTreeMap<String, Arc<dyn TransformOperation>>,
+}
+
+impl OperationRegistry {
+    pub fn new() -> Self {
+        OperationRegistry::default()
+    }
+
+    /// Add `operation`, failing if its name is already taken by a built in or registered one
+    pub fn register<O: TransformOperation + 'static>(
+        &mut self,
+        operation: O,
+    ) -> Result<(), String> {
+        let name = operation.name().to_string();
+        if Operation::NAMES.contains(&name.as_str()) {
+            return Err(format!("'{}' is a built in operation", name));
+        }
+        if self.operations.contains_key(&name) {
+            return Err(format!("'{}' is already registered", name));
+        }
+        self.operations.insert(name, Arc::new(operation));
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<Arc<dyn TransformOperation>> {
+        self.operations.get(name).cloned()
+    }
+
+    /// Every operation a config can name: the built in ones, then those registered
+    pub fn names(&self) -> Vec<&str> {
+        Operation::NAMES
+            .iter()
+            .copied()
+            .chain(self.operations.keys().map(String::as_str))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod ach_operations_tests {
+    use crate::ach_config::{lint_with, ConfigFormat};
+    use crate::ach_file::AchFile;
+    use crate::ach_operations::{OperationContext, OperationRegistry, TransformOperation};
+    use crate::ach_transformations::Transformations;
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    /// Takes a flat fee of 25 cents from each matching debit
+    #[derive(Debug)]
+    struct ApplyFee;
+
+    impl TransformOperation for ApplyFee {
+        fn name(&self) -> &str {
+            "apply_fee"
+        }
+
+        fn apply(&self, context: &mut OperationContext) -> Result<(), String> {
+            for entry in context.matching_entries() {
+                let amount: u64 = context
+                    .ach_file()
+                    .entry_field(entry, "amount")
+                    .and_then(|a| a.parse().ok())
+                    .ok_or("entry has no amount")?;
+                context.ach_file_mut().set_entry_field(
+                    entry,
+                    "amount",
+                    &(amount + 25).to_string(),
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    fn registry() -> OperationRegistry {
+        let mut registry = OperationRegistry::new();
+        registry.register(ApplyFee).unwrap();
+        registry
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = registry();
+        assert_eq!(
+            registry.register(ApplyFee),
+            Err("'apply_fee' is already registered".to_string())
+        );
+        assert_eq!(registry.names().last(), Some(&"apply_fee"));
+    }
+
+    #[test]
+    fn test_custom_operation() {
+        let config = "fees:\n    operation: apply_fee\n    on: EntryDetail\n    where: transactions_code == 27\n";
+        let transformations =
+            Transformations::from_config_with(config, ConfigFormat::Yaml, &registry()).unwrap();
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let outcome = transformations.apply(&mut ach).unwrap();
+
+        assert!(ach.to_string().contains("0000002525EMP002"));
+        assert!(ach.to_string().contains("0000007525INV100"));
+        assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+        assert!(outcome.change_log.to_string().contains(
+            "[fees] batch 1 entry 3 (091000010000003) EntryDetail.amount: '0000002500' -> '0000002525'"
+        ));
+
+        // Without the registry the name is unknown, and the error lists what it could be
+        let diagnostics = lint_with(config, ConfigFormat::Yaml, &OperationRegistry::new());
+        assert_eq!(
+            diagnostics[0].message,
+            "'fees': unknown operation 'apply_fee', expected one of split, replace, drop, \
+             quarantine, rebatch, reroute, script, offset, sort, consolidate"
+        );
+        let diagnostics = lint_with(
+            "fees:\n    operation: apply_fees\n",
+            ConfigFormat::Yaml,
+            &registry(),
+        );
+        assert!(diagnostics[0].message.ends_with(
+            "expected one of split, replace, drop, quarantine, rebatch, reroute, script, offset, sort, consolidate, apply_fee"
+        ));
+    }
+}
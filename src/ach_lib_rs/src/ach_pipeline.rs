@@ -0,0 +1,271 @@
+use crate::ach_config::ConfigFormat;
+use crate::ach_file::AchFile;
+use crate::ach_transformations::{TransformOutcome, Transformations};
+use log::{error, info};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Configs applied one after another, each to the file the one before it produced
+#[derive(Debug)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+}
+
+#[derive(Debug)]
+pub struct Stage {
+    /// Name changes made by the stage are logged under, by default the stem of its config
+    pub name: String,
+    pub transformations: Transformations,
+    /// Stop the pipeline if the file does not validate once the stage is done
+    pub stop_on_invalid: bool,
+}
+
+/// A pipeline file: a config with nothing in it but `stages:`, whose configs are relative to it
+///
+/// ```yaml
+/// stages:
+///   - config: normalize.yml
+///   - config: client.yml
+///     stop_on_invalid: true
+///   - name: routing
+///     config: routing.toml
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PipelineFile {
+    stages: Vec<StageConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StageConfig {
+    config: String,
+    name: Option<String>,
+    #[serde(default)]
+    stop_on_invalid: bool,
+}
+
+/// Just enough of a config to tell a pipeline file from a transformation config
+#[derive(Deserialize)]
+struct Probe {
+    stages: Option<IgnoredAny>,
+}
+
+fn invalid(message: String) -> io::Error {
+    error!("{}", message);
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+impl Pipeline {
+    /// A stage for each of `configs` in turn. A pipeline file adds a stage for each config it
+    /// lists instead.
+    pub fn load(configs: &[PathBuf]) -> io::Result<Self> {
+        let mut stages = vec![];
+        for path in configs {
+            let source = fs::read_to_string(path).map_err(|e| {
+                error!("Could not open config {}: {}", path.display(), e);
+                e
+            })?;
+            match Pipeline::parse(&source, ConfigFormat::from(path.as_path()), path)? {
+                Some(pipeline) => {
+                    let dir = path.parent().unwrap_or(Path::new("."));
+                    for stage in pipeline.stages {
+                        let config = dir.join(&stage.config);
+                        stages.push(Stage {
+                            name: stage.name.unwrap_or_else(|| stem(&config)),
+                            transformations: Transformations::try_from(config.as_path())?,
+                            stop_on_invalid: stage.stop_on_invalid,
+                        });
+                    }
+                }
+                None => stages.push(Stage {
+                    name: stem(path),
+                    transformations: Transformations::try_from(path.as_path())?,
+                    stop_on_invalid: false,
+                }),
+            }
+        }
+
+        for (i, stage) in stages.iter().enumerate() {
+            if stages[..i].iter().any(|s| s.name == stage.name) {
+                return Err(invalid(format!(
+                    "more than one stage is named '{}', name them apart with 'name:'",
+                    stage.name
+                )));
+            }
+        }
+        Ok(Pipeline { stages })
+    }
+
+    /// `source` as a pipeline file, or nothing if it is a transformation config
+    fn parse(source: &str, format: ConfigFormat, path: &Path) -> io::Result<Option<PipelineFile>> {
+        let probe = match format {
+            ConfigFormat::Yaml => serde_yaml::from_str::<Probe>(source).ok(),
+            ConfigFormat::Toml => toml::from_str::<Probe>(source).ok(),
+            ConfigFormat::Json => serde_json::from_str::<Probe>(source).ok(),
+        };
+        if probe.and_then(|p| p.stages).is_none() {
+            return Ok(None);
+        }
+
+        let pipeline = match format {
+            ConfigFormat::Yaml => serde_yaml::from_str(source).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::from_str(source).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::from_str(source).map_err(|e| e.to_string()),
+        };
+        pipeline
+            .map(Some)
+            .map_err(|e| invalid(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Apply each stage in turn. Changes are logged under the stage that made them, trace
+    /// numbers are followed through every stage, and what stages quarantine or split off is
+    /// gathered together.
+    pub fn apply(&self, ach_file: &mut AchFile) -> io::Result<TransformOutcome> {
+        let mut outcome = TransformOutcome::default();
+        for stage in &self.stages {
+            let stage_outcome = stage.transformations.apply(ach_file)?;
+            info!(
+                "Stage '{}' made {} changes",
+                stage.name,
+                stage_outcome.change_log.len()
+            );
+
+            outcome
+                .change_log
+                .append(&stage.name, stage_outcome.change_log);
+            outcome.trace_map.extend(stage_outcome.trace_map);
+            outcome.outputs.extend(stage_outcome.outputs);
+            outcome.collapsed_batches += stage_outcome.collapsed_batches;
+            if let Some(quarantine) = stage_outcome.quarantine {
+                match &mut outcome.quarantine {
+                    Some(all) => {
+                        all.batches_mut()
+                            .extend(quarantine.batches().iter().cloned());
+                        all.recompute_controls();
+                    }
+                    None => outcome.quarantine = Some(quarantine),
+                }
+            }
+
+            if stage.stop_on_invalid {
+                let issues = ach_file.validate();
+                if let Some(first) = issues.first() {
+                    for issue in &issues {
+                        error!("{}", issue);
+                    }
+                    return Err(invalid(format!(
+                        "stage '{}': file does not validate, stopping the pipeline. First \
+                         problem: {}",
+                        stage.name, first
+                    )));
+                }
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Work out what [Pipeline::apply] would do to `ach_file` without changing it
+    pub fn dry_run(&self, ach_file: &AchFile) -> io::Result<TransformOutcome> {
+        self.apply(&mut ach_file.clone())
+    }
+}
+
+fn stem(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod ach_pipeline_tests {
+    use crate::ach_file::AchFile;
+    use crate::ach_pipeline::Pipeline;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    #[test]
+    fn test_configs_in_turn() {
+        let pipeline = Pipeline::load(&[
+            PathBuf::from("test_data/cfg/drop_zero_dollar.yml"),
+            PathBuf::from("test_data/cfg/pipeline/reroute.yml"),
+        ])
+        .unwrap();
+        assert_eq!(pipeline.stages.len(), 2);
+
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let outcome = pipeline.apply(&mut ach).unwrap();
+        assert!(ach.validate().is_empty());
+        assert!(!ach.to_string().contains("ZERO DOLLAR TEST"));
+
+        let log = outcome.change_log.to_string();
+        assert!(
+            log.contains("[drop_zero_dollar/drop_zero_dollar] batch 1 entry 2 (091000010000002)")
+        );
+        // The reroute sees the file without the dropped entry, so JANE ROE is now the second
+        assert!(log.contains(
+            "[reroute/backup_odfi] batch 1 entry 2 (091000010000003) EntryDetail.trace: \
+             '091000010000003' -> '021000020000003'"
+        ));
+        assert_eq!(
+            outcome.trace_map.get("091000010000003"),
+            Some("021000020000003")
+        );
+    }
+
+    #[test]
+    fn test_pipeline_file() {
+        let pipeline =
+            Pipeline::load(&[PathBuf::from("test_data/cfg/pipeline/nightly.yml")]).unwrap();
+        let names: Vec<_> = pipeline.stages.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["normalize", "routing"]);
+        assert!(pipeline.stages[0].stop_on_invalid);
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        pipeline.apply(&mut ach).unwrap();
+
+        // A file that does not validate after a stage stops the pipeline there
+        let mut ach: AchFile = SAMPLE
+            .replace("622076401251123456789", "622076401259123456789")
+            .parse()
+            .unwrap();
+        let error = pipeline.apply(&mut ach).unwrap_err().to_string();
+        assert!(
+            error.starts_with("stage 'normalize': file does not validate, stopping the pipeline"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_pipeline_file_errors() {
+        let dir = std::env::temp_dir().join(format!("ach_rs_pipeline_err_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("typo.yml"), "stages:\n  - confg: a.yml\n").unwrap();
+        fs::write(
+            dir.join("twice.yml"),
+            "stages:\n  - config: a.yml\n  - config: a.yml\n",
+        )
+        .unwrap();
+        fs::copy("test_data/cfg/drop_zero_dollar.yml", dir.join("a.yml")).unwrap();
+
+        let error = Pipeline::load(&[dir.join("typo.yml")]).unwrap_err();
+        assert!(
+            error.to_string().contains("unknown field `confg`"),
+            "{}",
+            error
+        );
+        let error = Pipeline::load(&[dir.join("twice.yml")]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "more than one stage is named 'a', name them apart with 'name:'"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
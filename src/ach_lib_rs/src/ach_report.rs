@@ -0,0 +1,653 @@
+use crate::ach_file::{AchFile, AchRecord};
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+
+/// Entry counts and totals of one batch, computed from its entries
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchSummary {
+    pub batch_number: String,
+    pub company_name: String,
+    pub company_id: String,
+    pub sec: String,
+    pub entries: usize,
+    pub total_debits: u64,
+    pub total_credits: u64,
+}
+
+/// Entry counts and totals of a file and each of its batches, computed from the entries
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub batches: Vec<BatchSummary>,
+    pub entries: usize,
+    pub total_debits: u64,
+    pub total_credits: u64,
+}
+
+impl From<&AchFile> for Summary {
+    fn from(ach_file: &AchFile) -> Self {
+        let batches: Vec<BatchSummary> = ach_file
+            .batches()
+            .iter()
+            .map(|batch| {
+                let header = |name| {
+                    batch
+                        .header()
+                        .field(name)
+                        .unwrap()
+                        .as_str()
+                        .trim()
+                        .to_string()
+                };
+                let total = |debit| {
+                    let entries = batch.entries().iter().filter(|e| e.is_debit() == debit);
+                    entries.map(|e| e.amount()).sum()
+                };
+                BatchSummary {
+                    batch_number: header("batch_number"),
+                    company_name: header("company_name"),
+                    company_id: header("company_id"),
+                    sec: header("sec"),
+                    entries: batch.entries().len(),
+                    total_debits: total(true),
+                    total_credits: total(false),
+                }
+            })
+            .collect();
+
+        Summary {
+            entries: batches.iter().map(|b| b.entries).sum(),
+            total_debits: batches.iter().map(|b| b.total_debits).sum(),
+            total_credits: batches.iter().map(|b| b.total_credits).sum(),
+            batches,
+        }
+    }
+}
+
+impl Summary {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// An amount in cents as dollars and cents, e.g. `25.00`
+pub fn dollars(cents: u64) -> String {
+    format!("{}.{:02}", cents / 100, cents % 100)
+}
+
+/// A line for each batch, then one for the whole file
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<7}  {:<16}  {:<10}  {:<3}  {:>7}  {:>14}  {:>14}",
+            "batch", "company", "company_id", "sec", "entries", "debits", "credits"
+        )?;
+        for batch in &self.batches {
+            writeln!(
+                f,
+                "{:<7}  {:<16}  {:<10}  {:<3}  {:>7}  {:>14}  {:>14}",
+                batch.batch_number,
+                batch.company_name,
+                batch.company_id,
+                batch.sec,
+                batch.entries,
+                dollars(batch.total_debits),
+                dollars(batch.total_credits)
+            )?;
+        }
+        writeln!(
+            f,
+            "{:<7}  {:<16}  {:<10}  {:<3}  {:>7}  {:>14}  {:>14}",
+            "total",
+            "",
+            "",
+            "",
+            self.entries,
+            dollars(self.total_debits),
+            dollars(self.total_credits)
+        )
+    }
+}
+
+/// A control total as declared in a trailer beside the value computed from the records. The
+/// declared value is `None` when the trailer field is not a number.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Control {
+    pub declared: Option<u64>,
+    pub computed: u64,
+    pub matches: bool,
+}
+
+impl Control {
+    fn new(record: &dyn AchRecord, name: &str, computed: u64) -> Self {
+        let declared = record.field(name).and_then(|f| f.as_u64());
+        Control {
+            declared,
+            computed,
+            matches: declared == Some(computed),
+        }
+    }
+}
+
+/// Counts and totals of one batch, computed from its records and checked against its trailer
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BatchControls {
+    pub batch_number: String,
+    pub company_name: String,
+    pub company_id: String,
+    pub sec: String,
+    pub effective_entry_date: String,
+    pub entries: usize,
+    pub addenda: usize,
+    pub entry_and_addenda_count: Control,
+    pub entry_hash: Control,
+    pub total_debits: Control,
+    pub total_credits: Control,
+    /// Credits less debits, negative when the batch takes in more than it pays out
+    pub net: i64,
+}
+
+/// Counts and totals of every batch of one company
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CompanyControls {
+    pub company_name: String,
+    pub company_id: String,
+    pub batches: usize,
+    pub entries: usize,
+    pub addenda: usize,
+    pub total_debits: u64,
+    pub total_credits: u64,
+    pub net: i64,
+}
+
+/// Control totals of a file for approving it: each batch and each company, then the file as a
+/// whole, with every value a trailer declares beside the one computed from the records
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ControlReport {
+    pub immediate_dest: String,
+    pub immediate_orig: String,
+    pub file_creation_date: String,
+    pub file_creation_time: String,
+    pub batches: Vec<BatchControls>,
+    /// In the order each company first appears
+    pub companies: Vec<CompanyControls>,
+    pub entries: usize,
+    pub addenda: usize,
+    pub batch_count: Control,
+    pub block_count: Control,
+    pub entry_and_addenda_count: Control,
+    pub entry_hash: Control,
+    pub total_debits: Control,
+    pub total_credits: Control,
+    pub net: i64,
+}
+
+fn net(debits: u64, credits: u64) -> i64 {
+    credits as i64 - debits as i64
+}
+
+impl From<&AchFile> for ControlReport {
+    fn from(ach_file: &AchFile) -> Self {
+        let batches: Vec<BatchControls> = ach_file
+            .batches()
+            .iter()
+            .map(|batch| {
+                let header = |name| {
+                    batch
+                        .header()
+                        .field(name)
+                        .unwrap()
+                        .as_str()
+                        .trim()
+                        .to_string()
+                };
+                let trailer = batch.trailer();
+                BatchControls {
+                    batch_number: header("batch_number"),
+                    company_name: header("company_name"),
+                    company_id: header("company_id"),
+                    sec: header("sec"),
+                    effective_entry_date: header("effective_entry_date"),
+                    entries: batch.entries().len(),
+                    addenda: batch.entries().iter().map(|e| e.addenda().len()).sum(),
+                    entry_and_addenda_count: Control::new(
+                        trailer,
+                        "entry_and_addenda_count",
+                        batch.entry_and_addenda_count(),
+                    ),
+                    entry_hash: Control::new(trailer, "entry_hash", batch.entry_hash()),
+                    total_debits: Control::new(trailer, "total_debit_amount", batch.total_debits()),
+                    total_credits: Control::new(
+                        trailer,
+                        "total_credit_amount",
+                        batch.total_credits(),
+                    ),
+                    net: net(batch.total_debits(), batch.total_credits()),
+                }
+            })
+            .collect();
+
+        let mut companies: Vec<CompanyControls> = vec![];
+        for batch in &batches {
+            let company = match companies
+                .iter_mut()
+                .find(|c| c.company_id == batch.company_id)
+            {
+                Some(company) => company,
+                None => {
+                    companies.push(CompanyControls {
+                        company_name: batch.company_name.clone(),
+                        company_id: batch.company_id.clone(),
+                        batches: 0,
+                        entries: 0,
+                        addenda: 0,
+                        total_debits: 0,
+                        total_credits: 0,
+                        net: 0,
+                    });
+                    companies.last_mut().unwrap()
+                }
+            };
+            company.batches += 1;
+            company.entries += batch.entries;
+            company.addenda += batch.addenda;
+            company.total_debits += batch.total_debits.computed;
+            company.total_credits += batch.total_credits.computed;
+            company.net += batch.net;
+        }
+
+        let header = |name| {
+            ach_file
+                .header()
+                .field(name)
+                .unwrap()
+                .as_str()
+                .trim()
+                .to_string()
+        };
+        let trailer = ach_file.trailer();
+        let sum = |control: fn(&BatchControls) -> u64| batches.iter().map(control).sum::<u64>();
+        let total_debits = sum(|b| b.total_debits.computed);
+        let total_credits = sum(|b| b.total_credits.computed);
+        ControlReport {
+            immediate_dest: header("immediate_dest"),
+            immediate_orig: header("immediate_orig"),
+            file_creation_date: header("file_creation_date"),
+            file_creation_time: header("file_creation_time"),
+            entries: batches.iter().map(|b| b.entries).sum(),
+            addenda: batches.iter().map(|b| b.addenda).sum(),
+            batch_count: Control::new(trailer, "batch_count", batches.len() as u64),
+            block_count: Control::new(trailer, "block_count", ach_file.len().div_ceil(10) as u64),
+            entry_and_addenda_count: Control::new(
+                trailer,
+                "entry_and_addenda_count",
+                sum(|b| b.entry_and_addenda_count.computed),
+            ),
+            entry_hash: Control::new(
+                trailer,
+                "entry_hash",
+                sum(|b| b.entry_hash.computed) % 10_000_000_000,
+            ),
+            total_debits: Control::new(trailer, "total_debits", total_debits),
+            total_credits: Control::new(trailer, "total_credits", total_credits),
+            net: net(total_debits, total_credits),
+            batches,
+            companies,
+        }
+    }
+}
+
+impl BatchControls {
+    /// Each control of the batch trailer, by the name of its field
+    fn controls(&self) -> [(&'static str, &Control); 4] {
+        [
+            ("entry_and_addenda_count", &self.entry_and_addenda_count),
+            ("entry_hash", &self.entry_hash),
+            ("total_debit_amount", &self.total_debits),
+            ("total_credit_amount", &self.total_credits),
+        ]
+    }
+}
+
+impl ControlReport {
+    /// Each control of the file trailer, by the name of its field
+    fn controls(&self) -> [(&'static str, &Control); 6] {
+        [
+            ("batch_count", &self.batch_count),
+            ("block_count", &self.block_count),
+            ("entry_and_addenda_count", &self.entry_and_addenda_count),
+            ("entry_hash", &self.entry_hash),
+            ("total_debits", &self.total_debits),
+            ("total_credits", &self.total_credits),
+        ]
+    }
+
+    /// A line for each declared value that differs from the computed one. An empty result means
+    /// every trailer adds up.
+    pub fn mismatches(&self) -> Vec<String> {
+        let batches = self.batches.iter().flat_map(|batch| {
+            batch
+                .controls()
+                .into_iter()
+                .filter(|(_, control)| !control.matches)
+                .map(|(name, control)| {
+                    format!("batch {} {}", batch.batch_number, mismatch(name, control))
+                })
+        });
+        let file = self
+            .controls()
+            .into_iter()
+            .filter(|(_, control)| !control.matches)
+            .map(|(name, control)| format!("file {}", mismatch(name, control)));
+        batches.chain(file).collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// The report as Markdown tables of batches, companies and file controls, with mismatches
+    /// in bold
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!(
+            "# ACH file to {} from {}, created {} {}\n\n",
+            self.immediate_dest,
+            self.immediate_orig,
+            self.file_creation_date,
+            self.file_creation_time
+        );
+
+        out.push_str("## Batches\n\n");
+        out.push_str(
+            "| batch | company | company id | SEC | effective | entries | addenda | debits | \
+             credits | net | controls |\n",
+        );
+        out.push_str("|---|---|---|---|---|--:|--:|--:|--:|--:|---|\n");
+        for batch in &self.batches {
+            let mismatches: Vec<_> = batch
+                .controls()
+                .into_iter()
+                .filter(|(_, control)| !control.matches)
+                .map(|(name, control)| format!("**{}**", mismatch(name, control)))
+                .collect();
+            out.push_str(&format!(
+                "| {} | {} | {} | {} | {} | {} | {} | {} | {} | {} | {} |\n",
+                batch.batch_number,
+                batch.company_name,
+                batch.company_id,
+                batch.sec,
+                batch.effective_entry_date,
+                batch.entries,
+                batch.addenda,
+                dollars(batch.total_debits.computed),
+                dollars(batch.total_credits.computed),
+                signed_dollars(batch.net),
+                match mismatches.is_empty() {
+                    true => "ok".to_string(),
+                    false => mismatches.join("<br>"),
+                }
+            ));
+        }
+
+        out.push_str("\n## Companies\n\n");
+        out.push_str(
+            "| company | company id | batches | entries | addenda | debits | credits | net |\n",
+        );
+        out.push_str("|---|---|--:|--:|--:|--:|--:|--:|\n");
+        for company in &self.companies {
+            out.push_str(&format!(
+                "| {} | {} | {} | {} | {} | {} | {} | {} |\n",
+                company.company_name,
+                company.company_id,
+                company.batches,
+                company.entries,
+                company.addenda,
+                dollars(company.total_debits),
+                dollars(company.total_credits),
+                signed_dollars(company.net)
+            ));
+        }
+
+        out.push_str("\n## File\n\n");
+        out.push_str("| control | declared | computed |\n");
+        out.push_str("|---|--:|--:|\n");
+        for (name, control) in self.controls() {
+            let (declared, computed) = (declared(name, control), amount(name, control.computed));
+            match control.matches {
+                true => out.push_str(&format!("| {} | {} | {} |\n", name, declared, computed)),
+                false => out.push_str(&format!(
+                    "| **{}** | **{}** | **{}** |\n",
+                    name, declared, computed
+                )),
+            }
+        }
+        out.push_str(&format!(
+            "| entries | | {} |\n| addenda | | {} |\n| net | | {} |\n",
+            self.entries,
+            self.addenda,
+            signed_dollars(self.net)
+        ));
+        out
+    }
+}
+
+/// Whether the control `name` is an amount, rather than a count or hash
+fn is_amount(name: &str) -> bool {
+    name.starts_with("total_")
+}
+
+fn amount(name: &str, value: u64) -> String {
+    match is_amount(name) {
+        true => dollars(value),
+        false => value.to_string(),
+    }
+}
+
+fn declared(name: &str, control: &Control) -> String {
+    match control.declared {
+        Some(value) => amount(name, value),
+        None => "not a number".to_string(),
+    }
+}
+
+fn mismatch(name: &str, control: &Control) -> String {
+    format!(
+        "{} declared {}, computed {}",
+        name,
+        declared(name, control),
+        amount(name, control.computed)
+    )
+}
+
+/// [dollars] for an amount that can be negative, such as a net
+pub fn signed_dollars(cents: i64) -> String {
+    match cents < 0 {
+        true => format!("-{}", dollars(cents.unsigned_abs())),
+        false => dollars(cents as u64),
+    }
+}
+
+/// The batches, with any control that does not add up under its batch, then the companies, then
+/// the file controls declared and computed
+impl Display for ControlReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "ACH file to {} from {}, created {} {}\n",
+            self.immediate_dest,
+            self.immediate_orig,
+            self.file_creation_date,
+            self.file_creation_time
+        )?;
+        writeln!(
+            f,
+            "{:<7}  {:<16}  {:<10}  {:<3}  {:<9}  {:>7}  {:>7}  {:>14}  {:>14}  {:>14}",
+            "batch",
+            "company",
+            "company_id",
+            "sec",
+            "effective",
+            "entries",
+            "addenda",
+            "debits",
+            "credits",
+            "net"
+        )?;
+        for batch in &self.batches {
+            writeln!(
+                f,
+                "{:<7}  {:<16}  {:<10}  {:<3}  {:<9}  {:>7}  {:>7}  {:>14}  {:>14}  {:>14}",
+                batch.batch_number,
+                batch.company_name,
+                batch.company_id,
+                batch.sec,
+                batch.effective_entry_date,
+                batch.entries,
+                batch.addenda,
+                dollars(batch.total_debits.computed),
+                dollars(batch.total_credits.computed),
+                signed_dollars(batch.net)
+            )?;
+            for (name, control) in batch.controls() {
+                if !control.matches {
+                    writeln!(f, "  ! {}", mismatch(name, control))?;
+                }
+            }
+        }
+
+        writeln!(
+            f,
+            "\n{:<16}  {:<10}  {:>7}  {:>7}  {:>7}  {:>14}  {:>14}  {:>14}",
+            "company", "company_id", "batches", "entries", "addenda", "debits", "credits", "net"
+        )?;
+        for company in &self.companies {
+            writeln!(
+                f,
+                "{:<16}  {:<10}  {:>7}  {:>7}  {:>7}  {:>14}  {:>14}  {:>14}",
+                company.company_name,
+                company.company_id,
+                company.batches,
+                company.entries,
+                company.addenda,
+                dollars(company.total_debits),
+                dollars(company.total_credits),
+                signed_dollars(company.net)
+            )?;
+        }
+
+        writeln!(
+            f,
+            "\n{:<23}  {:>14}  {:>14}",
+            "file", "declared", "computed"
+        )?;
+        for (name, control) in self.controls() {
+            writeln!(
+                f,
+                "{:<23}  {:>14}  {:>14}{}",
+                name,
+                declared(name, control),
+                amount(name, control.computed),
+                if control.matches { "" } else { "  !" }
+            )?;
+        }
+        writeln!(f, "{:<23}  {:>14}  {:>14}", "entries", "", self.entries)?;
+        writeln!(f, "{:<23}  {:>14}  {:>14}", "addenda", "", self.addenda)?;
+        writeln!(
+            f,
+            "{:<23}  {:>14}  {:>14}",
+            "net",
+            "",
+            signed_dollars(self.net)
+        )
+    }
+}
+
+#[cfg(test)]
+mod ach_report_tests {
+    use crate::ach_file::AchFile;
+    use crate::ach_report::{ControlReport, Summary};
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    #[test]
+    fn test_summary() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let summary = Summary::from(&ach);
+        assert_eq!(summary.entries, 4);
+        assert_eq!(summary.total_debits, 10000);
+        assert_eq!(summary.total_credits, 1000);
+        assert_eq!(summary.batches[1].company_name, "BETA LLC");
+        assert_eq!(summary.batches[1].total_debits, 7500);
+
+        let text = summary.to_string();
+        assert!(text.contains(
+            "0000002  BETA LLC          9876543210  PPD        1           75.00            0.00"
+        ));
+        assert!(text.ends_with(
+            "total                                             4          100.00           10.00\n"
+        ));
+    }
+
+    #[test]
+    fn test_control_report() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let report = ControlReport::from(&ach);
+        assert!(report.mismatches().is_empty(), "{:?}", report.mismatches());
+        assert_eq!((report.entries, report.addenda), (4, 1));
+        assert_eq!(report.net, -9000);
+        assert_eq!(report.batches[0].addenda, 1);
+        assert_eq!(report.batches[0].net, -1500);
+        assert_eq!(report.companies.len(), 2);
+        assert_eq!(report.companies[1].total_debits, 7500);
+
+        let text = report.to_string();
+        assert!(
+            text.contains("\nfile                           declared        computed\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("\ntotal_debits                     100.00          100.00\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.ends_with("\nnet                                              -90.00\n"),
+            "{}",
+            text
+        );
+
+        // Trailers that no longer add up, as when an entry was edited by hand
+        let edited: AchFile = SAMPLE
+            .replace("0000007500INV100", "0000008000INV100")
+            .parse()
+            .unwrap();
+        let report = ControlReport::from(&edited);
+        assert_eq!(
+            report.mismatches(),
+            vec![
+                "batch 0000002 total_debit_amount declared 75.00, computed 80.00",
+                "file total_debits declared 100.00, computed 105.00",
+            ]
+        );
+        assert!(report
+            .to_string()
+            .contains("\n  ! total_debit_amount declared 75.00, computed 80.00\n"));
+        assert!(report
+            .to_string()
+            .contains("\ntotal_debits                     100.00          105.00  !\n"));
+
+        let markdown = report.to_markdown();
+        assert!(
+            markdown.contains("| **total_debits** | **100.00** | **105.00** |"),
+            "{}",
+            markdown
+        );
+        assert!(markdown.contains("| 0000001 | ACME CORP | 1234567890 | PPD |"));
+        assert!(markdown.contains("| ok |\n"));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["total_debits"]["declared"], 10000);
+        assert_eq!(json["total_debits"]["matches"], false);
+    }
+}
@@ -0,0 +1,406 @@
+use crate::ach_file::{service_class_code, AchFile, AchRecord, EntryId, RecordId, TraceMap};
+use crate::ach_generate::now;
+use crate::ach_offset::OffsetOptions;
+use crate::ach_report::dollars;
+
+/// What [AchFile::reverse] reverses, and when. With no batches or traces given, every entry of
+/// the file is reversed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReverseOptions {
+    /// YYMMDD the reversing entries settle on
+    pub effective_entry_date: String,
+    /// YYMMDD the reversal file is created, today by default
+    pub file_creation_date: String,
+    /// HHMM, now by default
+    pub file_creation_time: String,
+    /// File ID modifier of the reversal. Without one it takes the modifier after the reversed
+    /// file's, so the bank does not take the reversal for a duplicate of it.
+    pub file_id_modifier: Option<char>,
+    /// Batch numbers of the batches to reverse every entry of
+    pub batch_numbers: Vec<String>,
+    /// Trace numbers of single entries to reverse
+    pub traces: Vec<String>,
+    /// How to balance each batch of the reversal with [AchFile::balance]. Without it, every
+    /// batch has to balance as it is.
+    pub offset: Option<OffsetOptions>,
+}
+
+impl Default for ReverseOptions {
+    fn default() -> Self {
+        let (date, time) = now();
+        ReverseOptions {
+            effective_entry_date: Default::default(),
+            file_creation_date: date,
+            file_creation_time: time,
+            file_id_modifier: None,
+            batch_numbers: vec![],
+            traces: vec![],
+            offset: None,
+        }
+    }
+}
+
+/// Whether an entry of transaction code `code` moves money. Prenotes and zero-dollar entries,
+/// the codes ending in 3, 4, 8 and 9, do not, so there is nothing of theirs to reverse.
+fn moves_money(code: &str) -> bool {
+    !matches!(code.as_bytes().get(1), Some(b'3' | b'4' | b'8' | b'9'))
+}
+
+/// The transaction code that undoes `code`: credits become debits of the same account, and
+/// debits credits. Loan accounts take credits as 52 and debits as 55.
+fn reversed_code(code: &str) -> Option<String> {
+    let mut digits = code.chars();
+    let (account, kind) = (digits.next()?, digits.next()?);
+    let kind = match (account, kind) {
+        ('5', '2') => '5',
+        ('5', '5') => '2',
+        ('5', _) => return None,
+        (_, '2') => '7',
+        (_, '7') => '2',
+        _ => return None,
+    };
+    Some(format!("{}{}", account, kind))
+}
+
+/// The file ID modifier after `modifier`: A to Z, then 0 to 9, then A again
+fn next_modifier(modifier: char) -> char {
+    match modifier {
+        'A'..='Y' | '0'..='8' => (modifier as u8 + 1) as char,
+        'Z' => '0',
+        _ => 'A',
+    }
+}
+
+impl AchFile {
+    /// A file of entries reversing those of this file that `options` selects: each with its
+    /// debit or credit transaction code flipped and its amount, account and addenda kept, in
+    /// batches described as `REVERSAL` that settle on the new effective date. Traces continue
+    /// on from the highest sequence this file has, so no reversal shares a trace with an
+    /// original, and are returned as a map from each original trace to its reversal. The
+    /// reversal is a new file, with its own creation date, time and file ID modifier.
+    ///
+    /// Prenotes and zero-dollar entries move no money, so a batch or file being reversed
+    /// leaves them out, and naming one by its trace is an error.
+    ///
+    /// The reversal is a balanced file. With [ReverseOptions::offset], offsets this file has
+    /// are not reversed, and each batch of the reversal gets offsets of its own instead.
+    /// Without it, a batch whose debits and credits differ is an error.
+    pub fn reverse(&self, options: &ReverseOptions) -> Result<(AchFile, TraceMap), String> {
+        let date = options.effective_entry_date.as_str();
+        let created = (&options.file_creation_date, &options.file_creation_time);
+        for (name, value, layout, kind) in [
+            ("effective_entry_date", date, "YYMMDD", "date"),
+            ("file_creation_date", created.0, "YYMMDD", "date"),
+            ("file_creation_time", created.1, "HHMM", "time"),
+        ] {
+            if value.len() != layout.len() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(format!("{} '{}' is not a {} {}", name, value, layout, kind));
+            }
+        }
+        if let Some(modifier) = options
+            .file_id_modifier
+            .filter(|m| !m.is_ascii_uppercase() && !m.is_ascii_digit())
+        {
+            return Err(format!(
+                "file_id_modifier '{}' is not A to Z or 0 to 9",
+                modifier
+            ));
+        }
+
+        let trace = |e: &EntryId| self.entry_field(*e, "trace").unwrap().trim();
+        let batch_number = |e: &EntryId| self.batch_field(e.batch, "batch_number").unwrap();
+        // Batch numbers are zero padded, so "2" and "0000002" are the same batch
+        let same_number = |a: &str, b: &str| a.parse::<u64>().ok() == b.parse::<u64>().ok();
+        let entries = self.entry_ids();
+        if let Some(missing) = options
+            .traces
+            .iter()
+            .find(|t| !entries.iter().any(|e| trace(e) == t.as_str()))
+        {
+            return Err(format!("trace {} is not in the file", missing));
+        }
+        if let Some(missing) = options.batch_numbers.iter().find(|n| {
+            !(0..self.batch_count())
+                .any(|b| same_number(self.batch_field(b, "batch_number").unwrap(), n))
+        }) {
+            return Err(format!("batch {} is not in the file", missing));
+        }
+
+        let everything = options.batch_numbers.is_empty() && options.traces.is_empty();
+        let offsets = match &options.offset {
+            Some(offset) => self.offsets(offset),
+            None => vec![],
+        };
+        let selected: Vec<EntryId> = entries
+            .iter()
+            .filter(|e| !offsets.contains(e))
+            .filter(|e| {
+                let code = self.entry_field(**e, "transactions_code").unwrap();
+                let in_batch = options
+                    .batch_numbers
+                    .iter()
+                    .any(|n| same_number(n, batch_number(e)));
+                options.traces.iter().any(|t| t == trace(e))
+                    || ((everything || in_batch) && moves_money(code))
+            })
+            .copied()
+            .collect();
+        if selected.is_empty() {
+            return Err("there are no entries to reverse".to_string());
+        }
+        let last_sequence = entries
+            .iter()
+            .filter_map(|e| trace(e).get(8..)?.parse::<u64>().ok())
+            .max()
+            .unwrap_or(0);
+
+        let mut original = self.clone();
+        let mut reversal = original.take_entries(&selected);
+        for entry in reversal.entry_ids() {
+            let code = reversal
+                .entry_field(entry, "transactions_code")
+                .unwrap()
+                .to_string();
+            let reversed = reversed_code(&code).ok_or_else(|| {
+                format!(
+                    "entry {} has transaction code {}, which cannot be reversed",
+                    reversal.entry_field(entry, "trace").unwrap(),
+                    code
+                )
+            })?;
+            reversal.set_entry_field(entry, "transactions_code", &reversed)?;
+        }
+        let modifier = options.file_id_modifier.unwrap_or_else(|| {
+            let modifier = self.header().field("file_id_modifier").unwrap().as_str();
+            next_modifier(modifier.chars().next().unwrap_or('A'))
+        });
+        for (name, value) in [
+            ("file_creation_date", created.0.as_str()),
+            ("file_creation_time", created.1.as_str()),
+            ("file_id_modifier", &modifier.to_string()),
+        ] {
+            reversal.set_field(RecordId::Header, name, value)?;
+        }
+        for b in 0..reversal.batch_count() {
+            let service_class_code = service_class_code(reversal.batches()[b].entries());
+            reversal.set_batch_field(b, "service_class_code", service_class_code)?;
+            reversal.set_batch_field(b, "entry_desc", "REVERSAL")?;
+            reversal.set_batch_field(b, "effective_entry_date", date)?;
+        }
+
+        let trace_map = reversal.renumber_from(last_sequence + 1)?;
+        match &options.offset {
+            Some(offset) => {
+                reversal.balance(offset)?;
+            }
+            None => {
+                for (b, batch) in reversal.batches().iter().enumerate() {
+                    let (debits, credits) = (batch.total_debits(), batch.total_credits());
+                    if debits != credits {
+                        return Err(format!(
+                            "batch {} of the reversal debits {} and credits {}, so it needs \
+                             settlement accounts to offset it",
+                            reversal.batch_field(b, "batch_number").unwrap(),
+                            dollars(debits),
+                            dollars(credits)
+                        ));
+                    }
+                }
+            }
+        }
+        Ok((reversal, trace_map))
+    }
+}
+
+#[cfg(test)]
+mod ach_reversal_tests {
+    use crate::ach_file::{AchFile, AchRecord};
+    use crate::ach_offset::ach_offset_tests;
+    use crate::ach_reversal::{next_modifier, reversed_code, ReverseOptions};
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    fn options() -> ReverseOptions {
+        ReverseOptions {
+            effective_entry_date: "261025".to_string(),
+            file_creation_date: "261024".to_string(),
+            file_creation_time: "0930".to_string(),
+            offset: Some(ach_offset_tests::options()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_reversed_code() {
+        assert_eq!(reversed_code("22").as_deref(), Some("27"));
+        assert_eq!(reversed_code("27").as_deref(), Some("22"));
+        assert_eq!(reversed_code("42").as_deref(), Some("47"));
+        assert_eq!(reversed_code("52").as_deref(), Some("55"));
+        assert_eq!(reversed_code("55").as_deref(), Some("52"));
+        assert_eq!(reversed_code("21"), None);
+        assert_eq!(reversed_code("23"), None);
+        assert_eq!(reversed_code("38"), None);
+        assert_eq!(next_modifier('A'), 'B');
+        assert_eq!(next_modifier('Z'), '0');
+        assert_eq!(next_modifier('9'), 'A');
+    }
+
+    #[test]
+    fn test_reverse() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let (reversal, trace_map) = ach.reverse(&options()).unwrap();
+        assert!(reversal.validate().is_empty(), "{:?}", reversal.validate());
+        assert_eq!(reversal.entry_ids().len(), 6);
+        assert_eq!(reversal.batch_field(0, "entry_desc"), Some("REVERSAL  "));
+        assert_eq!(
+            reversal.batch_field(0, "effective_entry_date"),
+            Some("261025")
+        );
+        assert_eq!(reversal.batch_field(1, "service_class_code"), Some("200"));
+
+        let text = reversal.to_string();
+        // A new file, not a duplicate of the one it reverses
+        assert!(
+            text.starts_with("101 091000019 1234567892610240930B094101"),
+            "{}",
+            text
+        );
+        assert!(text.contains("627076401251123456789        0000001000EMP001         JOHN DOE"));
+        assert!(text.contains("622021000021555000111        0000002500EMP002         JANE ROE"));
+        assert!(text.contains("INVOICE 42"));
+        // Debits and credits trade places, and the offsets even them out
+        assert!(text.contains(
+            "627091000019800100           0000001500               SETTLEMENT              0091000010000009"
+        ), "{}", text);
+        assert!(text.contains(
+            "637091000019800200           0000007500               SETTLEMENT              0091000010000010"
+        ), "{}", text);
+        let total = |name| reversal.trailer().field(name).unwrap().as_str().to_string();
+        assert_eq!(total("total_debits"), "000000010000");
+        assert_eq!(total("total_credits"), "000000010000");
+        assert_eq!(trace_map.len(), 4);
+        assert_eq!(trace_map.get("091000010000001"), Some("091000010000005"));
+        assert_eq!(trace_map.get("091000010000004"), Some("091000010000008"));
+
+        // A balanced file's offsets are not reversed, the reversal gets its own
+        let mut balanced = ach.clone();
+        balanced
+            .balance(options().offset.as_ref().unwrap())
+            .unwrap();
+        let (reversal, trace_map) = balanced.reverse(&options()).unwrap();
+        assert_eq!(reversal.entry_ids().len(), 6);
+        assert_eq!(trace_map.len(), 4);
+        assert!(reversal.validate().is_empty(), "{:?}", reversal.validate());
+    }
+
+    #[test]
+    fn test_reverse_must_balance() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let unbalanced = ReverseOptions {
+            offset: None,
+            ..options()
+        };
+        assert_eq!(
+            ach.reverse(&unbalanced).unwrap_err(),
+            "batch 0000001 of the reversal debits 10.00 and credits 25.00, so it needs \
+             settlement accounts to offset it"
+        );
+
+        // Reversing a debit and the credit it paid for balances without offsets
+        let ach: AchFile = SAMPLE
+            .replace("0000001000EMP001", "0000002500EMP001")
+            .parse()
+            .unwrap();
+        let paired = ReverseOptions {
+            traces: vec!["091000010000001".to_string(), "091000010000003".to_string()],
+            ..unbalanced
+        };
+        let (reversal, _) = ach.reverse(&paired).unwrap();
+        assert_eq!(reversal.entry_ids().len(), 2);
+        assert!(reversal.validate().is_empty(), "{:?}", reversal.validate());
+    }
+
+    #[test]
+    fn test_reverse_selected() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let options = ReverseOptions {
+            batch_numbers: vec!["2".to_string()],
+            traces: vec!["091000010000003".to_string()],
+            ..options()
+        };
+        let (reversal, trace_map) = ach.reverse(&options).unwrap();
+        assert_eq!(reversal.batch_count(), 2);
+        assert_eq!(reversal.entry_ids().len(), 4);
+        assert_eq!(reversal.batch_field(0, "service_class_code"), Some("200"));
+        assert_eq!(trace_map.get("091000010000003"), Some("091000010000005"));
+
+        let missing = ReverseOptions {
+            traces: vec!["091000019999999".to_string()],
+            ..options.clone()
+        };
+        assert_eq!(
+            ach.reverse(&missing).unwrap_err(),
+            "trace 091000019999999 is not in the file"
+        );
+        let undated = ReverseOptions {
+            effective_entry_date: "tomorrow".to_string(),
+            ..options.clone()
+        };
+        assert_eq!(
+            ach.reverse(&undated).unwrap_err(),
+            "effective_entry_date 'tomorrow' is not a YYMMDD date"
+        );
+        let untimed = ReverseOptions {
+            file_creation_time: "930".to_string(),
+            ..options.clone()
+        };
+        assert_eq!(
+            ach.reverse(&untimed).unwrap_err(),
+            "file_creation_time '930' is not a HHMM time"
+        );
+        let modified = ReverseOptions {
+            file_id_modifier: Some('Q'),
+            ..options.clone()
+        };
+        let (reversal, _) = ach.reverse(&modified).unwrap();
+        assert_eq!(
+            reversal
+                .header()
+                .field("file_id_modifier")
+                .unwrap()
+                .as_str(),
+            "Q"
+        );
+        let lowercase = ReverseOptions {
+            file_id_modifier: Some('q'),
+            ..options
+        };
+        assert_eq!(
+            ach.reverse(&lowercase).unwrap_err(),
+            "file_id_modifier 'q' is not A to Z or 0 to 9"
+        );
+    }
+
+    #[test]
+    fn test_reverse_leaves_out_prenotes() {
+        // ZERO DOLLAR TEST becomes a prenote, which moves no money
+        let ach: AchFile = SAMPLE
+            .replace("622076401251987654321", "623076401251987654321")
+            .parse()
+            .unwrap();
+        let (reversal, trace_map) = ach.reverse(&options()).unwrap();
+        assert_eq!(trace_map.len(), 3);
+        assert_eq!(trace_map.get("091000010000002"), None);
+        assert!(!reversal.to_string().contains("ZERO DOLLAR TEST"));
+        assert!(reversal.validate().is_empty(), "{:?}", reversal.validate());
+
+        let named = ReverseOptions {
+            traces: vec!["091000010000002".to_string()],
+            ..options()
+        };
+        assert_eq!(
+            ach.reverse(&named).unwrap_err(),
+            "entry 091000010000002 has transaction code 23, which cannot be reversed"
+        );
+    }
+}
@@ -0,0 +1,329 @@
+//! `operation: script`, for rules too involved for conditions. Only built with the `scripting`
+//! feature.
+//!
+//! A script runs once for each batch, with two variables in scope:
+//! - `header`, the batch header as a map of its fields
+//! - `entries`, an array with a map for each entry, whose `addenda` field is an array of maps
+//!
+//! Numeric fields are integers and everything else is text with the padding on the right
+//! trimmed. Anything the script changes in `header` or `entries`, including entries pushed or
+//! removed, is written back to the batch. `new_entry()` gives a map with every entry field,
+//! ready to fill in. Entries left with a trace of 0 are given the next trace in the file, and
+//! addenda are renumbered to follow their entry. Batches left without entries are dropped.
+
+use crate::ach_file::{
+    AchFile, AchRecord, AchRecordType, Addenda, CompanyBatch, CompanyBatchHeader, EntryDetail,
+};
+use crate::string_reader::StringReader;
+use log::{debug, info};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST, INT};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A compiled script
+#[derive(Debug)]
+pub(crate) struct Script {
+    pub path: PathBuf,
+    ast: AST,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("could not read script '{}': {}", path.display(), e))?;
+        let ast = engine().compile(source).map_err(|e| {
+            format!(
+                "{}:{}:{}: {}",
+                path.display(),
+                e.1.line().unwrap_or(0),
+                e.1.position().unwrap_or(0),
+                e.0
+            )
+        })?;
+
+        Ok(Script {
+            path: path.to_path_buf(),
+            ast,
+        })
+    }
+
+    /// Run the script over each batch of `ach_file` in turn, then recompute its controls
+    pub fn run(&self, ach_file: &mut AchFile) -> Result<(), String> {
+        let engine = engine();
+        let mut last_sequence = ach_file
+            .batches()
+            .iter()
+            .flat_map(|b| b.entries())
+            .filter_map(|e| e.field("trace").unwrap().as_str().get(8..)?.parse().ok())
+            .max()
+            .unwrap_or(0);
+
+        let mut batches = vec![];
+        for (i, batch) in ach_file.batches().iter().enumerate() {
+            let fail = |e: String| format!("{}: batch {}: {}", self.path.display(), i + 1, e);
+
+            let mut scope = Scope::new();
+            scope.push(
+                "header",
+                to_map(AchRecordType::CompanyBatchHeader, batch.header()),
+            );
+            scope.push(
+                "entries",
+                batch
+                    .entries()
+                    .iter()
+                    .map(|entry| {
+                        let mut map = to_map(AchRecordType::EntryDetail, entry);
+                        let addenda: Array = entry
+                            .addenda()
+                            .iter()
+                            .map(|a| Dynamic::from_map(to_map(AchRecordType::Addenda, a)))
+                            .collect();
+                        map.insert("addenda".into(), addenda.into());
+                        Dynamic::from_map(map)
+                    })
+                    .collect::<Array>(),
+            );
+
+            engine
+                .run_ast_with_scope(&mut scope, &self.ast)
+                .map_err(|e| fail(e.to_string()))?;
+
+            let header = scope
+                .get_value::<Map>("header")
+                .ok_or_else(|| fail("'header' is no longer a map".to_string()))?;
+            let header =
+                to_line(AchRecordType::CompanyBatchHeader, &header, "header").map_err(fail)?;
+            let odfi_id = header[79..87].to_string();
+
+            let entries = scope
+                .get_value::<Array>("entries")
+                .ok_or_else(|| fail("'entries' is no longer an array".to_string()))?;
+            let mut new_entries = vec![];
+            for (j, entry) in entries.into_iter().enumerate() {
+                let what = format!("entry {}", j + 1);
+                let mut entry = entry
+                    .try_cast::<Map>()
+                    .ok_or_else(|| fail(format!("{} is not a map", what)))?;
+                let addenda = match entry.remove("addenda") {
+                    Some(addenda) => addenda
+                        .try_cast::<Array>()
+                        .ok_or_else(|| fail(format!("addenda of {} is not an array", what)))?,
+                    None => vec![],
+                };
+
+                if entry.get("trace").and_then(|t| t.as_int().ok()) == Some(0) {
+                    last_sequence += 1;
+                    let trace = format!("{}{:07}", odfi_id, last_sequence);
+                    entry.insert("trace".into(), trace.into());
+                }
+                let indicator: INT = if addenda.is_empty() { 0 } else { 1 };
+                entry.insert("addenda_indicator".into(), indicator.into());
+                let line = to_line(AchRecordType::EntryDetail, &entry, &what).map_err(fail)?;
+                let entry_sequence = line[87..].to_string();
+                let mut new_entry = EntryDetail::from(StringReader::new(line[1..].to_string()));
+
+                for (k, addenda) in addenda.into_iter().enumerate() {
+                    let what = format!("addenda {} of {}", k + 1, what);
+                    let mut addenda = addenda
+                        .try_cast::<Map>()
+                        .ok_or_else(|| fail(format!("{} is not a map", what)))?;
+                    addenda.insert("addenda_sequence".into(), (k as INT + 1).into());
+                    addenda.insert("batch".into(), entry_sequence.clone().into());
+                    let line = to_line(AchRecordType::Addenda, &addenda, &what).map_err(fail)?;
+                    new_entry
+                        .addenda_mut()
+                        .push(Addenda::from(StringReader::new(line[1..].to_string())));
+                }
+                new_entries.push(new_entry);
+            }
+
+            if !new_entries.is_empty() {
+                batches.push(CompanyBatch::from_entries(
+                    CompanyBatchHeader::from(StringReader::new(header[1..].to_string())),
+                    new_entries,
+                ));
+            }
+        }
+
+        *ach_file.batches_mut() = batches;
+        ach_file.recompute_controls();
+        Ok(())
+    }
+}
+
+/// An engine that can only work on the values it is given: no imports, no `eval`, and bounds
+/// on how long a script can run and how much it can allocate
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.disable_symbol("eval");
+    engine.set_max_operations(10_000_000);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(10_000);
+    engine.set_max_array_size(100_000);
+    engine.set_max_map_size(1_000);
+    engine.on_print(|s| info!("script: {}", s));
+    engine.on_debug(|s, _, position| debug!("script {}: {}", position, s));
+    engine.register_fn("new_entry", new_entry);
+    engine
+}
+
+fn new_entry() -> Map {
+    let mut entry: Map = AchRecordType::EntryDetail
+        .layout()
+        .iter()
+        .map(|spec| {
+            let value = match spec.numeric {
+                true => Dynamic::from_int(0),
+                false => Dynamic::from(String::new()),
+            };
+            (spec.name.into(), value)
+        })
+        .collect();
+    entry.insert("record_type_code".into(), Dynamic::from_int(6));
+    entry.insert("addenda".into(), Dynamic::from_array(vec![]));
+    entry
+}
+
+fn to_map(record_type: AchRecordType, record: &dyn AchRecord) -> Map {
+    record_type
+        .layout()
+        .iter()
+        .filter_map(|spec| {
+            let field = record.field(spec.name)?;
+            let value = match (spec.numeric, field.as_u64()) {
+                (true, Some(n)) => Dynamic::from_int(n as INT),
+                _ => Dynamic::from(field.as_str().trim_end().to_string()),
+            };
+            Some((spec.name.into(), value))
+        })
+        .collect()
+}
+
+/// The line `map` is written as, checking it has every field of `record_type` and nothing else,
+/// and that each value fits
+fn to_line(record_type: AchRecordType, map: &Map, what: &str) -> Result<String, String> {
+    if let Some(key) = map.keys().find(|k| !record_type.has_field(k)) {
+        return Err(format!("{} has unknown field '{}'", what, key));
+    }
+
+    let mut line = String::new();
+    for spec in record_type.layout() {
+        let value = map
+            .get(spec.name)
+            .ok_or_else(|| format!("{} has no field '{}'", what, spec.name))?;
+        let text = match (value.as_int(), value.clone().into_string()) {
+            (Ok(n), _) if n >= 0 => n.to_string(),
+            (_, Ok(s)) => s,
+            _ => {
+                return Err(format!(
+                    "{}.{} is {}, expected a string or a number of at least 0",
+                    what,
+                    spec.name,
+                    value.type_name()
+                ))
+            }
+        };
+
+        line.push_str(&spec.format(&text).map_err(|e| format!("{}.{}", what, e))?);
+    }
+    Ok(line)
+}
+
+#[cfg(test)]
+mod ach_scripting_tests {
+    use crate::ach_file::AchFile;
+    use crate::ach_scripting::Script;
+    use std::path::Path;
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    #[test]
+    fn test_net_script() {
+        let script = Script::load(Path::new("test_data/cfg/rules/net.rhai")).unwrap();
+
+        // JOHN DOE and JANE ROE are the same individual, whose 2500 debit outweighs the 1000 credit
+        let mut ach: AchFile = SAMPLE.replace("EMP002", "EMP001").parse().unwrap();
+        script.run(&mut ach).unwrap();
+        let written = ach.to_string();
+        assert!(!written.contains("JOHN DOE"));
+        assert!(
+            written.contains("0000001500EMP001         JANE ROE                1091000010000003")
+        );
+        assert!(written.contains("705INVOICE 42"));
+        assert!(written.contains("ZERO DOLLAR TEST"));
+        assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+
+        // Nobody nets out in the sample itself
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        script.run(&mut ach).unwrap();
+        assert_eq!(
+            ach.to_string(),
+            SAMPLE.parse::<AchFile>().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_script_adds_entries() {
+        let dir = std::env::temp_dir().join(format!("ach_rs_script_add_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("add.rhai");
+        std::fs::write(
+            &path,
+            "if header.company_name == \"BETA LLC\" {\n\
+                 let entry = new_entry();\n\
+                 entry.transactions_code = 22;\n\
+                 entry.receiving_dfi_id = 7640125;\n\
+                 entry.check_digit = 1;\n\
+                 entry.dfi_account = \"555\";\n\
+                 entry.amount = 42;\n\
+                 entry.individual_name = \"REFUND\";\n\
+                 entries.push(entry);\n\
+             }\n\
+             entries.retain(|e| e.amount != 0);",
+        )
+        .unwrap();
+        let script = Script::load(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        script.unwrap().run(&mut ach).unwrap();
+        let written = ach.to_string();
+        assert!(!written.contains("ZERO DOLLAR TEST"));
+        // The new entry is given the next trace in the file
+        assert!(written.contains(
+            "622076401251555              0000000042               REFUND                  0091000010000005"
+        ));
+        assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let error = Script::load(Path::new("test_data/cfg/rules/missing.rhai")).unwrap_err();
+        assert!(error.starts_with("could not read script"));
+
+        let dir = std::env::temp_dir().join(format!("ach_rs_script_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let run = |source: &str| {
+            let path = dir.join("test.rhai");
+            std::fs::write(&path, source).unwrap();
+            let mut ach: AchFile = SAMPLE.parse().unwrap();
+            Script::load(&path).and_then(|s| s.run(&mut ach))
+        };
+
+        assert!(run("let x = ;").unwrap_err().contains("test.rhai:1:"));
+        assert!(run("entries[0].amount = \"ten\";")
+            .unwrap_err()
+            .contains("batch 1: entry 1.amount 'ten' is not numeric"));
+        assert!(run("entries[0].colour = 1;")
+            .unwrap_err()
+            .contains("entry 1 has unknown field 'colour'"));
+        assert!(run("import \"other\" as other;").is_err());
+        assert!(run("eval(\"1\");").is_err());
+        assert!(run("loop {}").unwrap_err().contains("Too many operations"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
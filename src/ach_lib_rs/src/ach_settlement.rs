@@ -0,0 +1,293 @@
+use crate::ach_file::{AchFile, AchRecord};
+use crate::ach_report::{dollars, signed_dollars};
+use log::error;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Bank names by routing number, from the fixed width FedACH participant directory the Federal
+/// Reserve publishes: the routing number in columns 1-9 and the name in 36-71
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FedDirectory {
+    names: HashMap<String, String>,
+}
+
+impl FedDirectory {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut names = HashMap::new();
+        for (i, line) in source.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let routing = line
+                .get(0..9)
+                .filter(|r| r.bytes().all(|b| b.is_ascii_digit()));
+            match (routing, line.get(35..71)) {
+                (Some(routing), Some(name)) => {
+                    names.insert(routing.to_string(), name.trim().to_string());
+                }
+                _ => return Err(format!("line {} is not a FedACH directory record", i + 1)),
+            }
+        }
+        Ok(FedDirectory { names })
+    }
+
+    /// Name of the bank with 9 digit routing number `routing`
+    pub fn name(&self, routing: &str) -> Option<&str> {
+        self.names.get(routing).map(|name| name.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+impl TryFrom<&Path> for FedDirectory {
+    type Error = io::Error;
+
+    fn try_from(path: &Path) -> Result<Self, Self::Error> {
+        let source = fs::read_to_string(path)?;
+        FedDirectory::parse(&source).map_err(|e| {
+            error!("{}: {}", path.display(), e);
+            io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e))
+        })
+    }
+}
+
+/// Counts and totals of a set of entries
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SettlementTotals {
+    pub debits: usize,
+    pub total_debits: u64,
+    pub credits: usize,
+    pub total_credits: u64,
+    /// Credits less debits, negative when the receiving banks pay more than they are paid
+    pub net: i64,
+}
+
+impl SettlementTotals {
+    fn add(&mut self, debit: bool, amount: u64) {
+        match debit {
+            true => {
+                self.debits += 1;
+                self.total_debits += amount;
+                self.net -= amount as i64;
+            }
+            false => {
+                self.credits += 1;
+                self.total_credits += amount;
+                self.net += amount as i64;
+            }
+        }
+    }
+
+    fn add_totals(&mut self, other: &SettlementTotals) {
+        self.debits += other.debits;
+        self.total_debits += other.total_debits;
+        self.credits += other.credits;
+        self.total_credits += other.total_credits;
+        self.net += other.net;
+    }
+}
+
+/// What one receiving bank settles
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RdfiSettlement {
+    /// `receiving_dfi_id` and `check_digit`
+    pub routing: String,
+    /// From the [FedDirectory], if one was given and has the bank
+    pub bank_name: Option<String>,
+    /// Whether the bank is the one the report is for, so its entries never leave it
+    pub on_us: bool,
+    #[serde(flatten)]
+    pub totals: SettlementTotals,
+}
+
+/// Expected settlement of one or more files by receiving bank, with the entries for the bank
+/// the report is for (on-us) set apart from those sent on to other banks (transit)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Settlement {
+    /// Routing number of the bank the report is for
+    pub routing: String,
+    /// In order of routing number
+    pub rdfis: Vec<RdfiSettlement>,
+    pub on_us: SettlementTotals,
+    pub transit: SettlementTotals,
+    pub total: SettlementTotals,
+}
+
+impl Settlement {
+    /// Settlement of the entries of every one of `files` for the bank with routing number
+    /// `routing`
+    pub fn new(files: &[AchFile], routing: &str) -> Self {
+        let routing = routing.trim();
+        let mut rdfis: BTreeMap<String, SettlementTotals> = BTreeMap::new();
+        for ach_file in files {
+            for batch in ach_file.batches() {
+                for entry in batch.entries() {
+                    let field = |name| entry.field(name).unwrap().as_str();
+                    let rdfi = format!("{}{}", field("receiving_dfi_id"), field("check_digit"));
+                    rdfis
+                        .entry(rdfi)
+                        .or_default()
+                        .add(entry.is_debit(), entry.amount());
+                }
+            }
+        }
+
+        let mut settlement = Settlement {
+            routing: routing.to_string(),
+            rdfis: vec![],
+            on_us: SettlementTotals::default(),
+            transit: SettlementTotals::default(),
+            total: SettlementTotals::default(),
+        };
+        for (rdfi, totals) in rdfis {
+            let on_us = rdfi == routing;
+            match on_us {
+                true => settlement.on_us.add_totals(&totals),
+                false => settlement.transit.add_totals(&totals),
+            }
+            settlement.total.add_totals(&totals);
+            settlement.rdfis.push(RdfiSettlement {
+                routing: rdfi,
+                bank_name: None,
+                on_us,
+                totals,
+            });
+        }
+        settlement
+    }
+
+    /// Fill in the name of each bank `directory` has
+    pub fn name_banks(&mut self, directory: &FedDirectory) {
+        for rdfi in &mut self.rdfis {
+            rdfi.bank_name = directory.name(&rdfi.routing).map(|name| name.to_string());
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+fn totals_row(
+    f: &mut Formatter<'_>,
+    routing: &str,
+    name: &str,
+    totals: &SettlementTotals,
+) -> std::fmt::Result {
+    writeln!(
+        f,
+        "{:<9}  {:<36}  {:>7}  {:>14}  {:>7}  {:>14}  {:>14}",
+        routing,
+        name,
+        totals.debits,
+        dollars(totals.total_debits),
+        totals.credits,
+        dollars(totals.total_credits),
+        signed_dollars(totals.net)
+    )
+}
+
+/// A line for each receiving bank, marked `*` when on-us, then the on-us, transit and overall
+/// totals
+impl Display for Settlement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<9}  {:<36}  {:>7}  {:>14}  {:>7}  {:>14}  {:>14}",
+            "routing", "bank", "debits", "debit total", "credits", "credit total", "net"
+        )?;
+        for rdfi in &self.rdfis {
+            let name = match (&rdfi.bank_name, rdfi.on_us) {
+                (Some(name), true) => format!("* {}", name),
+                (Some(name), false) => name.clone(),
+                (None, true) => "* on-us".to_string(),
+                (None, false) => String::new(),
+            };
+            totals_row(f, &rdfi.routing, &name, &rdfi.totals)?;
+        }
+        totals_row(f, "on-us", "", &self.on_us)?;
+        totals_row(f, "transit", "", &self.transit)?;
+        totals_row(f, "total", "", &self.total)
+    }
+}
+
+#[cfg(test)]
+mod ach_settlement_tests {
+    use crate::ach_file::AchFile;
+    use crate::ach_settlement::{FedDirectory, Settlement};
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    fn directory_line(routing: &str, name: &str) -> String {
+        format!(
+            "{}O0710003011020207000000000{:<36}{:<83}",
+            routing, name, ""
+        )
+    }
+
+    #[test]
+    fn test_settlement() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let mut settlement = Settlement::new(&[ach.clone(), ach], "076401251");
+        assert_eq!(settlement.rdfis.len(), 2);
+        let (transit, on_us) = (&settlement.rdfis[0], &settlement.rdfis[1]);
+        assert_eq!(transit.routing, "021000021");
+        assert!(!transit.on_us);
+        assert_eq!(
+            (transit.totals.debits, transit.totals.total_debits),
+            (2, 5000)
+        );
+        assert_eq!(on_us.routing, "076401251");
+        assert!(on_us.on_us);
+        assert_eq!(
+            (on_us.totals.credits, on_us.totals.total_credits),
+            (4, 2000)
+        );
+        assert_eq!(settlement.on_us.net, -13000);
+        assert_eq!(settlement.transit.net, -5000);
+        assert_eq!(settlement.total.net, -18000);
+
+        let directory = FedDirectory::parse(&format!(
+            "{}\n{}\n",
+            directory_line("021000021", "JPMORGAN CHASE BANK, NA"),
+            directory_line("011000015", "FEDERAL RESERVE BANK")
+        ))
+        .unwrap();
+        assert_eq!(directory.len(), 2);
+        settlement.name_banks(&directory);
+        assert_eq!(
+            settlement.rdfis[0].bank_name.as_deref(),
+            Some("JPMORGAN CHASE BANK, NA")
+        );
+        assert_eq!(settlement.rdfis[1].bank_name, None);
+
+        let text = settlement.to_string();
+        assert!(text.contains(
+            "021000021  JPMORGAN CHASE BANK, NA                     2           50.00        0            0.00          -50.00\n"
+        ), "{}", text);
+        assert!(text.contains("076401251  * on-us  "));
+        assert!(text.ends_with(
+            "total                                                  4          200.00        4           20.00         -180.00\n"
+        ), "{}", text);
+
+        let json: serde_json::Value = serde_json::from_str(&settlement.to_json()).unwrap();
+        assert_eq!(json["rdfis"][1]["total_debits"], 15000);
+        assert_eq!(json["rdfis"][1]["bank_name"], serde_json::Value::Null);
+
+        assert_eq!(
+            FedDirectory::parse("not a directory").unwrap_err(),
+            "line 1 is not a FedACH directory record"
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use crate::ach_file::{AchFile, AchRecord};
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// A field of an entry detail record that entries can be sorted on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKey {
+    /// The receiving bank, by its routing number
+    Rdfi,
+    Account,
+    Amount,
+    IndividualName,
+    IndividualId,
+    TransactionCode,
+    Trace,
+}
+
+impl EntryKey {
+    const ALL: &'static [EntryKey] = &[
+        EntryKey::Rdfi,
+        EntryKey::Account,
+        EntryKey::Amount,
+        EntryKey::IndividualName,
+        EntryKey::IndividualId,
+        EntryKey::TransactionCode,
+        EntryKey::Trace,
+    ];
+
+    /// Name of the field in the entry detail layout
+    pub fn field(&self) -> &'static str {
+        match self {
+            EntryKey::Rdfi => "receiving_dfi_id",
+            EntryKey::Account => "dfi_account",
+            EntryKey::Amount => "amount",
+            EntryKey::IndividualName => "individual_name",
+            EntryKey::IndividualId => "individual_id",
+            EntryKey::TransactionCode => "transactions_code",
+            EntryKey::Trace => "trace",
+        }
+    }
+}
+
+/// The key whose [EntryKey::field] is `name`
+impl FromStr for EntryKey {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let names: Vec<_> = EntryKey::ALL.iter().map(EntryKey::field).collect();
+        EntryKey::ALL
+            .iter()
+            .find(|k| k.field() == name)
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "unknown entry sort key '{}', expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// A field of a batch header that batches can be sorted on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchKey {
+    EffectiveEntryDate,
+    CompanyId,
+    CompanyName,
+    Sec,
+    EntryDesc,
+    ServiceClassCode,
+    BatchNumber,
+}
+
+impl BatchKey {
+    const ALL: &'static [BatchKey] = &[
+        BatchKey::EffectiveEntryDate,
+        BatchKey::CompanyId,
+        BatchKey::CompanyName,
+        BatchKey::Sec,
+        BatchKey::EntryDesc,
+        BatchKey::ServiceClassCode,
+        BatchKey::BatchNumber,
+    ];
+
+    /// Name of the field in the batch header layout
+    pub fn field(&self) -> &'static str {
+        match self {
+            BatchKey::EffectiveEntryDate => "effective_entry_date",
+            BatchKey::CompanyId => "company_id",
+            BatchKey::CompanyName => "company_name",
+            BatchKey::Sec => "sec",
+            BatchKey::EntryDesc => "entry_desc",
+            BatchKey::ServiceClassCode => "service_class_code",
+            BatchKey::BatchNumber => "batch_number",
+        }
+    }
+}
+
+/// The key whose [BatchKey::field] is `name`
+impl FromStr for BatchKey {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let names: Vec<_> = BatchKey::ALL.iter().map(BatchKey::field).collect();
+        BatchKey::ALL
+            .iter()
+            .find(|k| k.field() == name)
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "unknown batch sort key '{}', expected one of {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// One key of a sort, and which way it runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey<K> {
+    pub key: K,
+    pub descending: bool,
+}
+
+impl<K> SortKey<K> {
+    pub fn ascending(key: K) -> Self {
+        SortKey {
+            key,
+            descending: false,
+        }
+    }
+
+    pub fn descending(key: K) -> Self {
+        SortKey {
+            key,
+            descending: true,
+        }
+    }
+}
+
+/// A key's field name, followed by `asc` or `desc` to say which way it runs, e.g. `amount desc`
+impl<K: FromStr<Err = String>> FromStr for SortKey<K> {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let key = words.next().unwrap_or_default().parse()?;
+        let descending = match words.next() {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(other) => return Err(format!("'{}' is not asc or desc", other)),
+        };
+        if let Some(extra) = words.next() {
+            return Err(format!("unexpected '{}' after the sort key", extra));
+        }
+        Ok(SortKey { key, descending })
+    }
+}
+
+/// Order of `a` and `b` by each field in turn. Fields are compared as written, which for the
+/// zero padded numeric fields is their numeric order.
+fn compare(a: &dyn AchRecord, b: &dyn AchRecord, fields: &[(&str, bool)]) -> Ordering {
+    fields
+        .iter()
+        .map(|(name, descending)| {
+            let (a, b) = (a.field(name).unwrap(), b.field(name).unwrap());
+            let ordering = a.as_str().cmp(b.as_str());
+            match descending {
+                true => ordering.reverse(),
+                false => ordering,
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+impl AchFile {
+    /// Put the entries of each batch in order of `keys`, each key deciding between entries the
+    /// keys before it left tied. Entries tied on every key keep the order they were in, and
+    /// addenda stay with their entry. Entries stay in their batch, and traces are not
+    /// renumbered; see [AchFile::renumber].
+    pub fn sort_entries_by(&mut self, keys: &[SortKey<EntryKey>]) {
+        let fields: Vec<_> = keys.iter().map(|k| (k.key.field(), k.descending)).collect();
+        for batch in self.batches_mut() {
+            batch.entries_mut().sort_by(|a, b| compare(a, b, &fields));
+        }
+    }
+
+    /// Put the batches in order of `keys` on their headers, each key deciding between batches
+    /// the keys before it left tied. Batches tied on every key keep the order they were in.
+    /// Batch numbers are not changed; see [AchFile::renumber].
+    pub fn sort_batches_by(&mut self, keys: &[SortKey<BatchKey>]) {
+        let fields: Vec<_> = keys.iter().map(|k| (k.key.field(), k.descending)).collect();
+        self.batches_mut()
+            .sort_by(|a, b| compare(a.header(), b.header(), &fields));
+    }
+}
+
+#[cfg(test)]
+mod ach_sort_tests {
+    use crate::ach_file::AchFile;
+    use crate::ach_sort::{BatchKey, EntryKey, SortKey};
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    fn names(ach: &AchFile) -> Vec<String> {
+        ach.entry_ids()
+            .into_iter()
+            .map(|e| {
+                ach.entry_field(e, "individual_name")
+                    .unwrap()
+                    .trim()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_entries_by() {
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        ach.sort_entries_by(&[SortKey::descending(EntryKey::Amount)]);
+        assert_eq!(
+            names(&ach),
+            ["JANE ROE", "JOHN DOE", "ZERO DOLLAR TEST", "BETA CUSTOMER"]
+        );
+        // The addenda moved with its entry
+        assert!(ach
+            .to_string()
+            .contains("JANE ROE                1091000010000003\n705INVOICE 42"));
+
+        // JOHN DOE and ZERO DOLLAR TEST share a bank, so the amount breaks the tie
+        ach.sort_entries_by(&[
+            SortKey::ascending(EntryKey::Rdfi),
+            SortKey::ascending(EntryKey::Amount),
+        ]);
+        assert_eq!(
+            names(&ach),
+            ["JANE ROE", "ZERO DOLLAR TEST", "JOHN DOE", "BETA CUSTOMER"]
+        );
+
+        // Without keys everything is tied, so nothing moves
+        let before = ach.to_string();
+        ach.sort_entries_by(&[]);
+        assert_eq!(ach.to_string(), before);
+    }
+
+    #[test]
+    fn test_sort_batches_by() {
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        ach.sort_batches_by(&["effective_entry_date desc".parse().unwrap()]);
+        assert_eq!(ach.batch_field(0, "company_name"), Some("BETA LLC        "));
+        assert_eq!(ach.batch_field(0, "batch_number"), Some("0000002"));
+
+        ach.renumber().unwrap();
+        assert_eq!(ach.batch_field(0, "batch_number"), Some("0000001"));
+        assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+
+        assert_eq!(
+            "effective_entry_date".parse::<SortKey<BatchKey>>(),
+            Ok(SortKey::ascending(BatchKey::EffectiveEntryDate))
+        );
+        assert_eq!(
+            "amount sideways".parse::<SortKey<EntryKey>>().unwrap_err(),
+            "'sideways' is not asc or desc"
+        );
+        assert!("colour"
+            .parse::<SortKey<EntryKey>>()
+            .unwrap_err()
+            .starts_with("unknown entry sort key 'colour', expected one of receiving_dfi_id"));
+    }
+}
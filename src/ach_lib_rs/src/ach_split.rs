@@ -0,0 +1,358 @@
+use crate::ach_file::{AchFile, AchRecord, AchRecordType, CompanyBatchHeader, Header};
+use log::{error, info};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Where a SPLIT output is written, e.g. `out/{date}_{company_id}.ach`. Each `{name}` is
+/// filled from the field of that name in the batch header or file header, trimmed, or is one of
+/// `{date}` and `{time}` for when the file was created, or `{output}` for the output's name.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct OutputTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Text(String),
+    Placeholder(String),
+}
+
+const PLACEHOLDERS: &[&str] = &["date", "time", "output"];
+
+impl TryFrom<&str> for OutputTemplate {
+    type Error = String;
+
+    fn try_from(template: &str) -> Result<Self, Self::Error> {
+        let mut parts = vec![];
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(TemplatePart::Text(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed '{{' in output template '{}'", template))?;
+            let name = &rest[open + 1..open + close];
+            if !PLACEHOLDERS.contains(&name)
+                && !AchRecordType::CompanyBatchHeader.has_field(name)
+                && !AchRecordType::Header.has_field(name)
+            {
+                return Err(format!(
+                    "unknown placeholder '{{{}}}', expected {} or a CompanyBatchHeader or Header \
+                     field",
+                    name,
+                    PLACEHOLDERS.join(", ")
+                ));
+            }
+            parts.push(TemplatePart::Placeholder(name.to_string()));
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Text(rest.to_string()));
+        }
+        if parts.is_empty() {
+            return Err("output template is empty".to_string());
+        }
+
+        Ok(OutputTemplate { parts })
+    }
+}
+
+impl OutputTemplate {
+    /// Path of the file `batch_header`'s batch goes to. Characters from fields that do not
+    /// belong in a file name are replaced with `_`, `.` among them so that a field of `..`
+    /// cannot lead out of the template's directory.
+    pub(crate) fn render(
+        &self,
+        output: &str,
+        header: &Header,
+        batch_header: &CompanyBatchHeader,
+    ) -> PathBuf {
+        let mut path = String::new();
+        for part in &self.parts {
+            let value = match part {
+                TemplatePart::Text(text) => {
+                    path.push_str(text);
+                    continue;
+                }
+                TemplatePart::Placeholder(name) => match name.as_str() {
+                    "date" => header.field("file_creation_date").unwrap().as_str(),
+                    "time" => header.field("file_creation_time").unwrap().as_str(),
+                    "output" => output,
+                    name => batch_header
+                        .field(name)
+                        .or_else(|| header.field(name))
+                        .map(|f| f.as_str())
+                        .unwrap_or_default(),
+                },
+            };
+            path.extend(value.trim().chars().map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '_') {
+                    c
+                } else {
+                    '_'
+                }
+            }));
+        }
+        PathBuf::from(path)
+    }
+}
+
+/// One file produced by a SPLIT
+#[derive(Debug, Clone)]
+pub struct SplitFile {
+    /// Label of the transformation that produced it
+    pub label: String,
+    /// Name of the output in `outputs:`
+    pub output: String,
+    pub path: PathBuf,
+    pub ach_file: AchFile,
+}
+
+/// A produced file as listed in the manifest
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ManifestEntry {
+    pub output: String,
+    pub path: PathBuf,
+    pub batches: usize,
+    pub entries: usize,
+    pub total_debits: u64,
+    pub total_credits: u64,
+}
+
+impl From<&SplitFile> for ManifestEntry {
+    fn from(file: &SplitFile) -> Self {
+        let total = |name| {
+            let field = file.ach_file.trailer().field(name);
+            field.and_then(|f| f.as_u64()).unwrap_or(0)
+        };
+        ManifestEntry {
+            output: file.output.clone(),
+            path: file.path.clone(),
+            batches: file.ach_file.batch_count(),
+            entries: file.ach_file.entry_ids().len(),
+            total_debits: total("total_debits"),
+            total_credits: total("total_credits"),
+        }
+    }
+}
+
+/// Split every batch of `ach_file` into the file `template` gives it, without a config. Batches
+/// that render the same path share a file. Each file is named after its path in the manifest,
+/// which is labelled `split`.
+pub fn split_by(ach_file: &mut AchFile, template: &str) -> Result<Vec<SplitFile>, String> {
+    let template = OutputTemplate::try_from(template)?;
+    let files =
+        ach_file.split(|header, batch_header| Some(template.render("split", header, batch_header)));
+    Ok(files
+        .into_iter()
+        .map(|(path, ach_file)| SplitFile {
+            label: "split".to_string(),
+            output: path.display().to_string(),
+            path,
+            ach_file,
+        })
+        .collect())
+}
+
+/// Manifest of the files the transformation `label` produced, written beside the first of them
+pub fn manifest_path(label: &str, files: &[SplitFile]) -> PathBuf {
+    let dir = files
+        .iter()
+        .find(|f| f.label == label)
+        .and_then(|f| f.path.parent())
+        .unwrap_or(Path::new(""));
+    dir.join(format!("{}.manifest.json", label))
+}
+
+/// Write every file, then a manifest of them for each transformation that produced any,
+/// returning the paths written. Each is written to a temporary file first and renamed into
+/// place, so nothing watching for them sees a partly written file, and the manifests only
+/// appear once all their files are in place.
+pub fn write_outputs(files: &[SplitFile]) -> io::Result<Vec<PathBuf>> {
+    let mut written = vec![];
+    let mut labels: Vec<&str> = vec![];
+    for file in files {
+        write_atomic(&file.path, file.ach_file.to_string().as_bytes())?;
+        written.push(file.path.clone());
+        if !labels.contains(&file.label.as_str()) {
+            labels.push(&file.label);
+        }
+    }
+
+    for label in labels {
+        let entries: Vec<ManifestEntry> = files
+            .iter()
+            .filter(|f| f.label == label)
+            .map(ManifestEntry::from)
+            .collect();
+        let path = manifest_path(label, files);
+        write_atomic(&path, serde_json::to_string_pretty(&entries)?.as_bytes())?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
+
+/// Write `contents` to `path` by way of a hidden temporary file beside it
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    if !dir.as_os_str().is_empty() {
+        fs::create_dir_all(dir)?;
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = dir.join(format!(".{}.tmp", name));
+
+    let result = fs::File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, path));
+    match result {
+        Ok(_) => {
+            info!("Wrote {}", path.display());
+            Ok(())
+        }
+        Err(e) => {
+            error!("Could not write {}: {}", path.display(), e);
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod ach_split_tests {
+    use crate::ach_config::{lint, ConfigFormat};
+    use crate::ach_file::AchFile;
+    use crate::ach_split::{split_by, write_outputs, ManifestEntry, OutputTemplate};
+    use crate::ach_transformations::Transformations;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    #[test]
+    fn test_output_template() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let template = OutputTemplate::try_from("out/{date}_{company_name}_{output}.ach").unwrap();
+        assert_eq!(
+            template.render("acme", ach.header(), ach.batches()[0].header()),
+            PathBuf::from("out/261018_ACME_CORP_acme.ach")
+        );
+        let template = OutputTemplate::try_from("out/{company_name}/{output}.ach").unwrap();
+        assert_eq!(
+            template.render("..", ach.header(), ach.batches()[0].header()),
+            PathBuf::from("out/ACME_CORP/__.ach")
+        );
+
+        assert_eq!(
+            OutputTemplate::try_from("out/{colour}.ach"),
+            Err(
+                "unknown placeholder '{colour}', expected date, time, output or a \
+                 CompanyBatchHeader or Header field"
+                    .to_string()
+            )
+        );
+        assert!(OutputTemplate::try_from("out/{date.ach").is_err());
+    }
+
+    #[test]
+    fn test_split_outputs() {
+        let dir = std::env::temp_dir().join(format!("ach_rs_split_{}", std::process::id()));
+        let config = format!(
+            "by_client:\n    operation: split\n    outputs:\n        acme:\n            \
+             path: {0}/{{date}}_{{company_id}}.ach\n            where: company_name == ACME CORP\n        \
+             rest: {0}/{{date}}_rest.ach\n",
+            dir.display()
+        );
+        let transformations = Transformations::from_config(&config, ConfigFormat::Yaml).unwrap();
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let outcome = transformations.apply(&mut ach).unwrap();
+
+        assert_eq!(ach.batch_count(), 0);
+        assert_eq!(outcome.outputs.len(), 2);
+        let written = write_outputs(&outcome.outputs).unwrap();
+        assert_eq!(
+            written,
+            vec![
+                dir.join("261018_1234567890.ach"),
+                dir.join("261018_rest.ach"),
+                dir.join("by_client.manifest.json"),
+            ]
+        );
+
+        let acme = AchFile::try_from(written[0].as_path()).unwrap();
+        assert!(acme.validate().is_empty(), "{:?}", acme.validate());
+        assert_eq!(acme.entry_ids().len(), 3);
+        let manifest: Vec<serde_json::Value> =
+            serde_json::from_str(&fs::read_to_string(&written[2]).unwrap()).unwrap();
+        assert_eq!(manifest[1]["output"], "rest");
+        assert_eq!(manifest[1]["entries"], 1);
+        assert_eq!(manifest[1]["total_debits"], 7500);
+        assert_eq!(ManifestEntry::from(&outcome.outputs[0]).total_credits, 1000);
+        // Nothing is left behind from the temporary files
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Both outputs render the same path, so nothing is split off
+        let config = "by_client:\n    operation: split\n    outputs:\n        acme:\n            \
+             path: out/{date}.ach\n            where: company_name == ACME CORP\n        \
+             rest: out/{date}.ach\n";
+        let transformations = Transformations::from_config(config, ConfigFormat::Yaml).unwrap();
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let error = transformations.apply(&mut ach).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "'by_client': outputs 'acme' and 'rest' are both written to out/261018.ach"
+        );
+        assert_eq!(ach.to_string(), SAMPLE);
+    }
+
+    #[test]
+    fn test_split_by() {
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let files = split_by(&mut ach, "out/{company_id}.ach").unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f.path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("out/1234567890.ach"),
+                PathBuf::from("out/9876543210.ach")
+            ]
+        );
+        assert_eq!(files[1].output, "out/9876543210.ach");
+        assert!(files[1].ach_file.validate().is_empty());
+        assert_eq!(ach.batch_count(), 0);
+
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        assert!(split_by(&mut ach, "out/{colour}.ach").is_err());
+        assert_eq!(ach.batch_count(), 2);
+    }
+
+    #[test]
+    fn test_lint_split() {
+        let diagnostics = lint(
+            "by_client:\n    operation: split\n    outputs:\n        all: out/{colour}.ach\n        \
+             acme:\n            path: out/acme.ach\n            where: company_id == 1\n",
+            ConfigFormat::Yaml,
+        );
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "<config>:4:14: error: 'by_client': output 'all': unknown placeholder '{colour}', \
+                 expected date, time, output or a CompanyBatchHeader or Header field",
+                "<config>:5:9: warning: 'by_client': output 'all' takes every batch, so 'acme' \
+                 and any after it are never used",
+            ]
+        );
+
+        let diagnostics = lint("by_client:\n    operation: split\n", ConfigFormat::Yaml);
+        assert_eq!(diagnostics[0].message, "'by_client': SPLIT needs outputs");
+    }
+}
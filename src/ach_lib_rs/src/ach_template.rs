@@ -0,0 +1,138 @@
+//! Reports written as [Tera](https://keats.github.io/tera/) templates. Only built with the
+//! `templates` feature.
+//!
+//! Templates are rendered with these variables:
+//! - `header`: each field of the file header by name, such as `header.immediate_dest_name`
+//! - `batches`: each batch, with
+//!   - `header` and `trailer`: the fields of its header and trailer
+//!   - `entries`: each entry, with its fields, `addenda` (the fields of each of its addenda),
+//!     `debit` (true for debits) and `dollars` (the amount as dollars and cents, e.g. `25.00`)
+//!   - `controls`: its [BatchControls], such as `controls.total_debits.computed`
+//! - `trailer`: the fields of the file trailer
+//! - `controls`: the [ControlReport] of the file, such as `controls.net`, `controls.companies`
+//!   or `controls.total_credits.matches`
+//! - `mismatches`: [ControlReport::mismatches], each control a trailer gets wrong
+//! - `issues`: what [AchFile::validate] finds, each with `line`, `record_type`, `field` and
+//!   `message`
+//! - `valid`: true when there are no issues
+//!
+//! Field values have their padding trimmed, and amounts are in cents.
+//!
+//! [BatchControls]: crate::ach_report::BatchControls
+
+use crate::ach_file::AchFile;
+use crate::ach_report::{dollars, ControlReport};
+use serde_json::{json, Value};
+use std::error::Error;
+use tera::{Context, Tera};
+
+/// `value` with the padding of every string trimmed
+fn trimmed(value: Value) -> Value {
+    match value {
+        Value::String(s) => Value::String(s.trim().to_string()),
+        Value::Array(values) => Value::Array(values.into_iter().map(trimmed).collect()),
+        Value::Object(map) => {
+            Value::Object(map.into_iter().map(|(k, v)| (k, trimmed(v))).collect())
+        }
+        value => value,
+    }
+}
+
+impl AchFile {
+    /// What a template is rendered with, as the module documentation describes
+    pub fn template_context(&self) -> Value {
+        let mut context = trimmed(self.to_json_value());
+        let report = ControlReport::from(self);
+        let issues = self.validate();
+
+        let batches = context["batches"].as_array_mut().unwrap();
+        for ((batch, controls), company_batch) in
+            batches.iter_mut().zip(&report.batches).zip(self.batches())
+        {
+            let entries = batch["entries"].as_array_mut().unwrap();
+            for (entry, entry_detail) in entries.iter_mut().zip(company_batch.entries()) {
+                entry["debit"] = json!(entry_detail.is_debit());
+                entry["dollars"] = json!(dollars(entry_detail.amount()));
+            }
+            batch["controls"] = json!(controls);
+        }
+        context["mismatches"] = json!(report.mismatches());
+        context["controls"] = json!(report);
+        context["valid"] = json!(issues.is_empty());
+        context["issues"] = json!(issues);
+        context
+    }
+
+    /// The file written out by the Tera `template`, escaping HTML in the values it shows when
+    /// `escape_html` is set
+    pub fn render_template(&self, template: &str, escape_html: bool) -> Result<String, String> {
+        let context = Context::from_value(self.template_context()).unwrap();
+        Tera::one_off(template, &context, escape_html).map_err(|e| {
+            // Tera puts what went wrong in the errors it wraps
+            let mut message = e.to_string();
+            let mut source = e.source();
+            while let Some(e) = source {
+                message.push_str(&format!(": {}", e));
+                source = e.source();
+            }
+            message
+        })
+    }
+}
+
+#[cfg(test)]
+mod ach_template_tests {
+    use crate::ach_file::AchFile;
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    #[test]
+    fn test_render_template() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let template = "File to {{ header.immediate_dest_name }}: {{ batches | length }} batches, \
+            net {{ controls.net }}, {% if valid %}valid{% else %}invalid{% endif %}
+{% for batch in batches %}{{ batch.header.company_name }} {{ batch.controls.total_debits.computed }}
+{% for entry in batch.entries %}  {{ entry.individual_name }} {{ entry.dollars }}{% if entry.debit %} DR{% endif %}{% for addenda in entry.addenda %} ({{ addenda.payment_related_info }}){% endfor %}
+{% endfor %}{% endfor %}";
+        assert_eq!(
+            ach.render_template(template, false).unwrap(),
+            "File to DEST BANK: 2 batches, net -9000, valid
+ACME CORP 2500
+  JOHN DOE 10.00
+  ZERO DOLLAR TEST 0.00
+  JANE ROE 25.00 DR (INVOICE 42)
+BETA LLC 7500
+  BETA CUSTOMER 75.00 DR
+"
+        );
+
+        let broken: AchFile = SAMPLE
+            .replace("622076401251123456789", "622076401259123456789")
+            .parse()
+            .unwrap();
+        assert_eq!(
+            broken
+                .render_template(
+                    "{% for issue in issues %}{{ issue.line }} {{ issue.field }}{% endfor %}",
+                    false
+                )
+                .unwrap(),
+            "3 check_digit"
+        );
+
+        let html: AchFile = SAMPLE
+            .replace("ACME CORP       ", "A&B <CORP>      ")
+            .parse()
+            .unwrap();
+        assert_eq!(
+            html.render_template("{{ batches.0.header.company_name }}", true)
+                .unwrap(),
+            "A&amp;B &lt;CORP&gt;"
+        );
+
+        let error = ach
+            .render_template("{{ batches.0.nothing }}", false)
+            .unwrap_err();
+        assert!(error.contains("nothing"), "{}", error);
+    }
+}
@@ -1,29 +1,106 @@
-use crate::ach_file::{AchFile, AchRecord, AchRecordType, Field, Header};
-use log::error;
-use std::fs::File;
+use crate::ach_change_log::ChangeLog;
+use crate::ach_config;
+use crate::ach_config::{ConfigFormat, Diagnostic, RawEntry, RawTransformation, Severity};
+use crate::ach_file::{
+    AchFile, AchRecord, AchRecordType, BankProfile, CompanyBatch, CompanyBatchHeader, EntryDetail,
+    EntryId, Field, TraceMap,
+};
+use crate::ach_offset::{OffsetAccount, OffsetOptions};
+use crate::ach_operations::{OperationContext, OperationRegistry, TransformOperation};
+#[cfg(feature = "scripting")]
+use crate::ach_scripting::Script;
+use crate::ach_sort::{BatchKey, EntryKey, SortKey};
+use crate::ach_split::{OutputTemplate, SplitFile};
+use log::{error, info, warn};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
 use std::io;
-use std::io::{BufRead, BufReader, ErrorKind};
+use std::io::ErrorKind;
 use std::path::Path;
-use std::str::Split;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Transformations {
-    base_ach_file: AchFile,
     transformations: Vec<Transformation>,
 }
 
+/// Everything applying [Transformations] produced, other than the changes made to the file itself
+#[derive(Debug, Default)]
+pub struct TransformOutcome {
+    /// Entries and batches set aside by `quarantine` operations, as a complete ACH file of their own
+    pub quarantine: Option<AchFile>,
+    /// Original and new trace numbers of every entry whose trace was rewritten
+    pub trace_map: TraceMap,
+    /// Every field each transformation changed, and every record it removed or added
+    pub change_log: ChangeLog,
+    /// Files `split` operations produced, to be written with [crate::ach_split::write_outputs]
+    pub outputs: Vec<SplitFile>,
+    /// Batches `consolidate` operations merged into others
+    pub collapsed_batches: usize,
+}
+
 impl Transformations {
-    fn with_ach_file(mut self, base_ach_file: AchFile) {
-        self.base_ach_file = base_ach_file;
+    /// Apply each transformation to `ach_file`, in the order they appear in the config
+    pub fn apply(&self, ach_file: &mut AchFile) -> io::Result<TransformOutcome> {
+        let mut outcome = TransformOutcome::default();
+        for transformation in &self.transformations {
+            let before = ach_file.clone();
+            let trace_map = transformation.apply(ach_file, &mut outcome)?;
+            outcome
+                .change_log
+                .record_diff(&transformation.label, &before, ach_file, &trace_map);
+            outcome.trace_map.extend(trace_map);
+        }
+        Ok(outcome)
+    }
+
+    /// Work out what [Transformations::apply] would do to `ach_file` without changing it.
+    /// The outcome's change log lists every change that would be made.
+    pub fn dry_run(&self, ach_file: &AchFile) -> io::Result<TransformOutcome> {
+        self.apply(&mut ach_file.clone())
     }
 }
 
-impl TryFrom<&Path> for Transformations {
-    type Error = io::Error;
+impl Transformations {
+    /// Build transformations from parsed config, recording every problem found in
+    /// `diagnostics` rather than stopping at the first
+    pub(crate) fn from_raw(
+        raw: Vec<RawTransformation>,
+        operations: &OperationRegistry,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Transformations {
+        let mut transformations = vec![];
+        for t in raw {
+            let first = diagnostics.len();
+            let file = t.file.clone();
+            transformations.push(Transformation::from_raw(t, operations, diagnostics));
+            for diagnostic in &mut diagnostics[first..] {
+                diagnostic.file = file.clone();
+            }
+        }
+        Transformations { transformations }
+    }
 
-    fn try_from(path: &Path) -> Result<Self, Self::Error> {
-        let lines: Vec<String> = BufReader::new(match File::open(path) {
-            Ok(f) => f,
+    /// Load transformations from config `source` written in `format`. Includes are read from
+    /// `./cfg`.
+    pub fn from_config(source: &str, format: ConfigFormat) -> io::Result<Self> {
+        Transformations::from_config_with(source, format, &OperationRegistry::default())
+    }
+
+    /// [Transformations::from_config], for a config that may also name `operations`
+    pub fn from_config_with(
+        source: &str,
+        format: ConfigFormat,
+        operations: &OperationRegistry,
+    ) -> io::Result<Self> {
+        Transformations::load(source, format, None, operations)
+    }
+
+    /// Load transformations from the config file at `path`, which may also name `operations`
+    pub fn from_file_with(path: &Path, operations: &OperationRegistry) -> io::Result<Self> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
             Err(e) => {
                 error!(
                     "Could not open transform file: {} \n Path: {}",
@@ -32,169 +109,1175 @@ impl TryFrom<&Path> for Transformations {
                 );
                 return Err(e);
             }
-        })
-        .lines()
-        .filter(|l: &io::Result<String>| {
-            let l = l.as_ref().to_owned().unwrap().trim();
-            !(l.starts_with("#") || l.len() == 0)
-        })
-        .map(|l| l.unwrap())
-        .collect();
+        };
+
+        Transformations::load(&source, ConfigFormat::from(path), Some(path), operations)
+    }
 
-        Ok(Transformations::try_from(lines)?)
+    fn load(
+        source: &str,
+        format: ConfigFormat,
+        file: Option<&Path>,
+        operations: &OperationRegistry,
+    ) -> io::Result<Self> {
+        let (transformations, diagnostics) = ach_config::check(source, format, file, operations);
+
+        let mut first_error = None;
+        for diagnostic in diagnostics {
+            match diagnostic.severity {
+                Severity::Error => {
+                    error!("{}", diagnostic);
+                    first_error.get_or_insert(diagnostic);
+                }
+                Severity::Warning => warn!("{}", diagnostic),
+            }
+        }
+
+        match (first_error, transformations) {
+            (None, Some(transformations)) => Ok(transformations),
+            (first_error, _) => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                first_error.map(|d| d.to_string()).unwrap_or_default(),
+            )),
+        }
     }
 }
 
-impl TryFrom<Vec<String>> for Transformations {
+impl TryFrom<&Path> for Transformations {
     type Error = io::Error;
 
-    fn try_from(mut lines: Vec<String>) -> Result<Self, Self::Error> {
-        // first line should be a YAML beginning of file tag, but we should ignore it.
-        if lines[0] == "---" {
-            lines.remove(0);
-        }
+    fn try_from(path: &Path) -> Result<Self, Self::Error> {
+        Transformations::from_file_with(path, &OperationRegistry::default())
+    }
+}
 
-        let base_indent_size = lines[0].len() - lines[0].trim().len();
-        let mut transformation_lines = vec![];
-        let mut transformations = vec![];
+impl TryFrom<Vec<String>> for Transformations {
+    type Error = io::Error;
 
-        for line in lines {
-            let indent_size = line.len() - line.trim().len();
-            if indent_size <= base_indent_size && transformation_lines.len() > 0 {
-                transformations.push(Transformation::try_from(transformation_lines.to_owned())?);
-                transformation_lines.clear();
-            }
-            transformation_lines.push(line.trim().to_string());
-        }
-        Ok(Transformations {
-            base_ach_file: Default::default(),
-            transformations,
-        })
+    /// `lines` of a YAML config
+    fn try_from(lines: Vec<String>) -> Result<Self, Self::Error> {
+        Transformations::from_config(&lines.join("\n"), ConfigFormat::Yaml)
     }
 }
 
 #[derive(Debug)]
-struct Transformation {
-    label: String,
+pub(crate) struct Transformation {
+    pub(crate) label: String,
     operation: Vec<Operation>,
     on: Vec<AchRecordType>,
     conditions: Vec<Condition>,
+    #[allow(dead_code)] // REPLACE is not wired up yet
     replacments: Vec<Replacement>,
+    /// `by:` keys REBATCH groups entries on: `direction`, or an EntryDetail or CompanyBatchHeader field
+    group_by: Vec<String>,
+    /// `effective_date:` given to every batch REBATCH builds, as YYMMDD
+    effective_date: Option<String>,
+    /// Bank REROUTE sends the file through, from `immediate_dest:`, `immediate_dest_name:`,
+    /// `immediate_orig:` and `odfi_id:`
+    bank: Option<BankProfile>,
+    /// Where SPLIT sends batches, from `outputs:`
+    outputs: Vec<SplitOutput>,
+    /// Script SCRIPT runs over each batch, from `script:`
+    #[cfg(feature = "scripting")]
+    script: Option<Script>,
+    /// Settlement accounts and name OFFSET balances each batch with, from `accounts:` and
+    /// `offset_name:`
+    offset: Option<OffsetOptions>,
+    /// Keys SORT orders the entries of each batch by, from `entries_by:`
+    sort_entries: Vec<SortKey<EntryKey>>,
+    /// Keys SORT orders batches by, from `batches_by:`
+    sort_batches: Vec<SortKey<BatchKey>>,
+    /// Whether SORT renumbers batches and traces afterwards, from `renumber:`
+    renumber: bool,
 }
 
-impl TryFrom<Vec<String>> for Transformation {
-    type Error = io::Error;
-    fn try_from(mut lines: Vec<String>) -> Result<Self, Self::Error> {
-        // first line should be the label, always
+impl Transformation {
+    /// Apply each operation in turn, returning how any trace numbers were changed
+    fn apply(
+        &self,
+        ach_file: &mut AchFile,
+        outcome: &mut TransformOutcome,
+    ) -> io::Result<TraceMap> {
+        let mut trace_map = TraceMap::default();
+
+        for operation in &self.operation {
+            match operation {
+                Operation::DROP | Operation::QUARANTINE => {
+                    let removed = self.remove_matching(ach_file)?;
+                    info!(
+                        "'{}': {:?} took {} entries from {} batches",
+                        self.label,
+                        operation,
+                        removed.iter().map(|b| b.entries().len()).sum::<usize>(),
+                        removed.len()
+                    );
+
+                    if let Operation::QUARANTINE = operation {
+                        let quarantine = outcome.quarantine.get_or_insert_with(|| {
+                            AchFile::from_batches(ach_file.header().clone(), vec![])
+                        });
+                        quarantine.batches_mut().extend(removed);
+                        quarantine.recompute_controls();
+                    }
+                }
+                Operation::REBATCH => trace_map.extend(self.rebatch(ach_file)?),
+                Operation::REROUTE => trace_map.extend(self.reroute(ach_file)?),
+                Operation::SCRIPT => self.script(ach_file)?,
+                Operation::OFFSET => self.offset(ach_file)?,
+                Operation::SORT => trace_map.extend(self.sort(ach_file)?),
+                Operation::CONSOLIDATE => {
+                    let batches_before = ach_file.batch_count();
+                    let collapsed = ach_file.consolidate_batches();
+                    info!(
+                        "'{}': consolidated {} batches into {}, {} collapsed",
+                        self.label,
+                        batches_before,
+                        ach_file.batch_count(),
+                        collapsed
+                    );
+                    outcome.collapsed_batches += collapsed;
+                }
+                Operation::CUSTOM(custom) => {
+                    let mut context = OperationContext {
+                        transformation: self,
+                        ach_file: &mut *ach_file,
+                        change_log: &mut outcome.change_log,
+                    };
+                    if let Err(e) = custom.apply(&mut context) {
+                        error!("'{}': {}: {}", self.label, custom.name(), e);
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!("'{}': {}: {}", self.label, custom.name(), e),
+                        ));
+                    }
+                    ach_file.batches_mut().retain(|b| !b.entries().is_empty());
+                    ach_file.recompute_controls();
+                }
+                Operation::SPLIT => self.split(ach_file, outcome)?,
+                Operation::REPLACE => {
+                    warn!(
+                        "'{}': {:?} is not supported yet, skipping",
+                        self.label, operation
+                    )
+                }
+            }
+        }
+
+        Ok(trace_map)
+    }
+
+    #[cfg(feature = "scripting")]
+    fn script(&self, ach_file: &mut AchFile) -> io::Result<()> {
+        let script = self
+            .script
+            .as_ref()
+            .expect("SCRIPT is only loaded with a script");
+        script.run(ach_file).map_err(|e| {
+            error!("'{}': {}", self.label, e);
+            io::Error::new(ErrorKind::InvalidData, format!("'{}': {}", self.label, e))
+        })
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    fn script(&self, _ach_file: &mut AchFile) -> io::Result<()> {
+        unreachable!("SCRIPT is rejected at load without the scripting feature")
+    }
+
+    /// `message` from the library as the error of this transformation, logged
+    fn invalid(&self, message: String) -> io::Error {
+        error!("'{}': {}", self.label, message);
+        io::Error::new(
+            ErrorKind::InvalidData,
+            format!("'{}': {}", self.label, message),
+        )
+    }
+
+    fn offset(&self, ach_file: &mut AchFile) -> io::Result<()> {
+        let options = self
+            .offset
+            .as_ref()
+            .expect("OFFSET is only loaded with accounts");
+        let outcome = ach_file.balance(options).map_err(|e| self.invalid(e))?;
+        info!(
+            "'{}': removed {} offsets and added {}",
+            self.label, outcome.removed, outcome.added
+        );
+        Ok(())
+    }
+
+    fn sort(&self, ach_file: &mut AchFile) -> io::Result<TraceMap> {
+        ach_file.sort_batches_by(&self.sort_batches);
+        ach_file.sort_entries_by(&self.sort_entries);
+        info!(
+            "'{}': sorted {} batches and their entries",
+            self.label,
+            ach_file.batch_count()
+        );
+        match self.renumber {
+            true => ach_file.renumber().map_err(|e| self.invalid(e)),
+            false => Ok(TraceMap::default()),
+        }
+    }
+
+    /// Move each batch into the file of the first output whose conditions its header meets,
+    /// leaving batches no output takes in `ach_file`. When two outputs would be written to the
+    /// same path, `ach_file` is left as it was.
+    fn split(&self, ach_file: &mut AchFile, outcome: &mut TransformOutcome) -> io::Result<()> {
+        let mut remaining = ach_file.clone();
+        let files = remaining.split(|header, batch_header| {
+            let output = self
+                .outputs
+                .iter()
+                .find(|o| o.conditions.iter().all(|c| c.test(batch_header)))?;
+            let template = output.template.as_ref()?;
+            let path = template.render(&output.name, header, batch_header);
+            Some((output.name.clone(), path))
+        });
+
+        for (i, ((output, path), _)) in files.iter().enumerate() {
+            if let Some(((other, _), _)) = files[..i].iter().find(|((_, p), _)| p == path) {
+                let message = format!(
+                    "'{}': outputs '{}' and '{}' are both written to {}",
+                    self.label,
+                    other,
+                    output,
+                    path.display()
+                );
+                error!("{}", message);
+                return Err(io::Error::new(ErrorKind::InvalidData, message));
+            }
+        }
+        *ach_file = remaining;
+
+        info!(
+            "'{}': split {} batches into {} files, {} left",
+            self.label,
+            files.iter().map(|(_, f)| f.batch_count()).sum::<usize>(),
+            files.len(),
+            ach_file.batch_count()
+        );
+        outcome
+            .outputs
+            .extend(files.into_iter().map(|((output, path), file)| SplitFile {
+                label: self.label.clone(),
+                output,
+                path,
+                ach_file: file,
+            }));
+        Ok(())
+    }
+
+    fn rebatch(&self, ach_file: &mut AchFile) -> io::Result<TraceMap> {
+        let batches_before = ach_file.batches().len();
+        let trace_map = ach_file
+            .rebatch(
+                |h, e| self.rebatch_key(h, e),
+                self.effective_date.as_deref(),
+            )
+            .map_err(|e| self.invalid(e))?;
+        info!(
+            "'{}': rebatched {} batches into {}",
+            self.label,
+            batches_before,
+            ach_file.batches().len()
+        );
+        Ok(trace_map)
+    }
+
+    fn reroute(&self, ach_file: &mut AchFile) -> io::Result<TraceMap> {
+        let invalid = |message: String| {
+            error!("{}", message);
+            io::Error::new(ErrorKind::InvalidData, message)
+        };
+
+        let profile = match &self.bank {
+            Some(profile) => profile,
+            None => {
+                return Err(invalid(format!(
+                    "'{}': REROUTE needs immediate_dest and immediate_dest_name",
+                    self.label
+                )))
+            }
+        };
+
+        // Problems the file already had are not the reroute's doing, so only new ones stop it.
+        // Messages quote traces and routing numbers the reroute rewrites, so they are matched
+        // on where they are, not on what they say
+        let existing: Vec<_> = ach_file
+            .validate()
+            .into_iter()
+            .map(|issue| (issue.line, issue.record_type, issue.field))
+            .collect();
+        let mut rerouted = ach_file.clone();
+        let trace_map = rerouted.reroute(profile);
+
+        let issues: Vec<_> = rerouted
+            .validate()
+            .into_iter()
+            .filter(|issue| !existing.contains(&(issue.line, issue.record_type, issue.field)))
+            .collect();
+        if !issues.is_empty() {
+            for issue in &issues {
+                error!("{}", issue);
+            }
+            return Err(invalid(format!(
+                "'{}': rerouted file does not validate, first problem: {}",
+                self.label, issues[0]
+            )));
+        }
+        *ach_file = rerouted;
+
+        info!(
+            "'{}': rerouted through {} ({}), {} traces rewritten",
+            self.label,
+            profile.immediate_dest.trim(),
+            profile.immediate_dest_name,
+            trace_map.len()
+        );
+        Ok(trace_map)
+    }
+
+    fn rebatch_key(&self, batch_header: &CompanyBatchHeader, entry: &EntryDetail) -> String {
+        self.group_by
+            .iter()
+            .map(|key| match key.as_str() {
+                "direction" if entry.is_debit() => "debit",
+                "direction" => "credit",
+                name => entry
+                    .field(name)
+                    .or_else(|| batch_header.field(name))
+                    .map(|f| f.as_str().trim())
+                    .unwrap_or_default(),
+            })
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+
+    fn matches(&self, record: &dyn AchRecord) -> bool {
+        self.conditions.iter().all(|c| c.test(record))
+    }
+
+    /// Whether `entry` or its batch header, depending on `on:`, matches the conditions
+    pub(crate) fn matches_entry(&self, ach_file: &AchFile, entry: EntryId) -> bool {
+        let batch = match ach_file.batches().get(entry.batch) {
+            Some(batch) => batch,
+            None => return false,
+        };
+        let entry = match batch.entries().get(entry.entry) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if self.on.is_empty() {
+            return self.matches(entry);
+        }
+        self.on.iter().any(|record_type| match record_type {
+            AchRecordType::EntryDetail => self.matches(entry),
+            AchRecordType::CompanyBatchHeader => self.matches(batch.header()),
+            _ => false,
+        })
+    }
+
+    /// Take every entry or batch matching the conditions out of `ach_file`, returning them as
+    /// batches under a copy of the header they came from. Batches left empty are dropped and
+    /// all controls recomputed.
+    fn remove_matching(&self, ach_file: &mut AchFile) -> io::Result<Vec<CompanyBatch>> {
+        let mut removed = vec![];
+
+        for record_type in &self.on {
+            match record_type {
+                AchRecordType::EntryDetail => {
+                    for batch in ach_file.batches_mut() {
+                        let (matched, kept) = batch
+                            .entries_mut()
+                            .drain(..)
+                            .partition::<Vec<_>, _>(|e| self.matches(e));
+                        *batch.entries_mut() = kept;
+                        if !matched.is_empty() {
+                            removed
+                                .push(CompanyBatch::from_entries(batch.header().clone(), matched));
+                        }
+                    }
+                }
+                AchRecordType::CompanyBatchHeader => {
+                    let (matched, kept) = ach_file
+                        .batches_mut()
+                        .drain(..)
+                        .partition::<Vec<_>, _>(|b| self.matches(b.header()));
+                    *ach_file.batches_mut() = kept;
+                    removed.extend(matched);
+                }
+                t => {
+                    error!("'{}': cannot remove {:?} records", self.label, t);
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "'{}': cannot remove {:?} records, only EntryDetail or CompanyBatchHeader",
+                            self.label, t
+                        ),
+                    ));
+                }
+            }
+        }
+
+        ach_file.batches_mut().retain(|b| !b.entries().is_empty());
+        ach_file.recompute_controls();
+        Ok(removed)
+    }
+}
+
+const KEYS: &[&str] = &[
+    "operation",
+    "on",
+    "where",
+    "by",
+    "effective_date",
+    "immediate_dest",
+    "immediate_dest_name",
+    "immediate_orig",
+    "odfi_id",
+    "script",
+    "outputs",
+    "offset_name",
+    "accounts",
+    "entries_by",
+    "batches_by",
+    "renumber",
+];
+const BANK_KEYS: &[&str] = &[
+    "immediate_dest",
+    "immediate_dest_name",
+    "immediate_orig",
+    "odfi_id",
+];
+const RECORD_TYPES: &[&str] = &[
+    "Header",
+    "CompanyBatchHeader",
+    "EntryDetail",
+    "Addenda",
+    "CompanyBatchTrailer",
+    "Trailer",
+];
+
+impl Transformation {
+    /// Check everything that can be known before the transformation meets a file: keys,
+    /// operations, record types, the fields conditions compare and the values they compare
+    /// them with, and that each operation has the settings it needs.
+    fn from_raw(
+        raw: RawTransformation,
+        operations: &OperationRegistry,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Self {
+        let label = raw.label.text.clone();
         let mut transformation = Transformation {
-            label: lines[0].trim().trim_end_matches(":").to_string(),
+            label: label.clone(),
             operation: vec![],
             on: vec![],
             conditions: vec![],
             replacments: vec![],
+            group_by: vec![],
+            effective_date: None,
+            bank: None,
+            outputs: vec![],
+            #[cfg(feature = "scripting")]
+            script: None,
+            offset: None,
+            sort_entries: vec![],
+            sort_batches: vec![],
+            renumber: false,
+        };
+        let mut error = |at, message: String| {
+            diagnostics.push(Diagnostic::error(at, format!("'{}': {}", label, message)))
         };
-        lines.remove(0);
-
-        let mut broken_line_content = "".to_string();
-        for line in lines {
-            let mut tmp_line = "".to_string();
-            if line.trim_end().ends_with(",") {
-                broken_line_content = format!("{} {}", broken_line_content, line.trim_end());
-                continue;
-            } else if !broken_line_content.is_empty() {
-                tmp_line.push_str(&*broken_line_content);
-                tmp_line.push_str(&*line);
+        let mut warnings = vec![];
+
+        let mut operations_at = vec![];
+        let mut on_at = vec![];
+        let mut conditions_at = vec![];
+        let mut entries_at: Vec<&RawEntry> = vec![];
+
+        for entry in &raw.entries {
+            let items = entry.values.clone();
+            match entry.key.text.as_str() {
+                "operation" => {
+                    for item in items {
+                        let operation = Operation::from_name(&item.text)
+                            .or_else(|| operations.get(&item.text).map(Operation::CUSTOM));
+                        match operation {
+                            Some(operation) => {
+                                transformation.operation.push(operation);
+                                operations_at.push(item);
+                            }
+                            None => error(
+                                item.at(),
+                                format!(
+                                    "unknown operation '{}', expected one of {}",
+                                    item.text,
+                                    operations.names().join(", ")
+                                ),
+                            ),
+                        }
+                    }
+                }
+                "on" => {
+                    for item in items {
+                        match AchRecordType::from(item.text.as_str()) {
+                            AchRecordType::Unknown => error(
+                                item.at(),
+                                format!(
+                                    "unknown record type '{}', expected one of {}",
+                                    item.text,
+                                    RECORD_TYPES.join(", ")
+                                ),
+                            ),
+                            record_type => {
+                                transformation.on.push(record_type);
+                                on_at.push(item);
+                            }
+                        }
+                    }
+                }
+                "where" => {
+                    for item in items {
+                        match Condition::try_from(item.text.as_str()) {
+                            Ok(condition) => {
+                                transformation.conditions.push(condition);
+                                conditions_at.push(item);
+                            }
+                            Err(e) => error(item.at(), e.to_string()),
+                        }
+                    }
+                }
+                "by" => {
+                    for item in items {
+                        if item.text == "direction"
+                            || AchRecordType::EntryDetail.has_field(&item.text)
+                            || AchRecordType::CompanyBatchHeader.has_field(&item.text)
+                        {
+                            transformation.group_by.push(item.text);
+                        } else {
+                            error(
+                                item.at(),
+                                format!(
+                                    "unknown rebatch key '{}', expected direction or an \
+                                     EntryDetail or CompanyBatchHeader field",
+                                    item.text
+                                ),
+                            );
+                        }
+                    }
+                }
+                "entries_by" => {
+                    for item in items {
+                        match item.text.parse() {
+                            Ok(key) => transformation.sort_entries.push(key),
+                            Err(e) => error(item.at(), e),
+                        }
+                    }
+                }
+                "batches_by" => {
+                    for item in items {
+                        match item.text.parse() {
+                            Ok(key) => transformation.sort_batches.push(key),
+                            Err(e) => error(item.at(), e),
+                        }
+                    }
+                }
+                "renumber" => {
+                    let renumber = &entry.values[0];
+                    match renumber.text.as_str() {
+                        "true" => transformation.renumber = true,
+                        "false" => transformation.renumber = false,
+                        _ => error(
+                            renumber.at(),
+                            format!("renumber '{}' is not true or false", renumber.text),
+                        ),
+                    }
+                }
+                "effective_date" => {
+                    let date = &entry.values[0];
+                    if date.text.len() == 6 && date.text.chars().all(|c| c.is_ascii_digit()) {
+                        transformation.effective_date = Some(date.text.clone());
+                    } else {
+                        error(
+                            date.at(),
+                            format!("effective_date '{}' is not YYMMDD", date.text),
+                        );
+                    }
+                }
+                key if BANK_KEYS.contains(&key) || key == "script" => {}
+                key if key == "offset_name" || key.starts_with("accounts.") => {}
+                key if key.starts_with("outputs.") => {
+                    let key = &key["outputs.".len()..];
+                    let (name, conditions) = match key.strip_suffix(".where") {
+                        Some(name) => (name, true),
+                        None => (key, false),
+                    };
+                    let index = match transformation.outputs.iter().position(|o| o.name == name) {
+                        Some(index) => index,
+                        None => {
+                            transformation.outputs.push(SplitOutput {
+                                name: name.to_string(),
+                                template: None,
+                                conditions: vec![],
+                            });
+                            transformation.outputs.len() - 1
+                        }
+                    };
+                    let output = &mut transformation.outputs[index];
+                    for item in items {
+                        if conditions {
+                            match Condition::try_from(item.text.as_str()) {
+                                Ok(condition) => {
+                                    for (field, _) in condition.condition.comparisons() {
+                                        if !AchRecordType::CompanyBatchHeader.has_field(field) {
+                                            error(
+                                                item.at(),
+                                                format!(
+                                                    "output '{}': CompanyBatchHeader has no field \
+                                                     '{}'",
+                                                    name, field
+                                                ),
+                                            );
+                                        }
+                                    }
+                                    output.conditions.push(condition);
+                                }
+                                Err(e) => error(item.at(), e.to_string()),
+                            }
+                        } else {
+                            match OutputTemplate::try_from(item.text.as_str()) {
+                                Ok(template) => output.template = Some(template),
+                                Err(e) => error(item.at(), format!("output '{}': {}", name, e)),
+                            }
+                        }
+                    }
+                }
+                key => {
+                    error(
+                        entry.key.at(),
+                        format!("unknown key '{}', expected one of {}", key, KEYS.join(", ")),
+                    );
+                    continue;
+                }
             }
-            let line = if !tmp_line.is_empty() { tmp_line } else { line };
-            println!("{}", line);
+            entries_at.push(entry);
+        }
 
-            let line_data = line.split(":").map(|s| s.trim()).collect::<Vec<&str>>();
-            if line_data.len() > 2 {
-                error!(
-                    "Malformed config file! Error on following line: \n {}",
-                    line
-                );
-                return Err(Self::Error::new(
-                    ErrorKind::InvalidData,
+        let setting = |key: &str| entries_at.iter().find(|e| e.key.text == key);
+
+        if raw.entries.is_empty() {
+            error(raw.label.at(), format!("'{}' has no settings", label));
+        } else if transformation.operation.is_empty() && setting("operation").is_none() {
+            error(raw.label.at(), "no operation".to_string());
+        }
+
+        for (condition, at) in transformation.conditions.iter().zip(&conditions_at) {
+            for record_type in &transformation.on {
+                for (field, value) in condition.condition.comparisons() {
+                    let spec = match record_type.layout().iter().find(|f| f.name == field) {
+                        Some(spec) => spec,
+                        None => {
+                            error(
+                                at.at(),
+                                format!("{:?} has no field '{}'", record_type, field),
+                            );
+                            continue;
+                        }
+                    };
+                    if spec.numeric && value.parse::<u64>().is_err() {
+                        error(
+                            at.at(),
+                            format!(
+                                "{:?}.{} is numeric but is compared with '{}'",
+                                record_type, field, value
+                            ),
+                        );
+                    } else if value.len() > spec.size {
+                        warnings.push(Diagnostic::warning(
+                            at.at(),
+                            format!(
+                                "'{}': '{}' is longer than the {} characters of {:?}.{}, so \
+                                 it can never match",
+                                label, value, spec.size, record_type, field
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+
+        let uses = |wanted: &[Operation]| {
+            transformation.operation.iter().any(|o| {
+                wanted
+                    .iter()
+                    .any(|w| std::mem::discriminant(o) == std::mem::discriminant(w))
+            })
+        };
+        let removes = uses(&[Operation::DROP, Operation::QUARANTINE]);
+        let uses_custom = transformation
+            .operation
+            .iter()
+            .any(|o| matches!(o, Operation::CUSTOM(_)));
+
+        for (operation, at) in transformation.operation.iter().zip(&operations_at) {
+            match operation {
+                Operation::DROP | Operation::QUARANTINE => {
+                    if transformation.on.is_empty() && setting("on").is_none() {
+                        error(
+                            at.at(),
+                            format!(
+                                "{:?} needs 'on: EntryDetail' or 'on: CompanyBatchHeader'",
+                                operation
+                            ),
+                        );
+                    }
+                    for (record_type, on) in transformation.on.iter().zip(&on_at) {
+                        if !matches!(
+                            record_type,
+                            AchRecordType::EntryDetail | AchRecordType::CompanyBatchHeader
+                        ) {
+                            error(
+                                on.at(),
+                                format!(
+                                    "{:?} cannot remove {:?} records, only EntryDetail or \
+                                     CompanyBatchHeader",
+                                    operation, record_type
+                                ),
+                            );
+                        }
+                    }
+                    if transformation.conditions.is_empty() && setting("where").is_none() {
+                        error(
+                            at.at(),
+                            format!("no conditions, refusing to {:?} every record", operation),
+                        );
+                    }
+                }
+                Operation::REBATCH => {
+                    if let Some(at) = conditions_at.first() {
+                        error(
+                            at.at(),
+                            "REBATCH regroups every entry and takes no conditions".to_string(),
+                        );
+                    }
+                }
+                Operation::CONSOLIDATE => {
+                    if let Some(at) = conditions_at.first() {
+                        error(
+                            at.at(),
+                            "CONSOLIDATE merges every batch it can and takes no conditions"
+                                .to_string(),
+                        );
+                    }
+                }
+                Operation::SORT => {
+                    if let Some(at) = conditions_at.first() {
+                        error(
+                            at.at(),
+                            "SORT orders every entry and takes no conditions".to_string(),
+                        );
+                    }
+                    if setting("entries_by").is_none() && setting("batches_by").is_none() {
+                        error(at.at(), "SORT needs entries_by or batches_by".to_string());
+                    }
+                    if let Some(entries_by) = setting("entries_by") {
+                        if !transformation.renumber {
+                            warnings.push(Diagnostic::warning(
+                                entries_by.key.at(),
+                                format!(
+                                    "'{}': entries_by without renumber: true leaves traces out \
+                                     of order within their batches",
+                                    label
+                                ),
+                            ));
+                        }
+                    }
+                }
+                Operation::OFFSET => {
+                    if let Some(at) = conditions_at.first() {
+                        error(
+                            at.at(),
+                            "OFFSET balances every batch and takes no conditions".to_string(),
+                        );
+                    }
+                    let mut options = OffsetOptions {
+                        accounts: BTreeMap::new(),
+                        individual_name: "OFFSET".to_string(),
+                    };
+                    if let Some(name) = setting("offset_name").map(|e| &e.values[0]) {
+                        if name.text.trim().is_empty() || name.text.len() > 22 {
+                            error(
+                                name.at(),
+                                format!("offset_name '{}' must be 1 to 22 characters", name.text),
+                            );
+                        }
+                        options.individual_name = name.text.to_uppercase();
+                    }
+                    let routings: Vec<_> = entries_at
+                        .iter()
+                        .filter(|e| {
+                            e.key.text.starts_with("accounts.") && e.key.text.ends_with(".routing")
+                        })
+                        .collect();
+                    if routings.is_empty() {
+                        error(at.at(), "OFFSET needs accounts".to_string());
+                    }
+                    for routing in routings {
+                        let company_id = &routing.key.text
+                            ["accounts.".len()..routing.key.text.len() - ".routing".len()];
+                        let value = |key| {
+                            setting(&format!("accounts.{}.{}", company_id, key))
+                                .map(|e| &e.values[0])
+                        };
+                        let savings = match value("account_type") {
+                            None => false,
+                            Some(t) if t.text == "checking" => false,
+                            Some(t) if t.text == "savings" => true,
+                            Some(t) => {
+                                error(
+                                    t.at(),
+                                    format!(
+                                        "account {}: account_type '{}' is not checking or savings",
+                                        company_id, t.text
+                                    ),
+                                );
+                                continue;
+                            }
+                        };
+                        let routing_value = &routing.values[0];
+                        match OffsetAccount::new(
+                            &routing_value.text,
+                            value("account").map_or("", |a| a.text.as_str()),
+                            savings,
+                        ) {
+                            Ok(account) => {
+                                options.accounts.insert(company_id.to_string(), account);
+                            }
+                            Err(e) => {
+                                error(routing_value.at(), format!("account {}: {}", company_id, e))
+                            }
+                        }
+                    }
+                    transformation.offset = Some(options);
+                }
+                Operation::REROUTE => {
+                    let dest = setting("immediate_dest").map(|e| &e.values[0]);
+                    let dest_name = setting("immediate_dest_name").map(|e| &e.values[0]);
+                    let (dest, dest_name) = match (dest, dest_name) {
+                        (Some(dest), Some(dest_name)) => (dest, dest_name),
+                        _ => {
+                            error(
+                                at.at(),
+                                "REROUTE needs immediate_dest and immediate_dest_name".to_string(),
+                            );
+                            continue;
+                        }
+                    };
+                    let optional = |key| setting(key).map(|e| e.values[0].text.clone());
+                    match BankProfile::new(
+                        &dest.text,
+                        &dest_name.text,
+                        optional("immediate_orig").as_deref(),
+                        optional("odfi_id").as_deref(),
+                    ) {
+                        Ok(profile) => transformation.bank = Some(profile),
+                        Err(e) => error(dest.at(), e),
+                    }
+                }
+                Operation::SCRIPT => match setting("script").map(|e| &e.values[0]) {
+                    #[cfg(feature = "scripting")]
+                    Some(path) => match Script::load(Path::new(&path.text)) {
+                        Ok(script) => transformation.script = Some(script),
+                        Err(e) => error(path.at(), e),
+                    },
+                    #[cfg(not(feature = "scripting"))]
+                    Some(_) => error(
+                        at.at(),
+                        "SCRIPT needs ach_rs built with the scripting feature".to_string(),
+                    ),
+                    None => error(at.at(), "SCRIPT needs a script".to_string()),
+                },
+                Operation::CUSTOM(_) => {}
+                Operation::SPLIT => {
+                    if transformation.outputs.is_empty() {
+                        error(at.at(), "SPLIT needs outputs".to_string());
+                    }
+                    // An output without conditions takes every batch the ones before it did not
+                    let outputs = &transformation.outputs;
+                    if let Some(i) = outputs.iter().position(|o| o.conditions.is_empty()) {
+                        if let Some(unreachable) = outputs.get(i + 1) {
+                            let key = format!("outputs.{}", unreachable.name);
+                            let at = setting(&key).map_or(at.at(), |e| e.key.at());
+                            warnings.push(Diagnostic::warning(
+                                at,
+                                format!(
+                                    "'{}': output '{}' takes every batch, so '{}' and any after \
+                                     it are never used",
+                                    label, outputs[i].name, unreachable.name
+                                ),
+                            ));
+                        }
+                    }
+                }
+                Operation::REPLACE => warnings.push(Diagnostic::warning(
+                    at.at(),
                     format!(
-                        "Malformed config file! Error on following line: \n {}",
-                        line
+                        "'{}': {:?} is not supported yet and will be skipped",
+                        label, operation
                     ),
-                ));
+                )),
             }
-            match line_data[0] {
-                "operation" => {
-                    let ops = Operation::new_vec(line_data[1]);
-                    let mut unwrapped_ops: Vec<Operation> = vec![];
-                    for op in ops {
-                        // Return any errors if they exist so we map unwrap() over the members without worrying about panics.
-                        unwrapped_ops.push(op?);
-                    }
-
-                    transformation.operation.append(&mut unwrapped_ops)
-                }
-                "on" => transformation.on.append(&mut {
-                    let mut st = line_data[1].clone();
-                    parse_config_value(st, |s| AchRecordType::from(s.trim()))
-                }),
-                _ => {
-                    error!("unknown key '{}'", line_data[0]);
-                    return Err(Self::Error::new(
-                        ErrorKind::InvalidData,
-                        format!("unknown key '{}'", line_data[0]),
-                    ));
+        }
+
+        for entry in &entries_at {
+            let used = match entry.key.text.as_str() {
+                "on" | "where" => removes || uses_custom,
+                "by" | "effective_date" => uses(&[Operation::REBATCH]),
+                "entries_by" | "batches_by" | "renumber" => uses(&[Operation::SORT]),
+                key if BANK_KEYS.contains(&key) => uses(&[Operation::REROUTE]),
+                "script" => uses(&[Operation::SCRIPT]),
+                key if key == "offset_name" || key.starts_with("accounts.") => {
+                    uses(&[Operation::OFFSET])
                 }
+                key if key.starts_with("outputs.") => uses(&[Operation::SPLIT]),
+                _ => true,
             };
-            println!("{:?}", transformation)
+            if !used {
+                warnings.push(Diagnostic::warning(
+                    entry.key.at(),
+                    format!(
+                        "'{}': '{}' is not used by any of its operations",
+                        label, entry.key.text
+                    ),
+                ));
+            }
         }
 
-        Ok(transformation)
+        diagnostics.extend(warnings);
+        transformation
     }
 }
 
+/// One of SPLIT's `outputs:`
+#[derive(Debug)]
+struct SplitOutput {
+    name: String,
+    /// Only missing from configs that fail to load
+    template: Option<OutputTemplate>,
+    /// Batch headers the output takes, or every batch when empty
+    conditions: Vec<Condition>,
+}
+
 #[derive(Debug)]
-enum Operation {
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) enum Operation {
     SPLIT,
     REPLACE,
+    DROP,
+    QUARANTINE,
+    REBATCH,
+    REROUTE,
+    SCRIPT,
+    OFFSET,
+    SORT,
+    CONSOLIDATE,
+    /// Registered in an [OperationRegistry]
+    CUSTOM(Arc<dyn TransformOperation>),
 }
 
 impl Operation {
-    fn new_vec(s: &str) -> Vec<io::Result<Self>> {
-        parse_config_value(s, |st: &str| match st.trim() {
-            "split" => Ok(Operation::SPLIT),
-            "replace" => Ok(Operation::REPLACE),
-            _ => {
-                error!("Unknown operation: {}", st);
-                Err(io::Error::new(
-                    ErrorKind::InvalidData,
-                    format!("Unknown operation: {}", st),
-                ))
-            }
-        })
+    pub(crate) const NAMES: &'static [&'static str] = &[
+        "split",
+        "replace",
+        "drop",
+        "quarantine",
+        "rebatch",
+        "reroute",
+        "script",
+        "offset",
+        "sort",
+        "consolidate",
+    ];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "split" => Some(Operation::SPLIT),
+            "replace" => Some(Operation::REPLACE),
+            "drop" => Some(Operation::DROP),
+            "quarantine" => Some(Operation::QUARANTINE),
+            "rebatch" => Some(Operation::REBATCH),
+            "reroute" => Some(Operation::REROUTE),
+            "script" => Some(Operation::SCRIPT),
+            "offset" => Some(Operation::OFFSET),
+            "sort" => Some(Operation::SORT),
+            "consolidate" => Some(Operation::CONSOLIDATE),
+            _ => None,
+        }
     }
 }
 
-trait Conditions: std::fmt::Debug {}
+trait Conditions: std::fmt::Debug {
+    fn test(&self, record: &dyn AchRecord) -> bool;
+
+    /// Every field this condition reads, with each value it is compared against
+    fn comparisons(&self) -> Vec<(&str, &str)>;
+}
 
+/// A single `where:` entry, e.g. `amount == 0`, `dfi_account in 1234|5678` or
+/// `not sec == CCD and amount > 100`. `and` binds tighter than `or`.
 #[derive(Debug)]
 struct Condition {
     condition: Box<dyn Conditions>,
     result: bool,
 }
 
+impl Condition {
+    fn test(&self, record: &dyn AchRecord) -> bool {
+        self.condition.test(record) == self.result
+    }
+}
+
+impl TryFrom<&str> for Condition {
+    type Error = io::Error;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        let s = s.trim();
+        let (result, s) = match s.strip_prefix("not ") {
+            Some(negated) => (false, negated),
+            None => (true, s),
+        };
+
+        Ok(Condition {
+            condition: parse_conditions(s)?,
+            result,
+        })
+    }
+}
+
+fn parse_conditions(s: &str) -> io::Result<Box<dyn Conditions>> {
+    for (keyword, conjunction) in [(" or ", Conjunction::OR), (" and ", Conjunction::AND)] {
+        if s.contains(keyword) {
+            let mut conditions = vec![];
+            for part in s.split(keyword) {
+                conditions.push(parse_conditions(part)?);
+            }
+            return Ok(Box::new(ConjunctionCondition {
+                conjunction,
+                conditions,
+            }));
+        }
+    }
+
+    if let Some((field, values)) = s.split_once(" in ") {
+        return Ok(Box::new(FieldArrayCondition {
+            field: field.trim().to_string(),
+            values: values.split('|').map(condition_value).collect(),
+        }));
+    }
+
+    for (symbol, comparison) in [
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ] {
+        if let Some((field, value)) = s.split_once(symbol) {
+            return Ok(Box::new(FieldCondition {
+                field: field.trim().to_string(),
+                comparison,
+                value: condition_value(value),
+            }));
+        }
+    }
+
+    Err(io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Could not parse condition: {}", s),
+    ))
+}
+
+fn condition_value(s: &str) -> String {
+    s.trim().trim_matches('"').to_string()
+}
+
+/// Numbers compare numerically, so `amount == 0` matches `0000000000`; anything else compares
+/// as text with the padding trimmed.
+fn compare_values(field: &Field, value: &str) -> Ordering {
+    let content = field.as_str().trim();
+    match (content.parse::<u64>(), value.parse::<u64>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => content.cmp(value),
+    }
+}
+
+#[derive(Debug)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
 #[derive(Debug)]
 struct FieldCondition {
-    field: Field,
+    field: String,
+    comparison: Comparison,
+    value: String,
+}
+
+impl Conditions for FieldCondition {
+    fn test(&self, record: &dyn AchRecord) -> bool {
+        let ordering = match record.field(&self.field) {
+            Some(field) => compare_values(field, &self.value),
+            None => return false,
+        };
+
+        match self.comparison {
+            Comparison::Equal => ordering == Ordering::Equal,
+            Comparison::NotEqual => ordering != Ordering::Equal,
+            Comparison::Less => ordering == Ordering::Less,
+            Comparison::LessOrEqual => ordering != Ordering::Greater,
+            Comparison::Greater => ordering == Ordering::Greater,
+            Comparison::GreaterOrEqual => ordering != Ordering::Less,
+        }
+    }
+
+    fn comparisons(&self) -> Vec<(&str, &str)> {
+        vec![(&self.field, &self.value)]
+    }
 }
-impl Conditions for FieldCondition {}
 
 #[derive(Debug)]
 struct FieldArrayCondition {
-    field_list: Vec<Field>,
+    field: String,
+    values: Vec<String>,
+}
+
+impl Conditions for FieldArrayCondition {
+    fn test(&self, record: &dyn AchRecord) -> bool {
+        match record.field(&self.field) {
+            Some(field) => self
+                .values
+                .iter()
+                .any(|v| compare_values(field, v) == Ordering::Equal),
+            None => false,
+        }
+    }
+
+    fn comparisons(&self) -> Vec<(&str, &str)> {
+        self.values
+            .iter()
+            .map(|v| (self.field.as_str(), v.as_str()))
+            .collect()
+    }
 }
-impl Conditions for FieldArrayCondition {}
 
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 enum Conjunction {
     AND,
     OR,
@@ -205,32 +1288,390 @@ struct ConjunctionCondition {
     conjunction: Conjunction,
     conditions: Vec<Box<dyn Conditions>>,
 }
-impl Conditions for ConjunctionCondition {}
 
-#[derive(Debug)]
-struct NotCondition<'a> {
-    condition: &'a dyn Conditions,
+impl Conditions for ConjunctionCondition {
+    fn test(&self, record: &dyn AchRecord) -> bool {
+        match self.conjunction {
+            Conjunction::AND => self.conditions.iter().all(|c| c.test(record)),
+            Conjunction::OR => self.conditions.iter().any(|c| c.test(record)),
+        }
+    }
+
+    fn comparisons(&self) -> Vec<(&str, &str)> {
+        self.conditions
+            .iter()
+            .flat_map(|c| c.comparisons())
+            .collect()
+    }
 }
-impl Conditions for NotCondition<'_> {}
 
 #[derive(Debug)]
+#[allow(dead_code)]
 struct Replacement {
     record: AchRecordType,
     replace_with: Field,
 }
 
-fn parse_config_value<T, F>(input_string: &str, map_closure: F) -> Vec<T>
-    where
-        F: Fn(&str) -> T,
-{
-    match input_string.find("[") {
-                Some(_) => input_string
-                    .trim_matches(|c| c == '[' || c == ']')
-                    .split(',')
-                    .map(map_closure)
-                    .collect::<Vec<T>>(),
-                None => vec![map_closure(input_string)],
-            }
-    
-    
+#[cfg(test)]
+mod ach_transformations_tests {
+    use crate::ach_config::{lint, ConfigFormat, Severity};
+    use crate::ach_file::AchFile;
+    use crate::ach_transformations::Transformations;
+    use std::io;
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    fn load(lines: &[&str]) -> io::Result<Transformations> {
+        Transformations::try_from(lines.iter().map(|l| l.to_string()).collect::<Vec<_>>())
+    }
+
+    fn config(lines: &[&str]) -> Transformations {
+        load(lines).unwrap()
+    }
+
+    #[test]
+    fn test_drop_zero_dollar_entries() {
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let outcome = config(&[
+            "drop_zero_dollar:",
+            "    operation: drop",
+            "    on: EntryDetail",
+            "    where: amount == 0",
+        ])
+        .apply(&mut ach)
+        .unwrap();
+
+        assert!(outcome.quarantine.is_none());
+        let batch = &ach.batches_mut()[0];
+        assert_eq!(batch.entries().len(), 2);
+        assert!(!format!("{}", ach).contains("ZERO DOLLAR TEST"));
+        // Controls have been recomputed, so the file reads back unchanged
+        let reparsed: AchFile = format!("{}", ach).parse().unwrap();
+        assert_eq!(format!("{}", reparsed), format!("{}", ach));
+    }
+
+    #[test]
+    fn test_quarantine_drops_emptied_batches() {
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let outcome = config(&[
+            "legal_hold:",
+            "    operation: quarantine",
+            "    on: EntryDetail",
+            "    where: dfi_account in 444000222|555000111",
+        ])
+        .apply(&mut ach)
+        .unwrap();
+
+        assert_eq!(ach.batches_mut().len(), 1);
+        assert_eq!(ach.batches_mut()[0].entries().len(), 2);
+
+        let mut quarantine = outcome.quarantine.unwrap();
+        assert_eq!(quarantine.batches_mut().len(), 2);
+        let written = format!("{}", quarantine);
+        assert!(written.contains("JANE ROE"));
+        assert!(written.contains("BETA CUSTOMER"));
+        assert_eq!(written.lines().count() % 10, 0);
+        assert!(written.lines().all(|l| l.len() == 94));
+    }
+
+    #[test]
+    fn test_drop_batches_by_header() {
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        config(&[
+            "drop_beta:",
+            "    operation: drop",
+            "    on: CompanyBatchHeader",
+            "    where: not company_id == 1234567890 and sec == PPD",
+        ])
+        .apply(&mut ach)
+        .unwrap();
+
+        assert_eq!(ach.batches_mut().len(), 1);
+        assert!(!format!("{}", ach).contains("BETA LLC"));
+    }
+
+    #[test]
+    fn test_rebatch_by_direction() {
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        config(&[
+            "separate_debits:",
+            "    operation: rebatch",
+            "    by: [direction, effective_entry_date]",
+            "    effective_date: 261022",
+        ])
+        .apply(&mut ach)
+        .unwrap();
+
+        let written = format!("{}", ach);
+        assert_eq!(ach.batches_mut().len(), 3);
+        assert!(written.contains("5220ACME CORP"));
+        assert!(written.contains("5225ACME CORP"));
+        assert!(written.contains("5225BETA LLC"));
+        assert!(!written.contains("261019"));
+
+        let result = load(&["bad_key:", "    operation: rebatch", "    by: colour"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_reroute_through_backup_bank() {
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let outcome = config(&[
+            "backup_odfi:",
+            "    operation: reroute",
+            "    immediate_dest: 021000021",
+            "    immediate_dest_name: Backup Bank NA",
+        ])
+        .apply(&mut ach)
+        .unwrap();
+
+        let written = format!("{}", ach);
+        assert!(written.starts_with("101 021000021 1234567892610181200A094101BACKUP BANK NA"));
+        assert!(!written.contains("09100001"));
+        assert_eq!(outcome.trace_map.len(), 4);
+        assert_eq!(
+            outcome.trace_map.get("091000010000003"),
+            Some("021000020000003")
+        );
+        assert!(outcome
+            .trace_map
+            .to_string()
+            .starts_with("original_trace,new_trace\n"));
+
+        // A problem the file already had does not stop the reroute
+        let broken = SAMPLE.replace("622076401251123", "622076401252123");
+        let mut ach: AchFile = broken.parse().unwrap();
+        assert_eq!(ach.validate().len(), 1);
+        config(&[
+            "backup_odfi:",
+            "    operation: reroute",
+            "    immediate_dest: 021000021",
+            "    immediate_dest_name: Backup Bank NA",
+        ])
+        .apply(&mut ach)
+        .unwrap();
+        assert!(ach.to_string().contains("622076401252123"));
+        assert!(ach.to_string().contains("021000020000001"));
+
+        // Nor does a duplicate trace, though its message names the trace being rewritten
+        let duplicated = SAMPLE.replace("091000010000002", "091000010000001");
+        let mut ach: AchFile = duplicated.parse().unwrap();
+        assert_eq!(ach.validate().len(), 2);
+        config(&[
+            "backup_odfi:",
+            "    operation: reroute",
+            "    immediate_dest: 021000021",
+            "    immediate_dest_name: Backup Bank NA",
+        ])
+        .apply(&mut ach)
+        .unwrap();
+        let rerouted = ach.to_string();
+        let traces = rerouted.lines().filter(|l| l.starts_with('6'));
+        assert_eq!(traces.filter(|l| l.ends_with("021000020000001")).count(), 2);
+
+        let result = load(&[
+            "bad_routing:",
+            "    operation: reroute",
+            "    immediate_dest: 021000022",
+            "    immediate_dest_name: Backup Bank NA",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_offset_balances_batches() {
+        let lines = [
+            "balance:",
+            "    operation: offset",
+            "    offset_name: Settlement",
+            "    accounts:",
+            "        \"1234567890\":",
+            "            routing: 091000019",
+            "            account: 800100",
+            "        \"9876543210\":",
+            "            routing: 091000019",
+            "            account: 800200",
+            "            account_type: savings",
+        ];
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let outcome = config(&lines).apply(&mut ach).unwrap();
+
+        let written = format!("{}", ach);
+        assert!(written.contains("622091000019800100           0000001500"));
+        assert!(written.contains("632091000019800200           0000007500"));
+        assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+        assert!(outcome.trace_map.is_empty());
+
+        // Balancing again finds the offsets already there
+        let before = format!("{}", ach);
+        config(&lines).apply(&mut ach).unwrap();
+        assert_eq!(format!("{}", ach), before);
+
+        let result = load(&[
+            "balance:",
+            "    operation: offset",
+            "    accounts:",
+            "        \"1234567890\":",
+            "            routing: 091000018",
+            "            account: 800100",
+        ]);
+        assert!(result.is_err());
+        assert!(load(&["balance:", "    operation: offset"]).is_err());
+    }
+
+    #[test]
+    fn test_sort_by_keys() {
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let outcome = config(&[
+            "order:",
+            "    operation: sort",
+            "    batches_by: effective_entry_date desc",
+            "    entries_by: [receiving_dfi_id, amount desc]",
+            "    renumber: true",
+        ])
+        .apply(&mut ach)
+        .unwrap();
+
+        let written = format!("{}", ach);
+        assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+        assert_eq!(ach.batch_field(0, "company_name"), Some("BETA LLC        "));
+        let jane = written.find("JANE ROE").unwrap();
+        assert!(jane < written.find("JOHN DOE").unwrap());
+        assert!(written.find("JOHN DOE").unwrap() < written.find("ZERO DOLLAR TEST").unwrap());
+        assert_eq!(
+            outcome.trace_map.get("091000010000003"),
+            Some("091000010000002")
+        );
+
+        // Without renumbering, sorted entries keep traces that are no longer ascending
+        let warnings = lint(
+            "order:\n    operation: sort\n    entries_by: amount desc\n",
+            ConfigFormat::Yaml,
+        );
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+        assert_eq!((warnings[0].line, warnings[0].column), (3, 5));
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        config(&[
+            "order:",
+            "    operation: sort",
+            "    entries_by: amount desc",
+        ])
+        .apply(&mut ach)
+        .unwrap();
+        assert_eq!(
+            ach.validate()[0].message,
+            "091000010000001 is not above the trace before it, 091000010000003"
+        );
+
+        let result = load(&["order:", "    operation: sort", "    entries_by: colour"]);
+        assert!(result.is_err());
+        assert!(load(&["order:", "    operation: sort", "    renumber: true"]).is_err());
+    }
+
+    #[test]
+    fn test_consolidate_batches() {
+        let mut ach: AchFile = SAMPLE.parse().unwrap();
+        let rebatch = config(&[
+            "per_employee:",
+            "    operation: rebatch",
+            "    by: individual_id",
+        ]);
+        rebatch.apply(&mut ach).unwrap();
+        assert_eq!(ach.batch_count(), 4);
+
+        let outcome = config(&["collapse:", "    operation: consolidate"])
+            .apply(&mut ach)
+            .unwrap();
+        assert_eq!(ach.batch_count(), 3);
+        assert_eq!(outcome.collapsed_batches, 1);
+        assert!(ach.validate().is_empty(), "{:?}", ach.validate());
+        assert_eq!(ach.batch_field(2, "batch_number"), Some("0000003"));
+        assert!(outcome.trace_map.is_empty());
+        assert!(outcome
+            .change_log
+            .to_string()
+            .contains("CompanyBatchHeader.record"));
+
+        let result = load(&[
+            "collapse:",
+            "    operation: consolidate",
+            "    where: amount == 0",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_dry_run_leaves_file_alone() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        let outcome = config(&[
+            "drop_zero_dollar:",
+            "    operation: drop",
+            "    on: EntryDetail",
+            "    where: amount == 0",
+            "backup_odfi:",
+            "    operation: reroute",
+            "    immediate_dest: 021000021",
+            "    immediate_dest_name: Backup Bank NA",
+        ])
+        .dry_run(&ach)
+        .unwrap();
+
+        assert_eq!(format!("{}", ach), SAMPLE);
+        let log = &outcome.change_log;
+        let dropped: Vec<_> = log.iter().filter(|c| c.field == "record").collect();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].label, "drop_zero_dollar");
+        assert_eq!(dropped[0].trace.as_deref(), Some("091000010000002"));
+
+        let rerouted = log
+            .iter()
+            .find(|c| c.label == "backup_odfi" && c.field == "trace")
+            .unwrap();
+        assert_eq!(rerouted.batch, Some(1));
+        assert_eq!(rerouted.entry, Some(1));
+        assert_eq!(rerouted.old, "091000010000001");
+        assert_eq!(rerouted.new, "021000020000001");
+        assert!(log.to_string().contains(
+            "[backup_odfi] Header.immediate_dest_name: 'DEST BANK              ' -> 'BACKUP BANK NA         '"
+        ));
+    }
+
+    #[test]
+    fn test_drop_requires_conditions() {
+        let result = load(&["drop_all:", "    operation: drop", "    on: EntryDetail"]);
+        let message = result.unwrap_err().to_string();
+        assert!(message.starts_with("<config>:2:16: error: 'drop_all': no conditions"));
+
+        let result = load(&[
+            "bad_field:",
+            "    operation: drop",
+            "    on: EntryDetail",
+            "    where: company_id == 1",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_script() {
+        let result = load(&["net:", "    operation: script"]);
+        let message = result.unwrap_err().to_string();
+        assert!(message.starts_with("<config>:2:16: error: 'net': SCRIPT needs a script"));
+
+        let result = Transformations::try_from(std::path::Path::new("test_data/cfg/net.yml"));
+        if cfg!(feature = "scripting") {
+            let mut ach: AchFile = SAMPLE.replace("EMP002", "EMP001").parse().unwrap();
+            let outcome = result.unwrap().apply(&mut ach).unwrap();
+            assert_eq!(ach.len(), SAMPLE.parse::<AchFile>().unwrap().len() - 1);
+            assert!(outcome
+                .change_log
+                .to_string()
+                .contains("(091000010000003) EntryDetail.amount: '0000002500' -> '0000001500'"));
+        } else {
+            let message = result.unwrap_err().to_string();
+            assert!(message.contains(
+                "net.yml:2:16: error: 'net': SCRIPT needs ach_rs built with the scripting feature"
+            ));
+        }
+    }
 }
@@ -0,0 +1,286 @@
+// https://achdevguide.nacha.org/ach-file-details
+
+use crate::ach_file::{routing_check_digit, AchFile, AchRecord, AchRecordType};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+/// A single problem found by [AchFile::validate]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationIssue {
+    /// 1-based line of the record, as the file is written out
+    pub line: usize,
+    pub record_type: AchRecordType,
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}: {:?}.{}: {}",
+            self.line, self.record_type, self.field, self.message
+        )
+    }
+}
+
+/// Control fields of the trailers, which have to agree with the records they summarize
+const BATCH_CONTROLS: &[&str] = &[
+    "service_class_code",
+    "entry_and_addenda_count",
+    "entry_hash",
+    "total_debit_amount",
+    "total_credit_amount",
+    "company_id",
+    "originating_dfi_id_num",
+    "batch_num",
+];
+const FILE_CONTROLS: &[&str] = &[
+    "batch_count",
+    "block_count",
+    "entry_and_addenda_count",
+    "entry_hash",
+    "total_debits",
+    "total_credits",
+];
+
+impl AchFile {
+    /// Check every record against the NACHA layout, the trailers against the records they
+    /// summarize, and entries against their batch. An empty result means the file can be sent.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = vec![];
+        let mut issue = |line, record_type, field, message| {
+            issues.push(ValidationIssue {
+                line,
+                record_type,
+                field,
+                message,
+            })
+        };
+
+        let mut expected = self.clone();
+        expected.recompute_controls();
+
+        for (i, ((record_type, record), (_, expected_record))) in
+            self.records().iter().zip(expected.records()).enumerate()
+        {
+            let line = i + 1;
+
+            for spec in record_type.layout() {
+                let field = match record.field(spec.name) {
+                    Some(field) => field,
+                    None => continue,
+                };
+                let width = field.to_string().len();
+                if width != spec.size {
+                    issue(
+                        line,
+                        *record_type,
+                        spec.name,
+                        format!("is {} characters wide, expected {}", width, spec.size),
+                    );
+                } else if spec.numeric && !field.as_str().chars().all(|c| c.is_ascii_digit()) {
+                    issue(
+                        line,
+                        *record_type,
+                        spec.name,
+                        format!("'{}' is not numeric", field.as_str()),
+                    );
+                }
+            }
+
+            let controls = match record_type {
+                AchRecordType::CompanyBatchTrailer => BATCH_CONTROLS,
+                AchRecordType::Trailer => FILE_CONTROLS,
+                _ => &[],
+            };
+            for name in controls {
+                let declared = record
+                    .field(name)
+                    .map(|f| f.as_str().trim())
+                    .unwrap_or_default();
+                let computed = expected_record
+                    .field(name)
+                    .map(|f| f.as_str().trim())
+                    .unwrap_or_default();
+                if declared != computed {
+                    issue(
+                        line,
+                        *record_type,
+                        name,
+                        format!(
+                            "declared {} but the records add up to {}",
+                            declared, computed
+                        ),
+                    );
+                }
+            }
+        }
+
+        // Routing numbers in the file header are right-justified behind a blank
+        for name in ["immediate_dest", "immediate_orig"] {
+            let value = self.header().field(name).unwrap().as_str();
+            let digits = value.strip_prefix(' ').unwrap_or(value);
+            if (name == "immediate_dest" && !value.starts_with(' ')) || value.ends_with(' ') {
+                issue(
+                    1,
+                    AchRecordType::Header,
+                    name,
+                    format!("'{}' is not right-justified behind a blank", value),
+                );
+            } else if !digits.chars().all(|c| c.is_ascii_digit()) {
+                issue(
+                    1,
+                    AchRecordType::Header,
+                    name,
+                    format!("'{}' is not a routing number", value),
+                );
+            }
+        }
+
+        let mut traces = HashSet::new();
+        let mut line = 1;
+        for batch in self.batches() {
+            line += 1;
+            let odfi_id = batch.header().field("odfi_id").unwrap().as_str();
+            let mut previous: Option<&str> = None;
+
+            for entry in batch.entries() {
+                line += 1;
+                let entry_line = line;
+                let field = |name| entry.field(name).unwrap().as_str();
+
+                if !field("trace").starts_with(odfi_id) {
+                    issue(
+                        line,
+                        AchRecordType::EntryDetail,
+                        "trace",
+                        format!("does not start with the batch ODFI {}", odfi_id),
+                    );
+                }
+                if !traces.insert(field("trace")) {
+                    issue(
+                        line,
+                        AchRecordType::EntryDetail,
+                        "trace",
+                        format!("{} is used by more than one entry", field("trace")),
+                    );
+                }
+                // Traces ascend within a batch, as banks expect to receive them
+                if let Some(previous) = previous.filter(|p| *p >= field("trace")) {
+                    issue(
+                        line,
+                        AchRecordType::EntryDetail,
+                        "trace",
+                        format!(
+                            "{} is not above the trace before it, {}",
+                            field("trace"),
+                            previous
+                        ),
+                    );
+                }
+                previous = Some(field("trace"));
+                if routing_check_digit(field("receiving_dfi_id"))
+                    != field("check_digit").parse().ok()
+                {
+                    issue(
+                        line,
+                        AchRecordType::EntryDetail,
+                        "check_digit",
+                        format!(
+                            "does not match receiving_dfi_id {}",
+                            field("receiving_dfi_id")
+                        ),
+                    );
+                }
+                let has_addenda = if entry.addenda().is_empty() { "0" } else { "1" };
+                if field("addenda_indicator") != has_addenda {
+                    issue(
+                        line,
+                        AchRecordType::EntryDetail,
+                        "addenda_indicator",
+                        format!(
+                            "is {} but the entry has {} addenda",
+                            field("addenda_indicator"),
+                            entry.addenda().len()
+                        ),
+                    );
+                }
+
+                for addenda in entry.addenda() {
+                    line += 1;
+                    let batch = addenda.field("batch").unwrap().as_str();
+                    if !field("trace").ends_with(batch) {
+                        issue(
+                            line,
+                            AchRecordType::Addenda,
+                            "batch",
+                            format!(
+                                "{} does not match the trace of the entry on line {}",
+                                batch, entry_line
+                            ),
+                        );
+                    }
+                }
+            }
+            line += 1;
+        }
+
+        issues.sort_by_key(|i| i.line);
+        issues
+    }
+}
+
+#[cfg(test)]
+mod ach_validation_tests {
+    use crate::ach_file::{AchFile, AchRecordType};
+
+    const SAMPLE: &str = include_str!("../test_data/sample.ach");
+
+    #[test]
+    fn test_sample_is_valid() {
+        let ach: AchFile = SAMPLE.parse().unwrap();
+        assert_eq!(ach.validate(), vec![]);
+    }
+
+    #[test]
+    fn test_reports_bad_controls_and_entries() {
+        // Change JOHN DOE's amount and break the check digit on BETA CUSTOMER
+        let broken = SAMPLE
+            .replace("0000001000EMP001", "0000001001EMP001")
+            .replace("627076401251444000222", "627076401259444000222");
+        let ach: AchFile = broken.parse().unwrap();
+        let issues = ach.validate();
+
+        let found: Vec<(usize, AchRecordType, &str)> = issues
+            .iter()
+            .map(|i| (i.line, i.record_type, i.field))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (7, AchRecordType::CompanyBatchTrailer, "total_credit_amount"),
+                (9, AchRecordType::EntryDetail, "check_digit"),
+                (11, AchRecordType::Trailer, "total_credits"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reports_left_justified_routing() {
+        // As a field editor writes them, padding after the routing numbers
+        let broken = SAMPLE.replace("101 091000019 123456789", "101091000019 123456789 ");
+        let ach: AchFile = broken.parse().unwrap();
+        let issues = ach.validate();
+        assert_eq!(issues.len(), 2, "{:?}", issues);
+        assert_eq!(
+            (issues[0].line, issues[0].record_type, issues[0].field),
+            (1, AchRecordType::Header, "immediate_dest")
+        );
+        assert_eq!(
+            issues[1].message,
+            "'123456789 ' is not right-justified behind a blank"
+        );
+    }
+}
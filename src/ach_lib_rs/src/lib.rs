@@ -1,3 +1,25 @@
+pub mod ach_change_log;
+pub mod ach_config;
+#[cfg(feature = "pdf")]
+pub mod ach_control_sheet;
+pub mod ach_diff;
+pub mod ach_explain;
 pub mod ach_file;
+pub mod ach_generate;
+pub mod ach_golden;
+pub mod ach_json;
+pub mod ach_offset;
+pub mod ach_operations;
+pub mod ach_pipeline;
+pub mod ach_report;
+pub mod ach_reversal;
+#[cfg(feature = "scripting")]
+mod ach_scripting;
+pub mod ach_settlement;
+pub mod ach_sort;
+pub mod ach_split;
+#[cfg(feature = "templates")]
+pub mod ach_template;
 pub mod ach_transformations;
+pub mod ach_validation;
 mod string_reader;
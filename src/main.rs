@@ -1,27 +1,707 @@
-use ach_lib_rs::ach_file::AchFile;
+use ach_lib_rs::ach_change_log::ChangeLog;
+use ach_lib_rs::ach_config::{lint_file, Severity};
+#[cfg(feature = "pdf")]
+use ach_lib_rs::ach_control_sheet::control_sheet;
+use ach_lib_rs::ach_file::{AchError, AchFile, BankProfile, RecordId};
+use ach_lib_rs::ach_generate::{generate, GenerateOptions};
+use ach_lib_rs::ach_golden::{compare, run_golden};
+use ach_lib_rs::ach_offset::{OffsetAccount, OffsetOptions};
+use ach_lib_rs::ach_pipeline::Pipeline;
+use ach_lib_rs::ach_report::{ControlReport, Summary};
+use ach_lib_rs::ach_reversal::ReverseOptions;
+use ach_lib_rs::ach_settlement::{FedDirectory, Settlement};
+use ach_lib_rs::ach_split::{split_by, write_outputs};
 use ach_lib_rs::ach_transformations::Transformations;
-use std::env;
+use clap::{Parser, Subcommand, ValueEnum};
+use log::{error, LevelFilter};
+use serde::Serialize;
+use std::fs;
 use std::io;
-use std::path::Path;
+use std::io::{ErrorKind, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+#[cfg(feature = "tui")]
+mod tui;
 
-    if args.len() != 3 {
-        println!(
-            "Usage: {} <ach file to process> <config file in ./cfg directory>",
-            args[0]
-        )
-    } else {
-        let operations = Transformations::try_from((format!("./cfg/{}", &args[2])).as_ref())?;
+/// The file or config was read but is not right: it does not validate, has lint errors, fails
+/// its golden tests or differs from what it is compared with. Usage errors exit with 2.
+const EXIT_INVALID: i32 = 1;
+/// Something went wrong other than the input being invalid, such as a file that could not be
+/// read or written
+const EXIT_ERROR: i32 = 3;
 
-        println!("ops: {:?}", operations);
+#[derive(Parser)]
+#[command(version, about = "Read, check and transform NACHA ACH files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
 
-        let ach = AchFile::try_from(Path::new(&args[1])).unwrap();
-        print!("{}", ach);
+    /// Where to write the result, instead of standard output
+    #[arg(short, long, global = true)]
+    output: Option<PathBuf>,
 
-        println!("{}", ach.len());
+    /// How to write the result: `text` is the ACH file itself, or a plain listing for commands
+    /// that do not produce a file
+    #[arg(short, long, global = true, value_enum)]
+    format: Option<Format>,
+
+    /// Log more, up to -vvv
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Log less: -q for errors only, -qq for nothing
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "verbose")]
+    quiet: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+/// Whether to colour output meant for a terminal
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Colour {
+    /// Only when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Read a file and write it back out
+    #[command(alias = "parse")]
+    Show { file: PathBuf },
+
+    /// Check a file against the NACHA rules, exiting with 1 if it has any problem
+    Validate { file: PathBuf },
+
+    /// Show each record under a column ruler, with its fields named and those with problems
+    /// marked
+    Explain {
+        file: PathBuf,
+        #[arg(long, value_enum, default_value_t = Colour::Auto)]
+        colour: Colour,
+    },
+
+    /// Apply configs to a file, one after another. Quarantined entries and renumbered traces
+    /// are written beside the output, or beside the file when writing to standard output.
+    Transform {
+        file: PathBuf,
+        /// Transformation configs or pipeline files, applied in turn
+        #[arg(required = true)]
+        configs: Vec<PathBuf>,
+        /// Only report what would change
+        #[arg(long)]
+        dry_run: bool,
+        /// Write every change made, or that would be with --dry-run, as JSON if the name ends
+        /// in `.json`
+        #[arg(long)]
+        change_log: Option<PathBuf>,
+    },
+
+    /// Split each batch into the file `--output` names, e.g. `out/{date}_{company_id}.ach`,
+    /// then write a manifest of them
+    Split { file: PathBuf },
+
+    /// Put the batches of several files for the same destination into one, renumbered. The map
+    /// from original to new trace is written beside the output, or beside the first file when
+    /// writing to standard output.
+    Merge {
+        #[arg(required = true, num_args = 2..)]
+        files: Vec<PathBuf>,
+    },
+
+    /// Number batches from 1 and rewrite every trace as the ODFI id and a running sequence, with
+    /// addenda following their entry. The map from original to new trace is written beside the
+    /// output, or beside the file when writing to standard output.
+    Renumber {
+        file: PathBuf,
+        /// Sequence the first trace takes
+        #[arg(long, default_value_t = 1)]
+        first_sequence: u64,
+    },
+
+    /// Reversing entries for a file sent in error, with debits and credits flipped, or for only
+    /// some of its batches or entries. Each batch of the reversal has to balance, or be given
+    /// offsets with --offset-account. The map from original to reversal trace is written beside
+    /// the output, or beside the file when writing to standard output.
+    Reverse {
+        file: PathBuf,
+        /// YYMMDD the reversals settle on
+        #[arg(long)]
+        effective_date: String,
+        /// Reverse every entry of this batch number
+        #[arg(long = "batch")]
+        batches: Vec<String>,
+        /// Reverse the entry with this trace number
+        #[arg(long = "trace")]
+        traces: Vec<String>,
+        /// Settlement account to offset a company's batches against, as
+        /// `<company id>=<routing>/<account>`, followed by `/savings` for a savings account
+        #[arg(long = "offset-account", value_parser = offset_account)]
+        offset_accounts: Vec<(String, OffsetAccount)>,
+        /// `individual_name` of the offsets
+        #[arg(long, default_value = "OFFSET")]
+        offset_name: String,
+        /// File ID modifier of the reversal, by default the one after the reversed file's
+        #[arg(long)]
+        file_id_modifier: Option<char>,
+    },
+
+    /// Convert an ACH file to JSON, or JSON back to an ACH file
+    Convert { file: PathBuf },
+
+    /// Entry counts and totals of each batch and the file
+    Report {
+        file: PathBuf,
+        /// Control totals of each batch, company and the file, with the values the trailers
+        /// declare beside those computed from the records
+        #[arg(long)]
+        controls: bool,
+        /// Write Markdown tables instead of plain text
+        #[arg(long, requires = "controls")]
+        markdown: bool,
+        /// Write the report from a Tera template instead, escaping HTML if its name has `.html`
+        /// in it. Needs ach_rs built with the templates feature.
+        #[arg(long, conflicts_with_all = ["controls", "markdown"])]
+        template: Option<PathBuf>,
+    },
+
+    /// A PDF control sheet to sign before the file is sent, with its totals and the SHA-256 of
+    /// its bytes. Needs ach_rs built with the pdf feature.
+    ControlSheet { file: PathBuf },
+
+    /// Expected settlement by receiving bank across one or more files, with on-us entries set
+    /// apart from transit
+    Settlement {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Our routing number, which decides what is on-us, instead of the immediate origin of
+        /// the first file
+        #[arg(long)]
+        routing: Option<String>,
+        /// FedACH participant directory to name the banks from
+        #[arg(long)]
+        fed_directory: Option<PathBuf>,
+    },
+
+    /// Check a config, exiting with 1 if it has errors
+    #[command(alias = "lint")]
+    LintConfig { config: PathBuf },
+
+    /// Compare two files batch by batch and entry by entry, ignoring padding, exiting with 1 if
+    /// they differ
+    Diff {
+        expected: PathBuf,
+        actual: PathBuf,
+        /// List each field that differs with its line, padding included, instead of each batch
+        /// and entry
+        #[arg(long)]
+        by_line: bool,
+    },
+
+    /// Make up a valid file to test with
+    Generate {
+        #[arg(long, default_value_t = 1)]
+        batches: usize,
+        /// Entries in each batch
+        #[arg(long, default_value_t = 10)]
+        entries: usize,
+        /// Routing number of the bank the file is sent to
+        #[arg(long)]
+        immediate_dest: Option<String>,
+        #[arg(long)]
+        company_name: Option<String>,
+        #[arg(long)]
+        company_id: Option<String>,
+        /// Creation and effective date, YYMMDD, instead of today
+        #[arg(long)]
+        date: Option<String>,
+    },
+
+    /// Run a config over every `.ach` file in a directory and compare each result with the
+    /// `.expected.ach` file beside it
+    Test {
+        config: PathBuf,
+        dir: PathBuf,
+        /// Write the expected outputs from the actual ones instead
+        #[arg(long)]
+        bless: bool,
+    },
+
+    /// Browse and edit a file in the terminal, saving it only if it validates. Needs ach_rs
+    /// built with the tui feature.
+    #[command(alias = "tui")]
+    Browse { file: PathBuf },
+}
+
+/// Why a command did not succeed, which decides the exit code
+enum Failure {
+    Invalid(String),
+    Error(String),
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::InvalidData => Failure::Invalid(e.to_string()),
+            _ => Failure::Error(e.to_string()),
+        }
     }
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let level = match (cli.quiet, cli.verbose) {
+        (0, 0) => LevelFilter::Warn,
+        (0, 1) => LevelFilter::Info,
+        (0, 2) => LevelFilter::Debug,
+        (0, _) => LevelFilter::Trace,
+        (1, _) => LevelFilter::Error,
+        _ => LevelFilter::Off,
+    };
+    env_logger::Builder::new().filter_level(level).init();
+
+    let code = match run(&cli) {
+        Ok(()) => 0,
+        Err(Failure::Invalid(message)) => {
+            error!("{}", message);
+            EXIT_INVALID
+        }
+        Err(Failure::Error(message)) => {
+            error!("{}", message);
+            EXIT_ERROR
+        }
+    };
+    process::exit(code);
+}
+
+fn run(cli: &Cli) -> Result<(), Failure> {
+    let format = cli.format.unwrap_or(Format::Text);
+    match &cli.command {
+        Command::Show { file } => write_file(cli, format, &read(file)?),
+        Command::Validate { file } => {
+            let issues = read(file)?.validate();
+            match format {
+                Format::Text => write_lines(cli, &issues)?,
+                Format::Json => write_json(cli, &issues)?,
+            }
+            if !issues.is_empty() {
+                return Err(Failure::Invalid(format!(
+                    "{}: {} problems",
+                    file.display(),
+                    issues.len()
+                )));
+            }
+            Ok(())
+        }
+        Command::Explain { file, colour } => {
+            let records = read(file)?.explain();
+            if format == Format::Json {
+                return write_json(cli, &records);
+            }
+            let colour = match colour {
+                Colour::Auto => cli.output.is_none() && io::stdout().is_terminal(),
+                Colour::Always => true,
+                Colour::Never => false,
+            };
+            let explained: Vec<String> = records.iter().map(|r| r.render(colour)).collect();
+            write(cli, &explained.join("\n"))
+        }
+        Command::Transform {
+            file,
+            configs,
+            dry_run,
+            change_log: change_log_path,
+        } => {
+            let pipeline = Pipeline::load(configs)?;
+            let mut ach = read(file)?;
+
+            let write_change_log = |change_log: &ChangeLog| match change_log_path {
+                Some(path) => match path.extension().is_some_and(|e| e == "json") {
+                    true => write_to(path, &change_log.to_json()),
+                    false => write_to(path, &change_log.to_string()),
+                },
+                None => Ok(()),
+            };
+
+            if *dry_run {
+                let outcome = pipeline.dry_run(&ach)?;
+                write_change_log(&outcome.change_log)?;
+                return match format {
+                    Format::Text => write(cli, &outcome.change_log.to_string()),
+                    Format::Json => write(cli, &outcome.change_log.to_json()),
+                };
+            }
+
+            let outcome = pipeline.apply(&mut ach)?;
+            let beside = cli.output.as_deref().unwrap_or(file);
+            if let Some(quarantine) = outcome.quarantine {
+                write_to(
+                    &beside.with_extension("quarantine.ach"),
+                    &quarantine.to_string(),
+                )?;
+            }
+            write_outputs(&outcome.outputs)?;
+            if !outcome.trace_map.is_empty() {
+                write_to(
+                    &beside.with_extension("traces.csv"),
+                    &outcome.trace_map.to_string(),
+                )?;
+            }
+            write_change_log(&outcome.change_log)?;
+            write_file(cli, format, &ach)
+        }
+        Command::Split { file } => {
+            let template = cli.output.as_ref().ok_or_else(|| {
+                Failure::Invalid(
+                    "split needs --output to name the files, e.g. 'out/{company_id}.ach'"
+                        .to_string(),
+                )
+            })?;
+            let mut ach = read(file)?;
+            let files =
+                split_by(&mut ach, &template.to_string_lossy()).map_err(Failure::Invalid)?;
+            for path in write_outputs(&files)? {
+                println!("{}", path.display());
+            }
+            Ok(())
+        }
+        Command::Merge { files } => {
+            let (merged, trace_map) = AchFile::merge(
+                files
+                    .iter()
+                    .map(|file| read(file))
+                    .collect::<Result<_, _>>()?,
+            )
+            .map_err(Failure::Invalid)?;
+            if !trace_map.is_empty() {
+                let beside = cli.output.as_deref().unwrap_or(&files[0]);
+                write_to(&beside.with_extension("traces.csv"), &trace_map.to_string())?;
+            }
+            write_file(cli, format, &merged)
+        }
+        Command::Renumber {
+            file,
+            first_sequence,
+        } => {
+            let mut ach = read(file)?;
+            let trace_map = ach
+                .renumber_from(*first_sequence)
+                .map_err(Failure::Invalid)?;
+            if !trace_map.is_empty() {
+                let beside = cli.output.as_deref().unwrap_or(file);
+                write_to(&beside.with_extension("traces.csv"), &trace_map.to_string())?;
+            }
+            write_file(cli, format, &ach)
+        }
+        Command::Reverse {
+            file,
+            effective_date,
+            batches,
+            traces,
+            offset_accounts,
+            offset_name,
+            file_id_modifier,
+        } => {
+            let ach = read(file)?;
+            let offset = (!offset_accounts.is_empty()).then(|| OffsetOptions {
+                accounts: offset_accounts.iter().cloned().collect(),
+                individual_name: offset_name.clone(),
+            });
+            let options = ReverseOptions {
+                effective_entry_date: effective_date.clone(),
+                batch_numbers: batches.clone(),
+                traces: traces.clone(),
+                offset,
+                file_id_modifier: *file_id_modifier,
+                ..Default::default()
+            };
+            let (reversal, trace_map) = ach.reverse(&options).map_err(Failure::Invalid)?;
+            let beside = cli.output.as_deref().unwrap_or(file);
+            write_to(&beside.with_extension("traces.csv"), &trace_map.to_string())?;
+            write_file(cli, format, &reversal)
+        }
+        Command::Convert { file } => {
+            let is_json = file.extension().is_some_and(|e| e == "json");
+            let format = match (cli.format, is_json) {
+                (Some(format), _) => format,
+                (None, true) => Format::Text,
+                (None, false) => Format::Json,
+            };
+            write_file(cli, format, &read(file)?)
+        }
+        Command::Report {
+            file,
+            template: Some(template),
+            ..
+        } => report_from_template(cli, &read(file)?, template),
+        Command::Report {
+            file,
+            controls,
+            markdown,
+            template: None,
+        } => {
+            let ach = read(file)?;
+            match (format, *controls) {
+                (Format::Json, true) => write(cli, &ControlReport::from(&ach).to_json()),
+                (Format::Json, false) => write(cli, &Summary::from(&ach).to_json()),
+                (Format::Text, true) if *markdown => {
+                    write(cli, &ControlReport::from(&ach).to_markdown())
+                }
+                (Format::Text, true) => write(cli, &ControlReport::from(&ach).to_string()),
+                (Format::Text, false) => write(cli, &Summary::from(&ach).to_string()),
+            }
+        }
+        #[cfg(feature = "pdf")]
+        Command::ControlSheet { file } => {
+            if cli.output.is_none() && io::stdout().is_terminal() {
+                return Err(Failure::Error(
+                    "give --output to write the PDF to".to_string(),
+                ));
+            }
+            let bytes = read_bytes(file)?;
+            let ach = parse(file, &bytes)?;
+            // Hashed as read, so the sheet matches the file that is sent, unless it was JSON
+            let sheet = match file.extension().is_some_and(|e| e == "json") {
+                true => ach.control_sheet(),
+                false => control_sheet(&ach, &bytes),
+            };
+            write_bytes(cli, &sheet)
+        }
+        #[cfg(not(feature = "pdf"))]
+        Command::ControlSheet { .. } => Err(Failure::Error(
+            "control-sheet needs ach_rs built with the pdf feature".to_string(),
+        )),
+        Command::Settlement {
+            files,
+            routing,
+            fed_directory,
+        } => {
+            let files = files
+                .iter()
+                .map(|file| read(file))
+                .collect::<Result<Vec<_>, _>>()?;
+            let routing = match routing {
+                Some(routing) => routing.clone(),
+                None => {
+                    let header = files[0].record_fields(RecordId::Header).unwrap();
+                    let (_, orig) = header.iter().find(|(n, _)| *n == "immediate_orig").unwrap();
+                    orig.to_string()
+                }
+            };
+            let mut settlement = Settlement::new(&files, &routing);
+            if let Some(path) = fed_directory {
+                settlement.name_banks(&FedDirectory::try_from(path.as_path())?);
+            }
+            match format {
+                Format::Text => write(cli, &settlement.to_string()),
+                Format::Json => write(cli, &settlement.to_json()),
+            }
+        }
+        Command::LintConfig { config } => {
+            let diagnostics = lint_file(config)?;
+            match format {
+                Format::Text => write_lines(cli, &diagnostics)?,
+                Format::Json => write_json(cli, &diagnostics)?,
+            }
+            let errors = diagnostics
+                .iter()
+                .filter(|d| d.severity == Severity::Error)
+                .count();
+            if errors > 0 {
+                return Err(Failure::Invalid(format!(
+                    "{}: {} errors",
+                    config.display(),
+                    errors
+                )));
+            }
+            Ok(())
+        }
+        Command::Diff {
+            expected,
+            actual,
+            by_line: true,
+        } => {
+            let mismatches = compare(&read(expected)?, &read(actual)?);
+            match format {
+                Format::Text => write_lines(cli, &mismatches)?,
+                Format::Json => write_json(cli, &mismatches)?,
+            }
+            if !mismatches.is_empty() {
+                return Err(Failure::Invalid(format!(
+                    "{} fields differ",
+                    mismatches.len()
+                )));
+            }
+            Ok(())
+        }
+        Command::Diff {
+            expected,
+            actual,
+            by_line: false,
+        } => {
+            let diff = read(expected)?.diff(&read(actual)?);
+            match format {
+                Format::Text => write(cli, &diff.to_string())?,
+                Format::Json => write(cli, &diff.to_json())?,
+            }
+            if !diff.is_empty() {
+                return Err(Failure::Invalid("the files differ".to_string()));
+            }
+            Ok(())
+        }
+        Command::Generate {
+            batches,
+            entries,
+            immediate_dest,
+            company_name,
+            company_id,
+            date,
+        } => {
+            let mut options = GenerateOptions {
+                batches: *batches,
+                entries: *entries,
+                ..Default::default()
+            };
+            if let Some(immediate_dest) = immediate_dest {
+                options.bank =
+                    BankProfile::new(immediate_dest, "DEST BANK", Some("123456789"), None)
+                        .map_err(Failure::Invalid)?;
+            }
+            if let Some(company_name) = company_name {
+                options.company_name = company_name.to_uppercase();
+            }
+            if let Some(company_id) = company_id {
+                options.company_id = company_id.clone();
+            }
+            if let Some(date) = date {
+                options.file_creation_date = date.clone();
+            }
+            let ach = generate(&options).map_err(Failure::Invalid)?;
+            write_file(cli, format, &ach)
+        }
+        Command::Test { config, dir, bless } => {
+            let transformations = Transformations::try_from(config.as_path())?;
+            let cases = run_golden(&transformations, dir, *bless)?;
+            let failed = cases.iter().filter(|c| !c.passed()).count();
+            let listing: String = cases.iter().map(|c| format!("{}\n", c)).collect();
+            write(
+                cli,
+                &format!(
+                    "{}{} passed, {} failed\n",
+                    listing,
+                    cases.len() - failed,
+                    failed
+                ),
+            )?;
+            if failed > 0 || cases.is_empty() {
+                return Err(Failure::Invalid(format!("{} cases failed", failed)));
+            }
+            Ok(())
+        }
+        #[cfg(feature = "tui")]
+        Command::Browse { file } => Ok(tui::run(file, read(file)?)?),
+        #[cfg(not(feature = "tui"))]
+        Command::Browse { .. } => Err(Failure::Error(
+            "browse needs ach_rs built with the tui feature".to_string(),
+        )),
+    }
+}
+
+#[cfg(feature = "templates")]
+fn report_from_template(cli: &Cli, ach: &AchFile, template: &Path) -> Result<(), Failure> {
+    let source = fs::read_to_string(template)
+        .map_err(|e| Failure::Error(format!("could not read {}: {}", template.display(), e)))?;
+    let escape_html = template.to_string_lossy().contains(".html");
+    let report = ach
+        .render_template(&source, escape_html)
+        .map_err(|e| Failure::Invalid(format!("{}: {}", template.display(), e)))?;
+    write(cli, &report)
+}
+
+#[cfg(not(feature = "templates"))]
+fn report_from_template(_: &Cli, _: &AchFile, _: &Path) -> Result<(), Failure> {
+    Err(Failure::Error(
+        "report --template needs ach_rs built with the templates feature".to_string(),
+    ))
+}
+
+/// The ACH file at `path`, or the JSON [AchFile::to_json] writes if the name ends in `.json`
+fn read(path: &Path) -> Result<AchFile, Failure> {
+    parse(path, &read_bytes(path)?)
+}
+
+fn read_bytes(path: &Path) -> Result<Vec<u8>, Failure> {
+    fs::read(path).map_err(|e| Failure::Error(format!("could not read {}: {}", path.display(), e)))
+}
+
+/// The file at `path`, from `bytes` already read from it
+fn parse(path: &Path, bytes: &[u8]) -> Result<AchFile, Failure> {
+    let contents = std::str::from_utf8(bytes)
+        .map_err(|e| Failure::Error(format!("could not read {}: {}", path.display(), e)))?;
+    let ach = match path.extension().is_some_and(|e| e == "json") {
+        true => AchFile::from_json(contents),
+        false => contents
+            .parse()
+            .map_err(|_: AchError| "not a valid ACH file".to_string()),
+    };
+    ach.map_err(|e| Failure::Invalid(format!("{}: {}", path.display(), e)))
+}
+
+fn write_file(cli: &Cli, format: Format, ach: &AchFile) -> Result<(), Failure> {
+    match format {
+        Format::Text => write(cli, &ach.to_string()),
+        Format::Json => write(cli, &ach.to_json()),
+    }
+}
+
+fn write_lines<T: std::fmt::Display>(cli: &Cli, items: &[T]) -> Result<(), Failure> {
+    write(
+        cli,
+        &items.iter().map(|i| format!("{}\n", i)).collect::<String>(),
+    )
+}
+
+fn write_json<T: Serialize>(cli: &Cli, value: &T) -> Result<(), Failure> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| Failure::Error(e.to_string()))?;
+    write(cli, &(json + "\n"))
+}
+
+/// Write `contents` to `--output`, or standard output without it
+fn write(cli: &Cli, contents: &str) -> Result<(), Failure> {
+    write_bytes(cli, contents.as_bytes())
+}
+
+fn write_bytes(cli: &Cli, contents: &[u8]) -> Result<(), Failure> {
+    match &cli.output {
+        Some(path) => fs::write(path, contents)
+            .map_err(|e| Failure::Error(format!("could not write {}: {}", path.display(), e))),
+        None => io::stdout()
+            .write_all(contents)
+            .map_err(|e| Failure::Error(e.to_string())),
+    }
+}
+
+/// `--offset-account`: `<company id>=<routing>/<account>`, then `/savings` for a savings account
+fn offset_account(value: &str) -> Result<(String, OffsetAccount), String> {
+    let (company_id, account) = value
+        .split_once('=')
+        .ok_or("expected <company id>=<routing>/<account>")?;
+    let account = match account.split('/').collect::<Vec<_>>()[..] {
+        [routing, account] => OffsetAccount::new(routing, account, false)?,
+        [routing, account, "savings"] => OffsetAccount::new(routing, account, true)?,
+        _ => return Err("expected <routing>/<account>, optionally followed by /savings".into()),
+    };
+    Ok((company_id.trim().to_string(), account))
+}
 
-    Ok(())
+fn write_to(path: &Path, contents: &str) -> Result<(), Failure> {
+    fs::write(path, contents)
+        .map_err(|e| Failure::Error(format!("could not write {}: {}", path.display(), e)))
 }
@@ -0,0 +1,800 @@
+use ach_lib_rs::ach_file::{AchFile, RecordId};
+use ach_lib_rs::ach_report::dollars;
+use ach_lib_rs::ach_validation::ValidationIssue;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Which pane the arrow keys move in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Focus {
+    Records,
+    Fields,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Mode {
+    Normal,
+    Search(String),
+    Edit(String),
+}
+
+/// A file open for browsing: the records as a tree of batches, entries and addenda on the
+/// left, the fields of the selected one on the right
+pub struct App {
+    path: PathBuf,
+    ach: AchFile,
+    /// Entries put on hold, saved beside the file rather than in it
+    held: Option<AchFile>,
+    /// The file and held entries before each change, for undo
+    history: Vec<(AchFile, Option<AchFile>)>,
+    collapsed: HashSet<usize>,
+    /// Records shown in the tree, with the line each is written on
+    rows: Vec<(RecordId, usize)>,
+    selected: usize,
+    field: usize,
+    focus: Focus,
+    mode: Mode,
+    search: String,
+    issues: Vec<ValidationIssue>,
+    status: String,
+    dirty: bool,
+    quit: bool,
+}
+
+/// Open `path` for browsing and editing until the user quits
+pub fn run(path: &Path, ach: AchFile) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = App::new(path, ach).run(&mut terminal);
+    ratatui::restore();
+    result
+}
+
+impl App {
+    fn new(path: &Path, ach: AchFile) -> Self {
+        let mut app = App {
+            path: path.to_path_buf(),
+            ach,
+            held: None,
+            history: vec![],
+            collapsed: HashSet::new(),
+            rows: vec![],
+            selected: 0,
+            field: 0,
+            focus: Focus::Records,
+            mode: Mode::Normal,
+            search: String::new(),
+            issues: vec![],
+            status: String::new(),
+            dirty: false,
+            quit: false,
+        };
+        app.refresh();
+        app.status = match app.issues.len() {
+            0 => "File is valid".to_string(),
+            n => format!("File has {} problems", n),
+        };
+        app
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    self.handle_key(key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild the tree and validate again, after anything changed
+    fn refresh(&mut self) {
+        self.issues = self.ach.validate();
+        self.rows = self
+            .ach
+            .record_ids()
+            .into_iter()
+            .enumerate()
+            .filter(|(_, id)| match id {
+                RecordId::Entry(e) | RecordId::Addenda(e, _) => !self.collapsed.contains(&e.batch),
+                RecordId::BatchTrailer(batch) => !self.collapsed.contains(batch),
+                _ => true,
+            })
+            .map(|(i, id)| (id, i + 1))
+            .collect();
+        self.selected = self.selected.min(self.rows.len() - 1);
+        self.field = self.field.min(self.fields().len().saturating_sub(1));
+    }
+
+    fn current(&self) -> RecordId {
+        self.rows[self.selected].0
+    }
+
+    fn fields(&self) -> Vec<(&'static str, &str)> {
+        self.ach.record_fields(self.current()).unwrap_or_default()
+    }
+
+    fn problems(&self, line: usize, field: Option<&str>) -> Vec<&ValidationIssue> {
+        self.issues
+            .iter()
+            .filter(|i| i.line == line && field.is_none_or(|f| i.field == f))
+            .collect()
+    }
+
+    /// Keep the file as it is, so the change about to be made can be undone
+    fn checkpoint(&mut self) {
+        self.history.push((self.ach.clone(), self.held.clone()));
+        self.dirty = true;
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        match std::mem::replace(&mut self.mode, Mode::Normal) {
+            Mode::Normal => self.handle_normal(key.code),
+            Mode::Search(query) => self.handle_search(key.code, query),
+            Mode::Edit(value) => self.handle_edit(key.code, value),
+        }
+    }
+
+    fn handle_normal(&mut self, code: KeyCode) {
+        if code != KeyCode::Char('q') && self.quit_pending() {
+            self.status.clear();
+        }
+        match code {
+            KeyCode::Char('q') if self.dirty && !self.quit_pending() => {
+                self.status = "Unsaved changes, press q again to quit without saving".to_string()
+            }
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Records => Focus::Fields,
+                    Focus::Fields => Focus::Records,
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::PageUp => self.move_by(-20),
+            KeyCode::PageDown => self.move_by(20),
+            KeyCode::Left | KeyCode::Right | KeyCode::Char(' ') => self.toggle_batch(),
+            KeyCode::Enter if self.focus == Focus::Records => self.focus = Focus::Fields,
+            KeyCode::Enter => self.start_edit(),
+            KeyCode::Char('/') => self.mode = Mode::Search(String::new()),
+            KeyCode::Char('n') => self.find_next(),
+            KeyCode::Char('d') => self.remove_entry(false),
+            KeyCode::Char('h') => self.remove_entry(true),
+            KeyCode::Char('u') => self.undo(),
+            KeyCode::Char('s') => self.save(),
+            _ => {}
+        }
+    }
+
+    fn quit_pending(&self) -> bool {
+        self.status.starts_with("Unsaved changes")
+    }
+
+    fn move_by(&mut self, by: isize) {
+        match self.focus {
+            Focus::Records => {
+                let last = self.rows.len() - 1;
+                self.selected = self.selected.saturating_add_signed(by).min(last);
+                self.field = 0;
+            }
+            Focus::Fields => {
+                let last = self.fields().len().saturating_sub(1);
+                self.field = self.field.saturating_add_signed(by).min(last);
+            }
+        }
+    }
+
+    fn toggle_batch(&mut self) {
+        let batch = match self.current() {
+            RecordId::BatchHeader(batch) | RecordId::BatchTrailer(batch) => batch,
+            RecordId::Entry(e) | RecordId::Addenda(e, _) => e.batch,
+            _ => return,
+        };
+        if !self.collapsed.remove(&batch) {
+            self.collapsed.insert(batch);
+        }
+        self.refresh();
+        if let Some(row) = self
+            .rows
+            .iter()
+            .position(|(id, _)| *id == RecordId::BatchHeader(batch))
+        {
+            self.selected = row;
+        }
+    }
+
+    fn handle_search(&mut self, code: KeyCode, mut query: String) {
+        match code {
+            KeyCode::Enter => {
+                self.search = query;
+                self.find_next();
+            }
+            KeyCode::Esc => {}
+            KeyCode::Backspace => {
+                query.pop();
+                self.mode = Mode::Search(query);
+            }
+            KeyCode::Char(c) => {
+                query.push(c);
+                self.mode = Mode::Search(query);
+            }
+            _ => self.mode = Mode::Search(query),
+        }
+    }
+
+    /// Whether `id` is what is being searched for: an entry by name, which need only contain
+    /// the search, by trace number, or by amount in dollars, such as `25` or `25.00`, or a batch
+    /// by company name
+    fn matches_search(&self, id: RecordId) -> bool {
+        let field = |name| {
+            self.ach
+                .record_fields(id)
+                .unwrap_or_default()
+                .into_iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.trim().to_string())
+                .unwrap_or_default()
+        };
+        let query = self.search.trim();
+        match id {
+            RecordId::Entry(_) => {
+                let cents = query
+                    .parse::<f64>()
+                    .ok()
+                    .map(|dollars| (dollars * 100.0).round() as u64);
+                field("individual_name")
+                    .to_uppercase()
+                    .contains(&query.to_uppercase())
+                    || field("trace") == query
+                    || cents.is_some() && field("amount").parse().ok() == cents
+            }
+            RecordId::BatchHeader(_) => field("company_name")
+                .to_uppercase()
+                .contains(&query.to_uppercase()),
+            _ => false,
+        }
+    }
+
+    fn find_next(&mut self) {
+        if self.search.trim().is_empty() {
+            return;
+        }
+        let ids = self.ach.record_ids();
+        let current = ids.iter().position(|id| *id == self.current()).unwrap_or(0);
+        let found = (1..=ids.len())
+            .map(|i| ids[(current + i) % ids.len()])
+            .find(|id| self.matches_search(*id));
+        let Some(found) = found else {
+            self.status = format!("'{}' not found", self.search);
+            return;
+        };
+
+        if let RecordId::Entry(e) = found {
+            self.collapsed.remove(&e.batch);
+            self.refresh();
+        }
+        self.selected = self
+            .rows
+            .iter()
+            .position(|(id, _)| *id == found)
+            .unwrap_or(0);
+        self.field = 0;
+        self.status = format!("Found '{}'", self.search);
+    }
+
+    fn start_edit(&mut self) {
+        if matches!(
+            self.current(),
+            RecordId::BatchTrailer(_) | RecordId::Trailer
+        ) {
+            self.status =
+                "Trailers are recomputed from the records, edit those instead".to_string();
+            return;
+        }
+        if let Some((_, value)) = self.fields().get(self.field) {
+            // Only the padding after a value goes, routing numbers keep their leading blank
+            self.mode = Mode::Edit(value.trim_end().to_string());
+        }
+    }
+
+    fn handle_edit(&mut self, code: KeyCode, mut value: String) {
+        let current = self.current();
+        let Some(&(name, _)) = self.fields().get(self.field) else {
+            return;
+        };
+        let spec = current
+            .record_type()
+            .layout()
+            .iter()
+            .find(|s| s.name == name)
+            .copied()
+            .unwrap();
+
+        match code {
+            KeyCode::Enter => {
+                let mut edited = self.ach.clone();
+                match edited.set_field(current, name, &value) {
+                    Ok(_) => {
+                        self.checkpoint();
+                        self.ach = edited;
+                        self.ach.recompute_controls();
+                        self.refresh();
+                        self.status = format!("Set {}", name);
+                    }
+                    Err(e) => {
+                        self.status = e;
+                        self.mode = Mode::Edit(value);
+                    }
+                }
+            }
+            KeyCode::Esc => {}
+            KeyCode::Backspace => {
+                value.pop();
+                self.mode = Mode::Edit(value);
+            }
+            KeyCode::Char(_) if value.len() >= spec.size => {
+                self.status = format!("{} is {} characters wide", name, spec.size);
+                self.mode = Mode::Edit(value);
+            }
+            KeyCode::Char(c) if spec.numeric && !c.is_ascii_digit() => {
+                self.status = format!("{} only takes digits", name);
+                self.mode = Mode::Edit(value);
+            }
+            KeyCode::Char(c) => {
+                value.push(c.to_ascii_uppercase());
+                self.mode = Mode::Edit(value);
+            }
+            _ => self.mode = Mode::Edit(value),
+        }
+    }
+
+    /// Delete the selected entry, or with `hold` set it aside to be saved beside the file
+    fn remove_entry(&mut self, hold: bool) {
+        let entry = match self.current() {
+            RecordId::Entry(e) | RecordId::Addenda(e, _) => e,
+            _ => {
+                self.status = "Select an entry first".to_string();
+                return;
+            }
+        };
+        self.checkpoint();
+        let taken = self.ach.take_entries(&[entry]);
+        self.ach.recompute_controls();
+        if hold {
+            match &mut self.held {
+                Some(held) => held.append(taken),
+                None => self.held = Some(taken),
+            }
+        }
+        self.refresh();
+        self.status = match hold {
+            true => "Entry held, press u to undo".to_string(),
+            false => "Entry deleted, press u to undo".to_string(),
+        };
+    }
+
+    fn undo(&mut self) {
+        match self.history.pop() {
+            Some((ach, held)) => {
+                self.ach = ach;
+                self.held = held;
+                self.dirty = !self.history.is_empty();
+                self.refresh();
+                self.status = "Undone".to_string();
+            }
+            None => self.status = "Nothing to undo".to_string(),
+        }
+    }
+
+    fn held_path(&self) -> PathBuf {
+        self.path.with_extension("held.ach")
+    }
+
+    /// Write the file, and any held entries beside it, if the file validates
+    fn save(&mut self) {
+        // Batches emptied by deletes and holds go with their last entry
+        let mut ach = self.ach.clone();
+        if ach.remove_empty_batches() > 0 {
+            self.checkpoint();
+            ach.recompute_controls();
+            self.ach = ach.clone();
+            self.refresh();
+        }
+
+        if let Some(issue) = self.issues.first() {
+            self.status = format!(
+                "Not saved, {} problems. First: {}",
+                self.issues.len(),
+                issue
+            );
+            return;
+        }
+        let mut written = vec![self.path.clone()];
+        let mut result = write(&self.path, &ach.to_string());
+        if let Some(held) = &self.held {
+            written.push(self.held_path());
+            result = result.and_then(|_| write(&self.held_path(), &held.to_string()));
+        }
+        self.status = match result {
+            Ok(_) => {
+                self.dirty = false;
+                let written: Vec<_> = written.iter().map(|p| p.display().to_string()).collect();
+                format!("Saved {}", written.join(" and "))
+            }
+            Err(e) => format!("Not saved: {}", e),
+        };
+    }
+
+    fn row_label(&self, id: RecordId) -> String {
+        let fields = self.ach.record_fields(id).unwrap_or_default();
+        let field = |name| {
+            fields
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.trim())
+                .unwrap_or_default()
+        };
+        let dollars = |name| dollars(field(name).parse().unwrap_or(0));
+        match id {
+            RecordId::Header => format!(
+                "File {} -> {}",
+                field("immediate_orig_name"),
+                field("immediate_dest_name")
+            ),
+            RecordId::BatchHeader(batch) => format!(
+                "{} Batch {} {} {} {}",
+                if self.collapsed.contains(&batch) {
+                    "+"
+                } else {
+                    "-"
+                },
+                field("batch_number"),
+                field("company_name"),
+                field("sec"),
+                field("entry_desc")
+            ),
+            RecordId::Entry(_) => format!(
+                "    {} {:<22} {:>12} {}",
+                field("transactions_code"),
+                field("individual_name"),
+                dollars("amount"),
+                field("trace")
+            ),
+            RecordId::Addenda(_, _) => format!("      Addenda {}", field("payment_related_info")),
+            RecordId::BatchTrailer(_) => format!(
+                "  Batch control: debits {}, credits {}",
+                dollars("total_debit_amount"),
+                dollars("total_credit_amount")
+            ),
+            RecordId::Trailer => format!(
+                "File control: debits {}, credits {}",
+                dollars("total_debits"),
+                dollars("total_credits")
+            ),
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, status, help] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [tree, detail] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(main);
+
+        let focused = |focus| match self.focus == focus {
+            true => Style::default().fg(Color::Cyan),
+            false => Style::default(),
+        };
+        let problem = Style::default().fg(Color::Red);
+
+        let items: Vec<ListItem> = self
+            .rows
+            .iter()
+            .map(|(id, line)| {
+                let label = self.row_label(*id);
+                match self.problems(*line, None).is_empty() {
+                    true => ListItem::new(label),
+                    false => ListItem::new(format!("{} !", label)).style(problem),
+                }
+            })
+            .collect();
+        let title = format!(
+            " {}{} ",
+            self.path.display(),
+            if self.dirty { " *" } else { "" }
+        );
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(title)
+                    .border_style(focused(Focus::Records)),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(list, tree, &mut state);
+
+        let (id, line) = self.rows[self.selected];
+        let mut start = 1;
+        let mut lines = vec![];
+        for (i, (name, value)) in self.fields().into_iter().enumerate() {
+            let problems = self.problems(line, Some(name));
+            let mut style = match problems.is_empty() {
+                true => Style::default(),
+                false => problem,
+            };
+            if self.focus == Focus::Fields && i == self.field {
+                style = style.add_modifier(Modifier::REVERSED);
+            }
+            let value = match (&self.mode, i == self.field) {
+                (Mode::Edit(edit), true) => format!("{}_", edit),
+                _ => format!("'{}'", value),
+            };
+            lines.push(Line::from(vec![
+                Span::raw(format!(
+                    "{:>5} ",
+                    format!("{}-{}", start, start + value_len(id, name) - 1)
+                )),
+                Span::styled(format!("{:<26}", name), style),
+                Span::raw(value),
+            ]));
+            for issue in problems {
+                lines.push(Line::styled(format!("      ! {}", issue.message), problem));
+            }
+            start += value_len(id, name);
+        }
+        let title = format!(" {:?}, line {} ", id.record_type(), line);
+        let detail_pane = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .border_style(focused(Focus::Fields)),
+        );
+        frame.render_widget(detail_pane, detail);
+
+        let status_line = match &self.mode {
+            Mode::Search(query) => format!("Search (name, trace or amount): {}_", query),
+            Mode::Edit(value) => format!(
+                "Editing, {} characters. Enter to set, Esc to cancel",
+                value.len()
+            ),
+            Mode::Normal => {
+                let held = self.held.as_ref().map_or(0, |h| h.entry_ids().len());
+                match held {
+                    0 => self.status.clone(),
+                    n => format!("{} ({} held)", self.status, n),
+                }
+            }
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+        frame.render_widget(
+            Paragraph::new(
+                "arrows move  tab switch pane  enter edit  space fold  / search  n next  \
+                 d delete  h hold  u undo  s save  q quit",
+            )
+            .style(Style::default().add_modifier(Modifier::DIM)),
+            help,
+        );
+    }
+}
+
+/// Width of field `name` in records like `id`
+fn value_len(id: RecordId, name: &str) -> usize {
+    let layout = id.record_type().layout();
+    layout.iter().find(|s| s.name == name).map_or(0, |s| s.size)
+}
+
+/// Write `contents` to `path` by way of a temporary file beside it
+fn write(path: &Path, contents: &str) -> io::Result<()> {
+    let temp = path.with_extension("ach.tmp");
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)
+}
+
+#[cfg(test)]
+mod tui_tests {
+    use crate::tui::{App, Focus, Mode};
+    use ach_lib_rs::ach_file::{AchFile, EntryId, RecordId};
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::{KeyCode, KeyEvent};
+    use ratatui::Terminal;
+    use std::fs;
+    use std::path::Path;
+
+    const SAMPLE: &str = include_str!("ach_lib_rs/test_data/sample.ach");
+
+    fn app(path: &Path) -> App {
+        App::new(path, SAMPLE.parse::<AchFile>().unwrap())
+    }
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\t' => KeyCode::Tab,
+                '\x1b' => KeyCode::Esc,
+                '\x08' => KeyCode::Backspace,
+                c => KeyCode::Char(c),
+            };
+            app.handle_key(KeyEvent::from(code));
+        }
+    }
+
+    fn screen(app: &App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(140, 20)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_navigate_and_search() {
+        let mut app = app(Path::new("sample.ach"));
+        assert_eq!(app.status, "File is valid");
+        assert_eq!(app.current(), RecordId::Header);
+
+        press(&mut app, "j ");
+        assert_eq!(app.current(), RecordId::BatchHeader(0));
+        assert!(!app
+            .rows
+            .iter()
+            .any(|(id, _)| matches!(id, RecordId::Entry(e) if e.batch == 0)));
+
+        // Finding an entry in a folded batch opens it
+        press(&mut app, "/jane roe\n");
+        let jane = EntryId { batch: 0, entry: 2 };
+        assert_eq!(app.current(), RecordId::Entry(jane));
+        press(&mut app, "/25\n");
+        assert_eq!(app.current(), RecordId::Entry(jane));
+        press(&mut app, "/091000010000004\n");
+        assert_eq!(
+            app.current(),
+            RecordId::Entry(EntryId { batch: 1, entry: 0 })
+        );
+        press(&mut app, "/nobody\n");
+        assert_eq!(app.status, "'nobody' not found");
+
+        let screen = screen(&app);
+        assert!(screen.contains("BETA CUSTOMER"), "{}", screen);
+        assert!(screen.contains("individual_name"), "{}", screen);
+        assert!(screen.contains("EntryDetail, line 9"), "{}", screen);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut app = app(Path::new("sample.ach"));
+        press(&mut app, "jj\t");
+        assert_eq!(app.focus, Focus::Fields);
+        let amount = app
+            .fields()
+            .iter()
+            .position(|(n, _)| *n == "amount")
+            .unwrap();
+        for _ in 0..amount {
+            press(&mut app, "j");
+        }
+
+        press(&mut app, "\n");
+        assert_eq!(app.mode, Mode::Edit("0000001000".to_string()));
+        press(&mut app, "x");
+        assert_eq!(app.status, "amount is 10 characters wide");
+        press(&mut app, &"\x08".repeat(10));
+        press(&mut app, "12a");
+        assert_eq!(app.status, "amount only takes digits");
+        press(&mut app, "00\n");
+        assert_eq!(app.status, "Set amount");
+        assert_eq!(
+            app.ach
+                .entry_field(EntryId { batch: 0, entry: 0 }, "amount"),
+            Some("0000001200")
+        );
+        assert!(app.issues.is_empty(), "{:?}", app.issues);
+        assert!(app.dirty);
+
+        press(&mut app, "u");
+        assert_eq!(app.ach.to_string(), SAMPLE);
+        assert!(!app.dirty);
+
+        // Trailers follow the records rather than being edited
+        press(&mut app, "\t");
+        app.selected = app.rows.len() - 1;
+        press(&mut app, "\t\n");
+        assert_eq!(app.mode, Mode::Normal);
+        assert!(app.status.starts_with("Trailers are recomputed"));
+
+        // Routing numbers keep the blank in front of them
+        app.selected = 0;
+        let dest = app
+            .fields()
+            .iter()
+            .position(|(n, _)| *n == "immediate_dest")
+            .unwrap();
+        app.field = dest;
+        press(&mut app, "\n");
+        assert_eq!(app.mode, Mode::Edit(" 091000019".to_string()));
+        press(&mut app, "\n");
+        assert_eq!(app.status, "Set immediate_dest");
+        assert_eq!(app.ach.to_string(), SAMPLE);
+    }
+
+    #[test]
+    fn test_delete_hold_and_save() {
+        let dir = std::env::temp_dir().join(format!("ach_rs_tui_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sample.ach");
+        fs::write(&path, SAMPLE).unwrap();
+
+        let mut app = app(&path);
+        press(&mut app, "/zero\nd");
+        press(&mut app, "/beta customer\nh");
+        assert_eq!(app.ach.entry_ids().len(), 2);
+        assert_eq!(app.held.as_ref().unwrap().entry_ids().len(), 1);
+
+        press(&mut app, "q");
+        assert!(!app.quit);
+        press(&mut app, "s");
+        assert!(app.status.starts_with("Saved"), "{}", app.status);
+        press(&mut app, "q");
+        assert!(app.quit);
+
+        let saved: AchFile = fs::read_to_string(&path).unwrap().parse().unwrap();
+        assert!(saved.validate().is_empty());
+        assert_eq!(saved.batch_count(), 1);
+        assert_eq!(saved.entry_ids().len(), 2);
+        let held: AchFile = fs::read_to_string(dir.join("sample.held.ach"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            held.batch_field(0, "company_name").unwrap().trim(),
+            "BETA LLC"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_refuses_invalid() {
+        let path =
+            std::env::temp_dir().join(format!("ach_rs_tui_invalid_{}.ach", std::process::id()));
+        let mut app = app(&path);
+        press(&mut app, "jj\t");
+        let check = app
+            .fields()
+            .iter()
+            .position(|(n, _)| *n == "check_digit")
+            .unwrap();
+        for _ in 0..check {
+            press(&mut app, "j");
+        }
+        press(&mut app, "\n\x089\n");
+        assert_eq!(app.issues.len(), 1);
+        press(&mut app, "s");
+        assert!(
+            app.status
+                .starts_with("Not saved, 1 problems. First: line 3"),
+            "{}",
+            app.status
+        );
+        assert!(!path.exists());
+        assert!(screen(&app).contains("! does not match receiving_dfi_id"));
+    }
+}